@@ -57,6 +57,7 @@ fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    mut hikari_materials: ResMut<Assets<HikariMaterial>>,
     asset_server: Res<AssetServer>,
 ) {
     commands.spawn((
@@ -79,24 +80,25 @@ fn setup(
 
     // Sphere
     commands.spawn((
-        PbrBundle {
-            mesh: meshes.add(Mesh::from(shape::UVSphere {
-                radius: 0.5,
-                ..Default::default()
-            })),
-            material: materials.add(StandardMaterial {
+        meshes.add(Mesh::from(shape::UVSphere {
+            radius: 0.5,
+            ..Default::default()
+        })),
+        hikari_materials.add(HikariMaterial {
+            emissive_strength: 128.0,
+            ..StandardMaterial {
                 base_color_texture: Some(asset_server.load("models/Earth/earth_daymap.jpg")),
-                emissive: Color::rgba(1.0, 1.0, 1.0, 0.5),
+                emissive: Color::WHITE,
                 emissive_texture: Some(asset_server.load("models/Earth/earth_daymap.jpg")),
                 ..Default::default()
-            }),
-            transform: Transform {
-                translation: Vec3::new(0.0, 1.0, 0.0),
-                rotation: Quat::from_rotation_x(-PI / 2.0),
-                ..Default::default()
-            },
+            }
+            .into()
+        }),
+        SpatialBundle::from_transform(Transform {
+            translation: Vec3::new(0.0, 1.0, 0.0),
+            rotation: Quat::from_rotation_x(-PI / 2.0),
             ..Default::default()
-        },
+        }),
         EmissiveSphere { speed: 0.2 },
         Name::new("Emissive Sphere"),
     ));
@@ -0,0 +1,287 @@
+use crate::{HikariColorGrading, HikariSettings, IDENTITY_LUT_HANDLE};
+use bevy::{
+    asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset},
+    ecs::query::QueryItem,
+    prelude::*,
+    render::{
+        extract_component::{ExtractComponent, ExtractComponentPlugin, UniformComponentPlugin},
+        render_resource::*,
+    },
+};
+use std::{error::Error, fmt::Display};
+
+pub const LUT_TEXTURE_FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+
+pub struct ColorGradingPlugin;
+impl Plugin for ColorGradingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset_loader::<CubeLoader>()
+            .add_plugin(ExtractComponentPlugin::<HikariColorGrading>::default())
+            .add_plugin(ExtractComponentPlugin::<ColorGradingUniform>::default())
+            .add_plugin(UniformComponentPlugin::<ColorGradingUniform>::default())
+            .add_startup_system(setup);
+    }
+}
+
+fn setup(mut images: ResMut<Assets<Image>>) {
+    // With trilinear interpolation, a LUT of only the corners of the color cube maps every color to itself.
+    let lut = CubeLut {
+        size: 2,
+        data: (0..8)
+            .map(|index| {
+                Vec3::new(
+                    (index & 1) as f32,
+                    (index >> 1 & 1) as f32,
+                    (index >> 2) as f32,
+                )
+            })
+            .collect(),
+    };
+    images.set_untracked(IDENTITY_LUT_HANDLE, lut.into());
+}
+
+impl ExtractComponent for HikariColorGrading {
+    type Query = &'static Self;
+    type Filter = With<HikariSettings>;
+
+    fn extract_component(item: QueryItem<Self::Query>) -> Self {
+        item.clone()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Component, ShaderType)]
+pub struct ColorGradingUniform {
+    pub lift: Vec3,
+    pub gamma: Vec3,
+    pub gain: Vec3,
+}
+
+impl Default for ColorGradingUniform {
+    fn default() -> Self {
+        Self {
+            lift: Vec3::ZERO,
+            gamma: Vec3::ONE,
+            gain: Vec3::ONE,
+        }
+    }
+}
+
+impl ExtractComponent for ColorGradingUniform {
+    type Query = Option<&'static HikariColorGrading>;
+    type Filter = With<HikariSettings>;
+
+    fn extract_component(item: QueryItem<Self::Query>) -> Self {
+        match item {
+            Some(grading) if grading.enabled => Self {
+                lift: grading.lift,
+                gamma: grading.gamma.max(Vec3::splat(0.0001)),
+                gain: grading.gain,
+            },
+            _ => Self::default(),
+        }
+    }
+}
+
+/// A 3D color lookup table, as exported by grading tools in the Adobe/Resolve `.cube` format.
+#[derive(Debug, Clone)]
+pub struct CubeLut {
+    /// Count of entries along each axis.
+    pub size: usize,
+    /// Output colors, with the red input varying fastest and the blue input slowest.
+    pub data: Vec<Vec3>,
+}
+
+impl CubeLut {
+    /// Parses the text of a `.cube` file. Only 3D tables over the unit input domain are supported.
+    pub fn parse(text: &str) -> Result<Self, CubeError> {
+        let mut size = None;
+        let mut data = vec![];
+
+        let lines = text
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        for line in lines {
+            let mut tokens = line.split_whitespace();
+            let keyword = tokens.next().unwrap_or_default();
+            let mut values = || -> Result<Vec<f32>, CubeError> {
+                tokens
+                    .by_ref()
+                    .map(|token| token.parse::<f32>().map_err(|_| CubeError::InvalidNumber))
+                    .collect()
+            };
+
+            match keyword {
+                "TITLE" => {}
+                "LUT_1D_SIZE" | "LUT_1D_INPUT_RANGE" => return Err(CubeError::Unsupported1D),
+                "LUT_3D_SIZE" => {
+                    size = match values()?[..] {
+                        [value] if value >= 2.0 => Some(value as usize),
+                        _ => return Err(CubeError::InvalidSize),
+                    }
+                }
+                "DOMAIN_MIN" => {
+                    if values()? != [0.0, 0.0, 0.0] {
+                        return Err(CubeError::UnsupportedDomain);
+                    }
+                }
+                "DOMAIN_MAX" | "LUT_3D_INPUT_RANGE" => {
+                    let values = values()?;
+                    if values != [1.0, 1.0, 1.0] && values != [0.0, 1.0] {
+                        return Err(CubeError::UnsupportedDomain);
+                    }
+                }
+                // Skips keywords of other tools, e.g. `LUT_3D_SHAPER` metadata of some exporters.
+                keyword if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {}
+                _ => {
+                    let entry = line
+                        .split_whitespace()
+                        .map(|token| token.parse::<f32>().map_err(|_| CubeError::InvalidNumber))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    match entry[..] {
+                        [r, g, b] => data.push(Vec3::new(r, g, b)),
+                        _ => return Err(CubeError::InvalidNumber),
+                    }
+                }
+            }
+        }
+
+        match size {
+            Some(size) if data.len() == size * size * size => Ok(Self { size, data }),
+            Some(_) => Err(CubeError::UnexpectedEof),
+            None => Err(CubeError::InvalidSize),
+        }
+    }
+}
+
+impl From<CubeLut> for Image {
+    fn from(lut: CubeLut) -> Self {
+        let size = lut.size as u32;
+        let data: Vec<f32> = lut
+            .data
+            .iter()
+            .flat_map(|color| color.extend(1.0).to_array())
+            .collect();
+        Image::new(
+            Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: size,
+            },
+            TextureDimension::D3,
+            bytemuck::cast_slice(&data).to_vec(),
+            LUT_TEXTURE_FORMAT,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CubeError {
+    Unsupported1D,
+    UnsupportedDomain,
+    InvalidSize,
+    InvalidNumber,
+    UnexpectedEof,
+}
+
+impl Display for CubeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid cube LUT: {self:?}")
+    }
+}
+
+impl Error for CubeError {}
+
+/// Loads `.cube` files as 3D [`Image`]s, to be used as [`HikariColorGrading::lut`].
+#[derive(Default)]
+pub struct CubeLoader;
+
+impl AssetLoader for CubeLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let text = String::from_utf8_lossy(bytes);
+            let lut = CubeLut::parse(&text)?;
+            load_context.set_default_asset(LoadedAsset::new(Image::from(lut)));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["cube"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY: &str = "TITLE \"Identity\"
+# Created by hand
+LUT_3D_SIZE 2
+DOMAIN_MIN 0.0 0.0 0.0
+DOMAIN_MAX 1.0 1.0 1.0
+
+0.0 0.0 0.0
+1.0 0.0 0.0
+0.0 1.0 0.0
+1.0 1.0 0.0
+0.0 0.0 1.0
+1.0 0.0 1.0
+0.0 1.0 1.0
+1.0 1.0 1.0
+";
+
+    #[test]
+    fn parse_identity() {
+        let lut = CubeLut::parse(IDENTITY).unwrap();
+        assert_eq!(lut.size, 2);
+        assert_eq!(lut.data.len(), 8);
+        assert_eq!(lut.data[1], Vec3::X);
+        assert_eq!(lut.data[2], Vec3::Y);
+        assert_eq!(lut.data[4], Vec3::Z);
+        assert_eq!(lut.data[7], Vec3::ONE);
+    }
+
+    #[test]
+    fn skip_unknown_keywords() {
+        let text = IDENTITY.replace(
+            "LUT_3D_SIZE 2",
+            "LUT_3D_SIZE 2\nLUT_IN_VIDEO_RANGE\nCUSTOM_TAG 1 2",
+        );
+        assert_eq!(CubeLut::parse(&text).unwrap().data.len(), 8);
+    }
+
+    #[test]
+    fn reject_size_mismatch() {
+        let text = IDENTITY.replace("LUT_3D_SIZE 2", "LUT_3D_SIZE 3");
+        assert_eq!(CubeLut::parse(&text).unwrap_err(), CubeError::UnexpectedEof);
+
+        let text = IDENTITY.replace("LUT_3D_SIZE 2\n", "");
+        assert_eq!(CubeLut::parse(&text).unwrap_err(), CubeError::InvalidSize);
+    }
+
+    #[test]
+    fn reject_bad_domain() {
+        let text = IDENTITY.replace("DOMAIN_MAX 1.0 1.0 1.0", "DOMAIN_MAX 2.0 2.0 2.0");
+        assert_eq!(
+            CubeLut::parse(&text).unwrap_err(),
+            CubeError::UnsupportedDomain
+        );
+
+        let text = IDENTITY.replace("DOMAIN_MIN 0.0 0.0 0.0", "DOMAIN_MIN -1.0 0.0 0.0");
+        assert_eq!(
+            CubeLut::parse(&text).unwrap_err(),
+            CubeError::UnsupportedDomain
+        );
+    }
+
+    #[test]
+    fn reject_1d() {
+        let text = IDENTITY.replace("LUT_3D_SIZE 2", "LUT_1D_SIZE 2");
+        assert_eq!(CubeLut::parse(&text).unwrap_err(), CubeError::Unsupported1D);
+    }
+}
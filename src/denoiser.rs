@@ -0,0 +1,175 @@
+use crate::{
+    post_process::{set_view_bind_groups, CachedPostProcessPipelines, PostProcessBindGroup},
+    prepass::{DeferredBindGroup, PrepassBindGroup},
+    view::{FrameUniform, PreviousViewUniformOffset},
+    WORKGROUP_SIZE,
+};
+use bevy::{
+    pbr::ViewLightsUniformOffset,
+    prelude::*,
+    render::{
+        extract_component::DynamicUniformIndex, render_graph::NodeRunError, render_resource::*,
+        renderer::RenderContext, view::ViewUniformOffset, RenderApp,
+    },
+};
+use std::marker::PhantomData;
+
+/// Replaces the denoiser of all views with `D`, which is created from the render world.
+pub struct HikariDenoiserPlugin<D: HikariDenoiser + FromWorld>(PhantomData<D>);
+
+impl<D: HikariDenoiser + FromWorld> Default for HikariDenoiserPlugin<D> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<D: HikariDenoiser + FromWorld> Plugin for HikariDenoiserPlugin<D> {
+    fn build(&self, app: &mut App) {
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            let denoiser = D::from_world(&mut render_app.world);
+            render_app.insert_resource(ActiveDenoiser(Box::new(denoiser)));
+        }
+    }
+}
+
+/// Which render of the light pass is being denoised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DenoiseTarget {
+    Direct,
+    Emissive,
+    Indirect,
+    /// In-scattered radiance of fog volumes, which is not demodulated by the surface albedo.
+    Fog,
+}
+
+impl DenoiseTarget {
+    pub const ALL: [Self; 4] = [Self::Direct, Self::Emissive, Self::Indirect, Self::Fog];
+
+    /// Index of the render in [`LightTextures`](crate::light::LightTextures).
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Textures a [`HikariDenoiser`] reads from and writes to, for one render of a view.
+/// The G-buffers are found in the [`PrepassTextures`](crate::prepass::PrepassTextures) of the view.
+pub struct DenoiserInput<'a> {
+    /// The camera entity in the render world.
+    pub view: Entity,
+    pub target: DenoiseTarget,
+    /// Size of the render textures, in pixels.
+    pub size: UVec2,
+    /// Irradiance of the render, with the surface albedo divided out.
+    pub demodulated: &'a TextureView,
+    /// Spatially filtered variance of the render.
+    pub variance: &'a TextureView,
+    pub albedo: &'a TextureView,
+    /// Receives the denoised irradiance, which is modulated by the albedo afterwards.
+    pub output: &'a TextureView,
+}
+
+/// A denoising backend, run on each render of a view between demodulation and tone mapping.
+///
+/// The default is [`SvgfDenoiser`]; replace it with a [`HikariDenoiserPlugin`].
+pub trait HikariDenoiser: Send + Sync + 'static {
+    fn denoise(
+        &self,
+        world: &World,
+        render_context: &mut RenderContext,
+        input: &DenoiserInput,
+    ) -> Result<(), NodeRunError>;
+}
+
+#[derive(Resource, Deref)]
+pub struct ActiveDenoiser(pub Box<dyn HikariDenoiser>);
+
+/// Temporal accumulation with moments-based variance, followed by 4 levels of edge-avoiding à-trous filtering.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SvgfDenoiser;
+
+impl HikariDenoiser for SvgfDenoiser {
+    fn denoise(
+        &self,
+        world: &World,
+        render_context: &mut RenderContext,
+        input: &DenoiserInput,
+    ) -> Result<(), NodeRunError> {
+        let view = world.entity(input.view);
+        let (
+            frame_uniform,
+            view_uniform,
+            previous_view_uniform,
+            view_lights,
+            deferred_bind_group,
+            post_process_bind_group,
+            prepass_bind_group,
+        ) = match (
+            view.get::<DynamicUniformIndex<FrameUniform>>(),
+            view.get::<ViewUniformOffset>(),
+            view.get::<PreviousViewUniformOffset>(),
+            view.get::<ViewLightsUniformOffset>(),
+            view.get::<DeferredBindGroup>(),
+            view.get::<PostProcessBindGroup>(),
+            world.get_resource::<PrepassBindGroup>(),
+        ) {
+            (Some(a), Some(b), Some(c), Some(d), Some(e), Some(f), Some(g)) => {
+                (a, b, c, d, e, f, g)
+            }
+            _ => return Ok(()),
+        };
+        let render_bind_group = match post_process_bind_group.denoise_render(input.target) {
+            Some(bind_group) => bind_group,
+            None => return Ok(()),
+        };
+
+        let pipelines = world.resource::<CachedPostProcessPipelines>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        let (temporal_accumulation, denoise) = match input.target {
+            DenoiseTarget::Direct => (pipelines.temporal_accumulation, pipelines.denoise_direct),
+            DenoiseTarget::Emissive | DenoiseTarget::Indirect => {
+                (pipelines.temporal_accumulation, pipelines.denoise)
+            }
+            DenoiseTarget::Fog => (
+                pipelines.temporal_accumulation_medium,
+                pipelines.denoise_medium,
+            ),
+        };
+
+        let mut pass = render_context
+            .command_encoder
+            .begin_compute_pass(&ComputePassDescriptor::default());
+
+        set_view_bind_groups(
+            &mut pass,
+            &prepass_bind_group.view,
+            &[
+                frame_uniform.index(),
+                view_uniform.offset,
+                previous_view_uniform.offset,
+                view_lights.offset,
+            ],
+            &deferred_bind_group.0,
+            &post_process_bind_group.sampler,
+        );
+        pass.set_bind_group(3, &post_process_bind_group.denoise_internal, &[]);
+        pass.set_bind_group(4, render_bind_group, &[]);
+        pass.set_bind_group(
+            5,
+            &post_process_bind_group.denoise_history[input.target.index()],
+            &[],
+        );
+
+        for pipeline in std::iter::once(&temporal_accumulation)
+            .chain(denoise.iter())
+            .filter_map(|pipeline| pipeline_cache.get_compute_pipeline(*pipeline))
+        {
+            pass.set_pipeline(pipeline);
+
+            let count = (input.size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+            pass.dispatch_workgroups(count.x, count.y, 1);
+        }
+
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use crate::{
+    color_grading::ColorGradingPlugin,
     light::{LightNode, LightPlugin},
     mesh_material::MeshMaterialPlugin,
     overlay::{OverlayNode, OverlayPlugin},
@@ -31,6 +32,8 @@ use std::num::NonZeroU32;
 #[macro_use]
 extern crate num_derive;
 
+pub mod color_grading;
+pub mod denoiser;
 pub mod light;
 pub mod mesh_material;
 pub mod overlay;
@@ -79,6 +82,10 @@ pub const DENOISE_SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 5179661212363325472);
 pub const TONE_MAPPING_SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 3567017338952956671);
+pub const TONEMAPPING_OPERATORS_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 13286071754315268749);
+pub const COLOR_GRADING_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 6014855187309626413);
 pub const TAA_SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 1780446804546284);
 pub const SMAA_SHADER_HANDLE: HandleUntyped =
@@ -91,6 +98,8 @@ pub const OVERLAY_SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 10969344919103020615);
 pub const QUAD_MESH_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Mesh::TYPE_UUID, 4740146776519512271);
+pub const IDENTITY_LUT_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Image::TYPE_UUID, 9317404735291858731);
 
 pub struct HikariPlugin;
 impl Plugin for HikariPlugin {
@@ -149,12 +158,24 @@ impl Plugin for HikariPlugin {
             "shaders/denoise.wgsl",
             Shader::from_wgsl
         );
+        load_internal_asset!(
+            app,
+            TONEMAPPING_OPERATORS_SHADER_HANDLE,
+            "shaders/tonemapping_operators.wgsl",
+            Shader::from_wgsl
+        );
         load_internal_asset!(
             app,
             TONE_MAPPING_SHADER_HANDLE,
             "shaders/tone_mapping.wgsl",
             Shader::from_wgsl
         );
+        load_internal_asset!(
+            app,
+            COLOR_GRADING_SHADER_HANDLE,
+            "shaders/color_grading.wgsl",
+            Shader::from_wgsl
+        );
         load_internal_asset!(
             app,
             TAA_SHADER_HANDLE,
@@ -222,6 +243,16 @@ impl Plugin for HikariPlugin {
             .register_type::<HikariSettings>()
             .register_type::<Taa>()
             .register_type::<Upscale>()
+            .register_type::<HikariTonemapping>()
+            .register_type::<IndirectResolution>()
+            .register_type::<AdaptiveSampling>()
+            .register_type::<RadianceCache>()
+            .register_type::<PathDepth>()
+            .register_type::<SpatialReuse>()
+            .register_type::<MotionBlur>()
+            .register_type::<HikariThinLens>()
+            .register_type::<HikariAutoExposure>()
+            .register_type::<HikariColorGrading>()
             .init_resource::<HikariUniversalSettings>()
             .add_plugin(ExtractResourcePlugin::<NoiseTextures>::default())
             .add_plugin(ExtractResourcePlugin::<HikariUniversalSettings>::default())
@@ -231,6 +262,7 @@ impl Plugin for HikariPlugin {
             .add_plugin(MeshMaterialPlugin)
             .add_plugin(PrepassPlugin)
             .add_plugin(LightPlugin)
+            .add_plugin(ColorGradingPlugin)
             .add_plugin(PostProcessPlugin)
             .add_plugin(OverlayPlugin)
             .add_startup_system(noise_load_system);
@@ -377,6 +409,9 @@ pub struct HikariUniversalSettings {
     pub build_mesh_acceleration_structure: bool,
     /// Whether to build acceleration structure for scene instances.
     pub build_instance_acceleration_structure: bool,
+    /// Illuminance below which an emissive instance is considered out of range.
+    /// Lower values let emissive instances light farther, at the cost of candidate quality.
+    pub emissive_threshold: f32,
 }
 
 impl Default for HikariUniversalSettings {
@@ -384,6 +419,7 @@ impl Default for HikariUniversalSettings {
         Self {
             build_mesh_acceleration_structure: true,
             build_instance_acceleration_structure: true,
+            emissive_threshold: 0.5,
         }
     }
 }
@@ -412,10 +448,18 @@ pub struct HikariSettings {
     pub max_reservoir_lifetime: f32,
     /// Half angle of the solar cone apex in radians.
     pub solar_angle: f32,
-    /// Count of indirect bounces.
+    /// Maximum count of indirect bounces.
+    /// With Russian roulette enabled, this can be set high to leave the path length to the throughput.
     pub indirect_bounces: usize,
+    /// Termination and lobe specific depth caps of indirect paths.
+    pub path_depth: PathDepth,
     /// Threshold for the indirect luminance to reduce fireflies.
     pub max_indirect_luminance: f32,
+    /// Maximum variance of reservoir weights fed into the denoiser.
+    pub max_variance: f32,
+    /// Distance beyond which a re-projected pixel is treated as a different surface,
+    /// as a fraction of the distance from the camera to the surface, so that it holds for scenes of any scale.
+    pub position_miss_threshold: f32,
     /// Clear color override.
     pub clear_color: Color,
     /// Whether to do temporal sample reuse in ReSTIR.
@@ -424,12 +468,32 @@ pub struct HikariSettings {
     pub emissive_spatial_reuse: bool,
     /// Whether to do spatial sample reuse for indirect lighting in ReSTIR.
     pub indirect_spatial_reuse: bool,
+    /// Resolution indirect lighting is traced at, relative to the render resolution.
+    pub indirect_resolution: IndirectResolution,
+    /// Distribution of emissive and indirect samples by the variance of the previous frame.
+    pub adaptive_sampling: AdaptiveSampling,
+    /// Neighbor counts and radii of spatial sample reuse.
+    pub spatial_reuse: SpatialReuse,
+    /// Whether to use unbiased ReSTIR, which weights reused samples by pairwise MIS
+    /// and traces visibility rays towards them. Removes darkening near contact shadows at a higher cost.
+    pub unbiased_restir: bool,
     /// Whether to do noise filtering.
     pub denoise: bool,
     /// Which temporal filtering implementation to use.
     pub taa: Taa,
     /// Which upscaling implementation to use.
     pub upscale: Upscale,
+    /// Operator compressing the combined render into display range, before anti-aliasing.
+    pub tonemapping: HikariTonemapping,
+    /// Exposure compensation in stops (EV), applied before tone mapping.
+    pub exposure: f32,
+    /// World space radiance cache for multiple indirect bounces.
+    pub radiance_cache: RadianceCache,
+    /// Time sampled transforms of moving instances, for cinematic output.
+    pub motion_blur: MotionBlur,
+    /// Whether rays skip faces culled by the cull mode of their material.
+    /// Double sided materials are never culled.
+    pub backface_culling: bool,
 }
 
 impl Default for HikariSettings {
@@ -444,12 +508,24 @@ impl Default for HikariSettings {
             clear_color: Color::rgb(0.4, 0.4, 0.4),
             indirect_bounces: 1,
             max_indirect_luminance: 10.0,
+            max_variance: 10.0,
+            position_miss_threshold: 0.05,
+            path_depth: PathDepth::default(),
             temporal_reuse: true,
             emissive_spatial_reuse: false,
             indirect_spatial_reuse: true,
+            indirect_resolution: IndirectResolution::default(),
+            adaptive_sampling: AdaptiveSampling::default(),
+            spatial_reuse: SpatialReuse::default(),
+            unbiased_restir: false,
             denoise: true,
             taa: Taa::default(),
             upscale: Upscale::default(),
+            tonemapping: HikariTonemapping::default(),
+            exposure: 0.0,
+            radiance_cache: RadianceCache::default(),
+            motion_blur: MotionBlur::default(),
+            backface_culling: false,
         }
     }
 }
@@ -471,12 +547,86 @@ pub enum Taa {
     None,
 }
 
+/// Tone mapping operator of the combined render.
+///
+/// The render is tone mapped before temporal anti-aliasing and upscaling, which keeps bright samples from
+/// dominating the history. On HDR cameras, the overlay applies the inverse before Bevy's own tone mapping.
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq, Reflect)]
+pub enum HikariTonemapping {
+    /// Linear output; values above 1.0 are clipped.
+    None,
+    /// Reinhard on luminance, which keeps hue and saturation.
+    #[default]
+    Reinhard,
+    /// Krzysztof Narkowicz's fit of the ACES filmic curve, applied per channel.
+    AcesFitted,
+    /// [AgX](https://github.com/sobotka/AgX) with a polynomial approximation of the sigmoid,
+    /// which desaturates highlights towards white.
+    AgX,
+    /// Reinhard on the largest channel, which keeps hue, blended towards white as it approaches 1.0
+    /// so that bright saturated colors do not clip.
+    HuePreserving,
+}
+
+/// Resolution of indirect lighting, independent of the [`Upscale`] ratio.
+///
+/// At reduced resolutions each indirect pixel covers a block of render pixels, and a different pixel
+/// of the block is traced each frame. A depth and normal aware upsample fills the render before denoising.
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq, Reflect)]
+pub enum IndirectResolution {
+    #[default]
+    Full,
+    /// One indirect sample per 2x2 pixels.
+    Half,
+    /// One indirect sample per 4x4 pixels.
+    Quarter,
+}
+
+impl IndirectResolution {
+    /// Edge length of the pixel block covered by one indirect sample.
+    pub fn scale(&self) -> u32 {
+        match self {
+            IndirectResolution::Full => 1,
+            IndirectResolution::Half => 2,
+            IndirectResolution::Quarter => 4,
+        }
+    }
+}
+
+/// Adaptive sampling of emissive and indirect lighting.
+///
+/// Instead of one sample per pixel, each pass spends a share of the ray budget on every pixel
+/// in proportion to the variance it had in the previous frame. Converged pixels may take no new sample
+/// and keep their temporal reservoir, while noisy pixels take several.
+#[derive(Debug, Clone, Copy, Reflect)]
+pub struct AdaptiveSampling {
+    pub enabled: bool,
+    /// Total count of rays traced by the emissive and indirect passes each frame, shared evenly by the two.
+    /// An emissive sample costs one shadow ray; an indirect sample costs two rays per bounce.
+    pub rays_per_frame: usize,
+    /// Maximum count of samples a pixel may take in one frame.
+    pub max_samples: usize,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rays_per_frame: 4_000_000,
+            max_samples: 4,
+        }
+    }
+}
+
 /// Upscale method to use.
 #[derive(Debug, Clone, Copy, Reflect)]
 pub enum Upscale {
+    /// Renders at the target resolution, without any upscaling pass.
+    None,
     /// [AMD FidelityFX™ Super Resolution](https://gpuopen.com/fidelityfx-superresolution/).
     Fsr1 {
         /// Renders the main pass and post process on a low resolution texture.
+        /// Any ratio from 1.0 upwards is supported.
         ratio: f32,
         /// From 0.0 - 2.0 where 0.0 means max sharpness.
         sharpness: f32,
@@ -484,6 +634,7 @@ pub enum Upscale {
     /// [Filmic SMAA TU4x](https://www.activision.com/cdn/research/Dynamic_Temporal_Antialiasing_and_Upsampling_in_Call_of_Duty_v4.pdf).
     SmaaTu4x {
         /// Renders the main pass and post process on a low resolution texture.
+        /// SMAA TU4x doubles the resolution; above a ratio of 2.0, FSR1 EASU upscales the rest of the way.
         ratio: f32,
     },
 }
@@ -500,10 +651,16 @@ impl Upscale {
 
     pub fn ratio(&self) -> f32 {
         match self {
-            Upscale::Fsr1 { ratio, .. } | Upscale::SmaaTu4x { ratio } => ratio.clamp(1.0, 2.0),
+            Upscale::None => 1.0,
+            Upscale::Fsr1 { ratio, .. } | Upscale::SmaaTu4x { ratio } => ratio.max(1.0),
         }
     }
 
+    /// Whether FSR1 EASU runs after SMAA TU4x, because doubling the render resolution falls short of the target.
+    pub fn smaa_easu(&self) -> bool {
+        matches!(self, Upscale::SmaaTu4x { .. }) && self.ratio() > 2.0
+    }
+
     pub fn sharpness(&self) -> f32 {
         match self {
             Upscale::Fsr1 { sharpness, .. } => *sharpness,
@@ -512,6 +669,199 @@ impl Upscale {
     }
 }
 
+/// Settings of the world space hashed radiance cache. Disabled by default.
+/// Indirect paths are terminated early at secondary hits where the cache has converged.
+/// Samples of a frame are summed with atomics, then blended into their cells in a separate pass.
+#[derive(Debug, Clone, Copy, Reflect)]
+pub struct RadianceCache {
+    /// Whether to use the cache. Only takes effect when `indirect_bounces` is greater than 1.
+    pub enabled: bool,
+    /// Edge length of a cache cell in world units.
+    pub cell_size: f32,
+    /// Minimum blend weight of a new sample into its cell.
+    /// Higher values react faster to lighting changes, at the cost of noise.
+    pub update_rate: f32,
+    /// Show the cached radiance at primary hits in place of indirect lighting.
+    pub debug: bool,
+}
+
+impl Default for RadianceCache {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cell_size: 0.25,
+            update_rate: 0.05,
+            debug: false,
+        }
+    }
+}
+
+/// Neighbor counts and radii of ReSTIR spatial reuse.
+/// Radii are fractions of the render height, so that the neighborhood covers the same part of the view at any resolution.
+#[derive(Debug, Clone, Copy, Reflect)]
+pub struct SpatialReuse {
+    /// Count of neighbors reused for emissive lighting.
+    pub emissive_count: usize,
+    /// Count of neighbors reused for indirect lighting.
+    pub indirect_count: usize,
+    /// Radius of the neighborhood for emissive lighting, relative to the render height.
+    pub emissive_range: f32,
+    /// Radius of the neighborhood for indirect lighting, relative to the render height.
+    pub indirect_range: f32,
+    /// Count of depth taps marched towards a neighbor to reject occluded ones.
+    pub taps: usize,
+}
+
+impl Default for SpatialReuse {
+    fn default() -> Self {
+        Self {
+            emissive_count: 8,
+            indirect_count: 16,
+            emissive_range: 0.01,
+            indirect_range: 0.02,
+            taps: 4,
+        }
+    }
+}
+
+/// Termination of multiple bounce indirect paths.
+/// Only takes effect when `indirect_bounces` is greater than 1.
+#[derive(Debug, Clone, Copy, Reflect)]
+pub struct PathDepth {
+    /// Whether to terminate paths randomly by their throughput.
+    pub russian_roulette: bool,
+    /// Count of bounces always traced before Russian roulette may terminate a path.
+    pub min_bounces: usize,
+    /// Maximum count of diffuse bounces in a path.
+    pub diffuse_bounces: usize,
+    /// Maximum count of specular bounces in a path.
+    pub specular_bounces: usize,
+    /// Maximum count of bounces through transparent surfaces in a path.
+    pub transmission_bounces: usize,
+}
+
+impl Default for PathDepth {
+    fn default() -> Self {
+        Self {
+            russian_roulette: true,
+            min_bounces: 2,
+            diffuse_bounces: 4,
+            specular_bounces: 8,
+            transmission_bounces: 8,
+        }
+    }
+}
+
+/// Motion blur of moving instances.
+///
+/// Each pixel draws its own time within the shutter interval before the current frame.
+/// Its primary ray and the paths from it see instances at their transforms interpolated to that time,
+/// so moving objects streak along with their lighting, shadows and reflections.
+#[derive(Debug, Clone, Copy, Reflect)]
+pub struct MotionBlur {
+    pub enabled: bool,
+    /// Fraction of the frame interval the shutter is open, from 0.0 to 1.0.
+    pub shutter: f32,
+}
+
+impl Default for MotionBlur {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            shutter: 0.5,
+        }
+    }
+}
+
+/// Thin lens camera model for depth of field.
+/// Attach it next to [`HikariSettings`]; primary visibility is then traced per pixel from its own point on the aperture,
+/// through the focus plane, instead of being rasterized through a pinhole.
+#[derive(Debug, Clone, Copy, Component, Reflect)]
+#[reflect(Component)]
+pub struct HikariThinLens {
+    /// Whether to sample the lens. When disabled (the default), the camera is a pinhole.
+    pub enabled: bool,
+    /// Diameter of the aperture in world units. Larger apertures give shallower depth of field.
+    pub aperture: f32,
+    /// Distance from the camera to the plane in perfect focus, in world units.
+    pub focus_distance: f32,
+    /// Count of aperture blades, which shapes the bokeh. Less than 3 means a circular aperture.
+    pub blade_count: u32,
+}
+
+impl Default for HikariThinLens {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            aperture: 0.05,
+            focus_distance: 5.0,
+            blade_count: 6,
+        }
+    }
+}
+
+/// Automatic exposure from a histogram of the render luminance, with temporally smoothed eye adaptation.
+/// Attach it next to [`HikariSettings`]; [`HikariSettings::exposure`] is then added as compensation.
+#[derive(Debug, Clone, Component, Reflect)]
+#[reflect(Component)]
+pub struct HikariAutoExposure {
+    /// Whether to meter the render. When disabled, only the manual exposure applies.
+    pub enabled: bool,
+    /// Darkest average scene luminance in EV (log2) the exposure adapts to.
+    pub min_ev: f32,
+    /// Brightest average scene luminance in EV (log2) the exposure adapts to.
+    pub max_ev: f32,
+    /// Rate of eye adaptation. The difference to the metered exposure decays by `exp(-speed * seconds)`.
+    pub speed: f32,
+    /// Weights of the screen in metering, read from the red channel and stretched over the view.
+    /// The default handle weights all pixels equally.
+    pub metering_mask: Handle<Image>,
+}
+
+impl Default for HikariAutoExposure {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_ev: -8.0,
+            max_ev: 8.0,
+            speed: 3.0,
+            metering_mask: Handle::default(),
+        }
+    }
+}
+
+/// Color grading of the tone mapped render, before anti-aliasing and upscaling.
+/// Attach it next to [`HikariSettings`].
+///
+/// The LUT and lift, gamma and gain take sRGB encoded colors, which is what display grades exported
+/// from tools like Resolve expect. Grading is skipped on HDR cameras, since their output is inverse tone mapped.
+#[derive(Debug, Clone, Component, Reflect)]
+#[reflect(Component)]
+pub struct HikariColorGrading {
+    pub enabled: bool,
+    /// 3D lookup table applied first, usually loaded from a `.cube` file.
+    /// The default handle leaves colors unchanged.
+    pub lut: Handle<Image>,
+    /// Offsets the shadows towards this color, leaving white in place.
+    pub lift: Vec3,
+    /// Power curve of the midtones; values above 1.0 brighten them.
+    pub gamma: Vec3,
+    /// Scales the highlights, leaving black in place.
+    pub gain: Vec3,
+}
+
+impl Default for HikariColorGrading {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            lut: Handle::default(),
+            lift: Vec3::ZERO,
+            gamma: Vec3::ONE,
+            gain: Vec3::ONE,
+        }
+    }
+}
+
 #[derive(Clone, Deref, Resource, ExtractResource)]
 pub struct NoiseTextures(pub Vec<Handle<Image>>);
 
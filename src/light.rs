@@ -1,11 +1,15 @@
 use crate::{
     mesh_material::{
-        MeshMaterialBindGroup, MeshMaterialBindGroupLayout, MeshMaterialSystems,
-        TextureBindGroupLayout,
+        fog_volume::FogVolumeRenderAssets, MeshMaterialBindGroup, MeshMaterialBindGroupLayout,
+        MeshMaterialSystems, TextureBindGroupLayout,
+    },
+    prepass::{
+        DeferredBindGroup, PrepassBindGroup, PrepassPipeline, PrepassTextures,
+        INSTANCE_MATERIAL_FORMAT, NORMAL_FORMAT, POSITION_FORMAT, VELOCITY_UV_FORMAT,
+        VERTEX_COLOR_UV_FORMAT,
     },
-    prepass::{DeferredBindGroup, PrepassBindGroup, PrepassPipeline, PrepassTextures},
     view::{FrameCounter, FrameUniform, PreviousViewUniformOffset},
-    HikariSettings, NoiseTextures, LIGHT_SHADER_HANDLE, WORKGROUP_SIZE,
+    HikariSettings, HikariThinLens, NoiseTextures, LIGHT_SHADER_HANDLE, WORKGROUP_SIZE,
 };
 use bevy::{
     pbr::ViewLightsUniformOffset,
@@ -29,6 +33,9 @@ use serde::Serialize;
 pub const ALBEDO_TEXTURE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
 pub const VARIANCE_TEXTURE_FORMAT: TextureFormat = TextureFormat::R32Float;
 pub const RENDER_TEXTURE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+/// Number of cells in the hashed radiance cache.
+pub const RADIANCE_CACHE_CAPACITY: usize = 1 << 18;
+const RADIANCE_CACHE_WORKGROUP_SIZE: u32 = 64;
 
 pub struct LightPlugin;
 impl Plugin for LightPlugin {
@@ -36,6 +43,8 @@ impl Plugin for LightPlugin {
         if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
             render_app
                 .init_resource::<ReservoirCache>()
+                .init_resource::<RadianceCacheBuffers>()
+                .init_resource::<AdaptiveStatisticsBuffers>()
                 .init_resource::<SpecializedComputePipelines<LightPipeline>>()
                 .add_system_to_stage(
                     RenderStage::Prepare,
@@ -68,10 +77,50 @@ pub struct GpuReservoirBuffer {
 #[derive(Default, Resource, Deref, DerefMut)]
 pub struct ReservoirCache(HashMap<Entity, Vec<StorageBuffer<GpuReservoirBuffer>>>);
 
+#[derive(Debug, Default, Clone, Copy, ShaderType)]
+pub struct GpuRadianceCacheCell {
+    /// Cached radiance in `rgb`, and the number of accumulated samples in `a`.
+    pub radiance: Vec4,
+    /// Hash of the cell key, to detect collisions.
+    pub checksum: u32,
+    /// Hash of the cell key updated this frame.
+    pub claim: u32,
+    /// Radiance sums of this frame in fixed point, and their sample count.
+    pub accumulation: [u32; 4],
+}
+
+#[derive(Default, Clone, ShaderType)]
+pub struct GpuRadianceCacheBuffer {
+    #[size(runtime)]
+    pub data: Vec<GpuRadianceCacheCell>,
+}
+
+#[derive(Default, Resource, Deref, DerefMut)]
+pub struct RadianceCacheBuffers(HashMap<Entity, StorageBuffer<GpuRadianceCacheBuffer>>);
+
+/// Screen totals of the emissive and indirect passes, which normalize adaptive sampling in the next frame.
+#[derive(Debug, Default, Clone, Copy, ShaderType)]
+pub struct GpuAdaptiveStatistics {
+    /// Sum of the normalized variance of the emissive and indirect passes, in fixed point.
+    pub variance_sum: UVec2,
+    /// Count of pixels covered by geometry in the emissive and indirect passes.
+    pub pixel_count: UVec2,
+}
+
+/// Statistics of the current and the previous frames.
+#[derive(Default, Clone, ShaderType)]
+pub struct GpuAdaptiveStatisticsBuffer {
+    pub data: [GpuAdaptiveStatistics; 2],
+}
+
+#[derive(Default, Resource, Deref, DerefMut)]
+pub struct AdaptiveStatisticsBuffers(HashMap<Entity, StorageBuffer<GpuAdaptiveStatisticsBuffer>>);
+
 #[derive(Resource)]
 pub struct LightPipeline {
     pub view_layout: BindGroupLayout,
     pub deferred_layout: BindGroupLayout,
+    pub primary_layout: BindGroupLayout,
     pub mesh_material_layout: BindGroupLayout,
 
     pub texture_count: u32,
@@ -91,6 +140,9 @@ pub enum LightEntryPoint {
     IndirectLitAmbient = 1,
     SpatialReuse = 2,
     FullScreenAlbedo = 3,
+    FogLit = 4,
+    TracePrimary = 5,
+    RadianceCacheResolve = 6,
 }
 
 bitflags::bitflags! {
@@ -155,9 +207,16 @@ impl SpecializedComputePipeline for LightPipeline {
             .unwrap()
             .into();
 
-        ComputePipelineDescriptor {
-            label: None,
-            layout: Some(vec![
+        let layout = match key.entry_point() {
+            // Writes the prepass textures, so they are bound as storage instead of being sampled.
+            LightEntryPoint::TracePrimary => vec![
+                self.view_layout.clone(),
+                self.primary_layout.clone(),
+                self.mesh_material_layout.clone(),
+                self.texture_layout.clone(),
+                self.noise_layout.clone(),
+            ],
+            _ => vec![
                 self.view_layout.clone(),
                 self.deferred_layout.clone(),
                 self.mesh_material_layout.clone(),
@@ -165,7 +224,12 @@ impl SpecializedComputePipeline for LightPipeline {
                 self.noise_layout.clone(),
                 self.render_layout.clone(),
                 self.reservoir_layout.clone(),
-            ]),
+            ],
+        };
+
+        ComputePipelineDescriptor {
+            label: None,
+            layout: Some(layout),
             shader: LIGHT_SHADER_HANDLE.typed::<Shader>(),
             shader_defs,
             entry_point,
@@ -193,6 +257,31 @@ fn prepare_light_pipeline(
     let deferred_layout = PrepassTextures::bind_group_layout(&render_device);
     let noise_layout = NoiseTextures::bind_group_layout(&render_device);
 
+    let primary_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[
+            (10, StorageTextureAccess::ReadWrite, POSITION_FORMAT),
+            (11, StorageTextureAccess::WriteOnly, NORMAL_FORMAT),
+            (
+                12,
+                StorageTextureAccess::WriteOnly,
+                INSTANCE_MATERIAL_FORMAT,
+            ),
+            (13, StorageTextureAccess::WriteOnly, VELOCITY_UV_FORMAT),
+            (14, StorageTextureAccess::WriteOnly, VERTEX_COLOR_UV_FORMAT),
+        ]
+        .map(|(binding, access, format)| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::StorageTexture {
+                access,
+                format,
+                view_dimension: TextureViewDimension::D2,
+            },
+            count: None,
+        }),
+    });
+
     let render_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
         label: None,
         entries: &[
@@ -279,12 +368,35 @@ fn prepare_light_pipeline(
                 },
                 count: None,
             },
+            // Radiance Cache
+            BindGroupLayoutEntry {
+                binding: 4,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(GpuRadianceCacheBuffer::min_size()),
+                },
+                count: None,
+            },
+            // Adaptive Sampling Statistics
+            BindGroupLayoutEntry {
+                binding: 5,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(GpuAdaptiveStatisticsBuffer::min_size()),
+                },
+                count: None,
+            },
         ],
     });
 
     commands.insert_resource(LightPipeline {
         view_layout,
         deferred_layout,
+        primary_layout,
         mesh_material_layout,
         texture_count,
         texture_layout,
@@ -299,8 +411,14 @@ pub struct LightTextures {
     /// Index of the current frame's output denoised texture.
     pub head: usize,
     pub albedo: TextureView,
-    pub variance: [TextureView; 3],
-    pub render: [TextureView; 3],
+    /// Variance of the direct, emissive, indirect and fog passes.
+    pub variance: [TextureView; 4],
+    /// Output of the direct, emissive, indirect and fog passes.
+    /// The fog texture holds the in-scattered radiance and the transmittance in alpha.
+    pub render: [TextureView; 4],
+    /// Output of the indirect pass at [`IndirectResolution`](crate::IndirectResolution).
+    /// At full resolution this is the same texture as the indirect render; otherwise it is upsampled into it.
+    pub indirect_render: TextureView,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -310,13 +428,20 @@ fn prepare_light_textures(
     render_queue: Res<RenderQueue>,
     mut texture_cache: ResMut<TextureCache>,
     mut reservoir_cache: ResMut<ReservoirCache>,
+    mut radiance_caches: ResMut<RadianceCacheBuffers>,
+    mut adaptive_statistics: ResMut<AdaptiveStatisticsBuffers>,
     cameras: Query<(Entity, &ExtractedCamera, &FrameCounter, &HikariSettings)>,
 ) {
     for (entity, camera, counter, settings) in &cameras {
         if let Some(size) = camera.physical_target_size {
-            let texture_usage = TextureUsages::TEXTURE_BINDING | TextureUsages::STORAGE_BINDING;
+            // The fog render is cleared as an attachment when there are no fog volumes.
+            let texture_usage = TextureUsages::TEXTURE_BINDING
+                | TextureUsages::STORAGE_BINDING
+                | TextureUsages::RENDER_ATTACHMENT;
             let scale = settings.upscale.ratio().recip();
             let scaled_size = (scale * size.as_vec2()).ceil().as_uvec2();
+            let indirect_scale = settings.indirect_resolution.scale();
+            let indirect_size = (scaled_size + indirect_scale - 1) / indirect_scale;
             let mut create_texture = |texture_format, size: UVec2| {
                 let extent = Extent3d {
                     width: size.x,
@@ -362,21 +487,53 @@ fn prepare_light_textures(
                 reservoir_cache.insert(entity, reservoirs);
             }
 
+            // A disabled cache is never read or written, so it only binds a single cell.
+            let capacity = match settings.radiance_cache.enabled {
+                true => RADIANCE_CACHE_CAPACITY,
+                false => 1,
+            };
+            if radiance_caches
+                .get(&entity)
+                .map_or(true, |buffer| buffer.get().data.len() != capacity)
+            {
+                let mut buffer = StorageBuffer::from(GpuRadianceCacheBuffer {
+                    data: vec![GpuRadianceCacheCell::default(); capacity],
+                });
+                buffer.write_buffer(&render_device, &render_queue);
+                radiance_caches.insert(entity, buffer);
+            }
+
+            if !adaptive_statistics.contains_key(&entity) {
+                let mut buffer = StorageBuffer::from(GpuAdaptiveStatisticsBuffer::default());
+                buffer.write_buffer(&render_device, &render_queue);
+                adaptive_statistics.insert(entity, buffer);
+            }
+
             macro_rules! create_texture_array {
                 [$texture_format:ident, $size:ident; $count:literal] => {
                     [(); $count].map(|_| create_texture($texture_format, $size))
                 };
             }
 
-            let variance = create_texture_array![VARIANCE_TEXTURE_FORMAT, scaled_size; 3];
-            let render = create_texture_array![RENDER_TEXTURE_FORMAT, scaled_size; 3];
+            let mut variance = create_texture_array![VARIANCE_TEXTURE_FORMAT, scaled_size; 4];
+            let render = create_texture_array![RENDER_TEXTURE_FORMAT, scaled_size; 4];
             let albedo = create_texture(ALBEDO_TEXTURE_FORMAT, size);
 
+            // The denoiser samples variance by UV, so the indirect variance is left at the lower resolution.
+            let indirect_render = match indirect_scale {
+                1 => render[2].clone(),
+                _ => {
+                    variance[2] = create_texture(VARIANCE_TEXTURE_FORMAT, indirect_size);
+                    create_texture(RENDER_TEXTURE_FORMAT, indirect_size)
+                }
+            };
+
             commands.entity(entity).insert(LightTextures {
                 head: counter.0 % 2,
                 albedo,
                 variance,
                 render,
+                indirect_render,
             });
         }
     }
@@ -384,6 +541,7 @@ fn prepare_light_textures(
 
 #[derive(Resource)]
 pub struct CachedLightPipelines {
+    trace_primary: CachedComputePipelineId,
     full_screen_albedo: CachedComputePipelineId,
     direct_lit: CachedComputePipelineId,
     direct_emissive: CachedComputePipelineId,
@@ -391,6 +549,8 @@ pub struct CachedLightPipelines {
     indirect_multiple_bounces: CachedComputePipelineId,
     emissive_spatial_reuse: CachedComputePipelineId,
     indirect_spatial_reuse: CachedComputePipelineId,
+    fog_lit: CachedComputePipelineId,
+    radiance_cache_resolve: CachedComputePipelineId,
 }
 
 fn queue_light_pipelines(
@@ -401,6 +561,11 @@ fn queue_light_pipelines(
 ) {
     let key = LightPipelineKey::from_texture_count(pipeline.texture_count);
 
+    let trace_primary = {
+        let key = key | LightPipelineKey::from_entry_point(LightEntryPoint::TracePrimary);
+        pipelines.specialize(&mut pipeline_cache, &pipeline, key)
+    };
+
     let full_screen_albedo = {
         let key = key | LightPipelineKey::from_entry_point(LightEntryPoint::FullScreenAlbedo);
         pipelines.specialize(&mut pipeline_cache, &pipeline, key)
@@ -441,7 +606,18 @@ fn queue_light_pipelines(
         pipelines.specialize(&mut pipeline_cache, &pipeline, key)
     };
 
+    let fog_lit = {
+        let key = key | LightPipelineKey::from_entry_point(LightEntryPoint::FogLit);
+        pipelines.specialize(&mut pipeline_cache, &pipeline, key)
+    };
+
+    let radiance_cache_resolve = {
+        let key = key | LightPipelineKey::from_entry_point(LightEntryPoint::RadianceCacheResolve);
+        pipelines.specialize(&mut pipeline_cache, &pipeline, key)
+    };
+
     commands.insert_resource(CachedLightPipelines {
+        trace_primary,
         full_screen_albedo,
         direct_lit,
         direct_emissive,
@@ -449,13 +625,17 @@ fn queue_light_pipelines(
         indirect_multiple_bounces,
         emissive_spatial_reuse,
         indirect_spatial_reuse,
+        fog_lit,
+        radiance_cache_resolve,
     })
 }
 
 #[derive(Component, Clone)]
 pub struct LightBindGroup {
     pub noise: BindGroup,
-    pub render: [BindGroup; 3],
+    /// Prepass textures as storage, for the primary tracing pass.
+    pub primary: BindGroup,
+    pub render: [BindGroup; 4],
     pub reservoir: [BindGroup; 3],
 }
 
@@ -468,15 +648,22 @@ fn queue_light_bind_groups(
     images: Res<RenderAssets<Image>>,
     fallback: Res<FallbackImage>,
     reservoir_cache: Res<ReservoirCache>,
-    query: Query<(Entity, &LightTextures), With<ExtractedCamera>>,
+    radiance_caches: Res<RadianceCacheBuffers>,
+    adaptive_statistics: Res<AdaptiveStatisticsBuffers>,
+    query: Query<(Entity, &LightTextures, &PrepassTextures), With<ExtractedCamera>>,
 ) {
-    for (entity, light) in &query {
+    for (entity, light, prepass) in &query {
         let reservoirs = reservoir_cache.get(&entity).unwrap();
-        if let Some(reservoir_bindings) = reservoirs
-            .iter()
-            .map(|buffer| buffer.binding())
-            .collect::<Option<Vec<_>>>()
-        {
+        let radiance_cache = radiance_caches.get(&entity).unwrap();
+        let statistics = adaptive_statistics.get(&entity).unwrap();
+        if let (Some(reservoir_bindings), Some(radiance_cache_binding), Some(statistics_binding)) = (
+            reservoirs
+                .iter()
+                .map(|buffer| buffer.binding())
+                .collect::<Option<Vec<_>>>(),
+            radiance_cache.binding(),
+            statistics.binding(),
+        ) {
             let current = light.head;
             let previous = 1 - current;
 
@@ -491,9 +678,47 @@ fn queue_light_bind_groups(
             }
             .bind_group;
 
-            let render = [0, 1, 2].map(|id| {
+            let prepass = match prepass.prepared(&images) {
+                Some(prepass) => prepass,
+                None => continue,
+            };
+            let primary = render_device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                layout: &pipeline.primary_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 10,
+                        resource: BindingResource::TextureView(&prepass.position.texture_view),
+                    },
+                    BindGroupEntry {
+                        binding: 11,
+                        resource: BindingResource::TextureView(&prepass.normal.texture_view),
+                    },
+                    BindGroupEntry {
+                        binding: 12,
+                        resource: BindingResource::TextureView(
+                            &prepass.instance_material.texture_view,
+                        ),
+                    },
+                    BindGroupEntry {
+                        binding: 13,
+                        resource: BindingResource::TextureView(&prepass.velocity_uv.texture_view),
+                    },
+                    BindGroupEntry {
+                        binding: 14,
+                        resource: BindingResource::TextureView(
+                            &prepass.vertex_color_uv.texture_view,
+                        ),
+                    },
+                ],
+            });
+
+            let render = [0, 1, 2, 3].map(|id| {
                 let variance = &light.variance[id];
-                let render = &light.render[id];
+                let render = match id {
+                    2 => &light.indirect_render,
+                    _ => &light.render[id],
+                };
 
                 render_device.create_bind_group(&BindGroupDescriptor {
                     label: None,
@@ -541,12 +766,21 @@ fn queue_light_bind_groups(
                             binding: 3,
                             resource: previous_spatial,
                         },
+                        BindGroupEntry {
+                            binding: 4,
+                            resource: radiance_cache_binding.clone(),
+                        },
+                        BindGroupEntry {
+                            binding: 5,
+                            resource: statistics_binding.clone(),
+                        },
                     ],
                 })
             });
 
             commands.entity(entity).insert(LightBindGroup {
                 noise,
+                primary,
                 render,
                 reservoir,
             });
@@ -563,8 +797,10 @@ pub struct LightNode {
         &'static PreviousViewUniformOffset,
         &'static ViewLightsUniformOffset,
         &'static DeferredBindGroup,
+        &'static LightTextures,
         &'static LightBindGroup,
         &'static HikariSettings,
+        Option<&'static HikariThinLens>,
     )>,
 }
 
@@ -601,8 +837,10 @@ impl Node for LightNode {
             previous_view_uniform,
             view_lights,
             deferred_bind_group,
+            light,
             light_bind_group,
             settings,
+            lens,
         ) = match self.query.get_manual(world, entity) {
             Ok(query) => query,
             Err(_) => return Ok(()),
@@ -622,6 +860,21 @@ impl Node for LightNode {
         let size = camera.physical_target_size.unwrap();
         let scale = settings.upscale.ratio().recip();
         let scaled_size = (scale * size.as_vec2()).ceil().as_uvec2();
+        let indirect_scale = settings.indirect_resolution.scale();
+        let indirect_size = (scaled_size + indirect_scale - 1) / indirect_scale;
+
+        // Reset the statistics of this frame; those of the previous frame drive adaptive sampling.
+        if let Some(buffer) = world
+            .resource::<AdaptiveStatisticsBuffers>()
+            .get(&entity)
+            .and_then(|statistics| statistics.buffer())
+        {
+            let size = GpuAdaptiveStatistics::min_size();
+            let offset = light.head as u64 * size.get();
+            render_context
+                .command_encoder
+                .clear_buffer(buffer, offset, Some(size));
+        }
 
         let mut pass = render_context
             .command_encoder
@@ -637,11 +890,28 @@ impl Node for LightNode {
                 view_lights.offset,
             ],
         );
-        pass.set_bind_group(1, &deferred_bind_group.0, &[]);
         pass.set_bind_group(2, &mesh_material_bind_group.mesh_material, &[]);
         pass.set_bind_group(3, &mesh_material_bind_group.texture, &[]);
         pass.set_bind_group(4, &light_bind_group.noise, &[]);
 
+        // Primary tracing pass for the thin lens and motion blur.
+        // It traces over the prepass textures before they are read.
+        let lens_enabled = lens.map_or(false, |lens| lens.enabled && lens.aperture > 0.0);
+        let motion_blur_enabled =
+            settings.motion_blur.enabled && settings.motion_blur.shutter > 0.0;
+        if let Some(pipeline) = pipeline_cache
+            .get_compute_pipeline(pipelines.trace_primary)
+            .filter(|_| lens_enabled || motion_blur_enabled)
+        {
+            pass.set_bind_group(1, &light_bind_group.primary, &[]);
+            pass.set_pipeline(pipeline);
+
+            let count = (size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+            pass.dispatch_workgroups(count.x, count.y, 1);
+        }
+
+        pass.set_bind_group(1, &deferred_bind_group.0, &[]);
+
         // Full screen albedo pass.
         if let Some(pipeline) = pipeline_cache.get_compute_pipeline(pipelines.full_screen_albedo) {
             pass.set_bind_group(5, &light_bind_group.render[0], &[]);
@@ -653,7 +923,7 @@ impl Node for LightNode {
         }
 
         // Direct, emissive and indirect passes.
-        for (render, reservoir, temporal_pipeline, spatial_pipeline, enable_spatial_reuse) in
+        for (render, reservoir, temporal_pipeline, spatial_pipeline, enable_spatial_reuse, size) in
             multizip((
                 light_bind_group.render.iter(),
                 light_bind_group.reservoir.iter(),
@@ -675,6 +945,7 @@ impl Node for LightNode {
                     settings.emissive_spatial_reuse,
                     settings.indirect_spatial_reuse,
                 ],
+                [scaled_size, scaled_size, indirect_size],
             ))
         {
             pass.set_bind_group(5, render, &[]);
@@ -683,7 +954,7 @@ impl Node for LightNode {
             if let Some(pipeline) = pipeline_cache.get_compute_pipeline(*temporal_pipeline) {
                 pass.set_pipeline(pipeline);
 
-                let count = (scaled_size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+                let count = (size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
                 pass.dispatch_workgroups(count.x, count.y, 1);
 
                 if let Some(pipeline) = spatial_pipeline
@@ -692,12 +963,56 @@ impl Node for LightNode {
                 {
                     pass.set_pipeline(pipeline);
 
-                    let count = (scaled_size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+                    let count = (size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
                     pass.dispatch_workgroups(count.x, count.y, 1);
                 }
             }
         }
 
+        // Blend the samples of the indirect pass into the radiance cache.
+        if let Some(pipeline) = pipeline_cache
+            .get_compute_pipeline(pipelines.radiance_cache_resolve)
+            .filter(|_| settings.radiance_cache.enabled)
+        {
+            pass.set_pipeline(pipeline);
+
+            let count = RADIANCE_CACHE_CAPACITY as u32 / RADIANCE_CACHE_WORKGROUP_SIZE;
+            pass.dispatch_workgroups(count, 1, 1);
+        }
+
+        // Participating media pass.
+        let fog_volumes = world.resource::<FogVolumeRenderAssets>();
+        if let Some(pipeline) = pipeline_cache
+            .get_compute_pipeline(pipelines.fog_lit)
+            .filter(|_| !fog_volumes.is_empty())
+        {
+            pass.set_bind_group(5, &light_bind_group.render[3], &[]);
+            pass.set_bind_group(6, &light_bind_group.reservoir[0], &[]);
+            pass.set_pipeline(pipeline);
+
+            let count = (scaled_size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+            pass.dispatch_workgroups(count.x, count.y, 1);
+        }
+        drop(pass);
+
+        if fog_volumes.is_empty() {
+            // Nothing is scattered and everything is transmitted
+            render_context
+                .command_encoder
+                .begin_render_pass(&RenderPassDescriptor {
+                    label: None,
+                    color_attachments: &[Some(RenderPassColorAttachment {
+                        view: &light.render[3],
+                        resolve_target: None,
+                        ops: Operations {
+                            load: LoadOp::Clear(Color::BLACK.into()),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+        }
+
         Ok(())
     }
 }
@@ -0,0 +1,437 @@
+use super::{
+    instance::HikariLightLink,
+    light_profile::{GpuLightProfileBuffer, HikariLightProfile, IesProfile},
+    material::MaterialTextures,
+    MeshMaterialSystems,
+};
+use crate::HikariUniversalSettings;
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        Extract, RenderApp, RenderStage,
+    },
+    utils::HashMap,
+};
+
+pub struct AreaLightPlugin;
+impl Plugin for AreaLightPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<HikariRectLight>()
+            .register_type::<HikariDiskLight>()
+            .register_type::<HikariSphereLight>();
+
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .init_resource::<ExtractedAreaLights>()
+                .init_resource::<AreaLightRenderAssets>()
+                .add_system_to_stage(RenderStage::Extract, extract_area_lights)
+                .add_system_to_stage(
+                    RenderStage::Prepare,
+                    prepare_area_lights
+                        .label(MeshMaterialSystems::PrepareAreaLights)
+                        .after(MeshMaterialSystems::PrepareTextures)
+                        .before(MeshMaterialSystems::PrepareAssets),
+                );
+        }
+    }
+}
+
+/// A rectangular area light without mesh geometry.
+/// Light sampling visits every area light, so they are meant for scenes with a few of them.
+/// The light lies in the local XY plane and emits towards the local -Z axis.
+/// It is not visible to the camera; pair it with an emissive mesh if that is desired.
+/// Spawn it with a [`SpatialBundle`] so that it has a transform and visibility.
+#[derive(Debug, Clone, Component, Reflect)]
+#[reflect(Component)]
+pub struct HikariRectLight {
+    pub color: Color,
+    /// Emissive strength, with the same meaning as [`HikariMaterial::emissive_strength`](super::HikariMaterial::emissive_strength).
+    pub intensity: f32,
+    /// Width and height of the rectangle, before the transform is applied.
+    pub size: Vec2,
+    /// Whether the light also emits from its back face.
+    pub two_sided: bool,
+}
+
+impl Default for HikariRectLight {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            intensity: 10.0,
+            size: Vec2::ONE,
+            two_sided: false,
+        }
+    }
+}
+
+/// A disk shaped area light without mesh geometry.
+/// The light lies in the local XY plane and emits towards the local -Z axis.
+/// It is not visible to the camera; pair it with an emissive mesh if that is desired.
+/// Spawn it with a [`SpatialBundle`] so that it has a transform and visibility.
+#[derive(Debug, Clone, Component, Reflect)]
+#[reflect(Component)]
+pub struct HikariDiskLight {
+    pub color: Color,
+    /// Emissive strength, with the same meaning as [`HikariMaterial::emissive_strength`](super::HikariMaterial::emissive_strength).
+    pub intensity: f32,
+    /// Radius of the disk, before the transform is applied.
+    pub radius: f32,
+    /// Whether the light also emits from its back face.
+    pub two_sided: bool,
+}
+
+impl Default for HikariDiskLight {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            intensity: 10.0,
+            radius: 0.5,
+            two_sided: false,
+        }
+    }
+}
+
+/// A spherical area light without mesh geometry.
+/// It is not visible to the camera; pair it with an emissive mesh if that is desired.
+/// Spawn it with a [`SpatialBundle`] so that it has a transform and visibility.
+#[derive(Debug, Clone, Component, Reflect)]
+#[reflect(Component)]
+pub struct HikariSphereLight {
+    pub color: Color,
+    /// Emissive strength, with the same meaning as [`HikariMaterial::emissive_strength`](super::HikariMaterial::emissive_strength).
+    pub intensity: f32,
+    /// Radius of the sphere, scaled by the largest axis of the transform.
+    /// The rotation of the transform orients an attached [`HikariLightProfile`].
+    pub radius: f32,
+}
+
+impl Default for HikariSphereLight {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            intensity: 10.0,
+            radius: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum AreaLightKind {
+    Rect = 0,
+    Disk = 1,
+    Sphere = 2,
+}
+
+#[derive(Debug, Default, Clone, Copy, ShaderType)]
+pub struct GpuAreaLight {
+    /// Linear color times intensity.
+    pub radiance: Vec3,
+    /// Shape of the light, see [`AreaLightKind`].
+    pub kind: u32,
+    /// Center of the light in world space.
+    pub position: Vec3,
+    /// Radius of influence: the extent of the light plus the distance at which
+    /// its illuminance falls below [`HikariUniversalSettings::emissive_threshold`].
+    pub range: f32,
+    /// Half extent along the first axis of the light.
+    pub axis_x: Vec3,
+    /// World space surface area.
+    pub area: f32,
+    /// Half extent along the second axis of the light.
+    pub axis_y: Vec3,
+    /// Whether the light emits from both faces.
+    pub two_sided: u32,
+    /// Offset of the IES intensity table in the profile buffer, or `u32::MAX` if none.
+    pub profile: u32,
+    /// Index of the cookie texture, or `u32::MAX` if none.
+    pub cookie: u32,
+    /// Tangent of the half projection angle of the cookie.
+    pub cookie_scale: f32,
+    /// Receiver groups this light affects, see [`HikariLightLink`].
+    pub light_include: u32,
+    /// Receiver groups this light never affects.
+    pub light_exclude: u32,
+}
+
+impl GpuAreaLight {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        kind: AreaLightKind,
+        color: Color,
+        intensity: f32,
+        transform: &GlobalTransform,
+        axis_x: Vec3,
+        axis_y: Vec3,
+        two_sided: bool,
+        threshold: f32,
+    ) -> Self {
+        let radiance = Vec4::from(color.as_linear_rgba_f32()).truncate() * intensity;
+        let area = match kind {
+            AreaLightKind::Rect => 4.0 * axis_x.cross(axis_y).length(),
+            AreaLightKind::Disk => std::f32::consts::PI * axis_x.cross(axis_y).length(),
+            AreaLightKind::Sphere => 4.0 * std::f32::consts::PI * axis_x.length_squared(),
+        };
+        let extent = match kind {
+            AreaLightKind::Rect => (axis_x + axis_y).length().max((axis_x - axis_y).length()),
+            AreaLightKind::Disk => axis_x.length().max(axis_y.length()),
+            AreaLightKind::Sphere => axis_x.length(),
+        };
+
+        // Same as emissive instances: illuminance approximated by `luminance * area / distance^2`.
+        let luminance = radiance.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+        let range = extent + (luminance * area / threshold.max(f32::EPSILON)).sqrt();
+
+        Self {
+            radiance,
+            kind: kind as u32,
+            position: transform.translation(),
+            range,
+            axis_x,
+            area,
+            axis_y,
+            two_sided: two_sided.into(),
+            profile: u32::MAX,
+            cookie: u32::MAX,
+            cookie_scale: 1.0,
+            light_include: u32::MAX,
+            light_exclude: 0,
+        }
+    }
+}
+
+#[derive(Default, ShaderType)]
+pub struct GpuAreaLightBuffer {
+    pub count: u32,
+    #[size(runtime)]
+    pub data: Vec<GpuAreaLight>,
+}
+
+#[derive(Default, Resource)]
+pub struct ExtractedAreaLights {
+    pub lights: Vec<GpuAreaLight>,
+    pub cookies: Vec<Option<Handle<Image>>>,
+    pub profiles: Vec<f32>,
+}
+
+#[derive(Default, Resource)]
+pub struct AreaLightRenderAssets {
+    pub area_light_buffer: StorageBuffer<GpuAreaLightBuffer>,
+    pub light_profile_buffer: StorageBuffer<GpuLightProfileBuffer>,
+}
+
+#[allow(clippy::type_complexity)]
+fn extract_area_lights(
+    mut extracted: ResMut<ExtractedAreaLights>,
+    universal_settings: Extract<Res<HikariUniversalSettings>>,
+    ies_profiles: Extract<Res<Assets<IesProfile>>>,
+    rect_lights: Extract<
+        Query<(
+            &HikariRectLight,
+            &GlobalTransform,
+            &ComputedVisibility,
+            Option<&HikariLightProfile>,
+            Option<&HikariLightLink>,
+        )>,
+    >,
+    disk_lights: Extract<
+        Query<(
+            &HikariDiskLight,
+            &GlobalTransform,
+            &ComputedVisibility,
+            Option<&HikariLightProfile>,
+            Option<&HikariLightLink>,
+        )>,
+    >,
+    sphere_lights: Extract<
+        Query<(
+            &HikariSphereLight,
+            &GlobalTransform,
+            &ComputedVisibility,
+            Option<&HikariLightProfile>,
+            Option<&HikariLightLink>,
+        )>,
+    >,
+) {
+    let threshold = universal_settings.emissive_threshold;
+    let mut lights = vec![];
+
+    for (light, transform, visibility, profile, link) in &rect_lights {
+        if !visibility.is_visible_in_hierarchy() {
+            continue;
+        }
+        let matrix = transform.compute_matrix();
+        let axis_x = matrix.transform_vector3(0.5 * light.size.x * Vec3::X);
+        let axis_y = matrix.transform_vector3(0.5 * light.size.y * Vec3::Y);
+        let area_light = GpuAreaLight::new(
+            AreaLightKind::Rect,
+            light.color,
+            light.intensity,
+            transform,
+            axis_x,
+            axis_y,
+            light.two_sided,
+            threshold,
+        );
+        lights.push((area_light, profile, link));
+    }
+
+    for (light, transform, visibility, profile, link) in &disk_lights {
+        if !visibility.is_visible_in_hierarchy() {
+            continue;
+        }
+        let matrix = transform.compute_matrix();
+        let axis_x = matrix.transform_vector3(light.radius * Vec3::X);
+        let axis_y = matrix.transform_vector3(light.radius * Vec3::Y);
+        let area_light = GpuAreaLight::new(
+            AreaLightKind::Disk,
+            light.color,
+            light.intensity,
+            transform,
+            axis_x,
+            axis_y,
+            light.two_sided,
+            threshold,
+        );
+        lights.push((area_light, profile, link));
+    }
+
+    for (light, transform, visibility, profile, link) in &sphere_lights {
+        if !visibility.is_visible_in_hierarchy() {
+            continue;
+        }
+        let (scale, rotation, _) = transform.to_scale_rotation_translation();
+        let radius = light.radius * scale.max_element();
+        let axis_x = rotation * (radius * Vec3::X);
+        let axis_y = rotation * (radius * Vec3::Y);
+        let area_light = GpuAreaLight::new(
+            AreaLightKind::Sphere,
+            light.color,
+            light.intensity,
+            transform,
+            axis_x,
+            axis_y,
+            true,
+            threshold,
+        );
+        lights.push((area_light, profile, link));
+    }
+
+    extracted.lights.clear();
+    extracted.cookies.clear();
+    extracted.profiles.clear();
+
+    let mut profile_offsets = HashMap::new();
+    for (mut area_light, profile, link) in lights {
+        if let Some(link) = link {
+            area_light.light_include = link.include;
+            area_light.light_exclude = link.exclude;
+        }
+
+        let mut cookie = None;
+        match profile {
+            Some(HikariLightProfile::Ies(handle)) => {
+                if let Some(profile) = ies_profiles.get(handle) {
+                    let offset = *profile_offsets.entry(handle.id()).or_insert_with(|| {
+                        let offset = extracted.profiles.len();
+                        extracted.profiles.extend_from_slice(&profile.table);
+                        offset
+                    });
+                    area_light.profile = offset as u32;
+                }
+            }
+            Some(HikariLightProfile::Cookie { image, fov }) => {
+                area_light.cookie_scale = (0.5 * fov).tan();
+                cookie = Some(image.clone_weak());
+            }
+            None => {}
+        }
+        extracted.lights.push(area_light);
+        extracted.cookies.push(cookie);
+    }
+}
+
+fn prepare_area_lights(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    extracted: Res<ExtractedAreaLights>,
+    mut textures: ResMut<MaterialTextures>,
+    mut render_assets: ResMut<AreaLightRenderAssets>,
+) {
+    let lights = extracted
+        .lights
+        .iter()
+        .zip(extracted.cookies.iter())
+        .map(|(area_light, cookie)| {
+            let mut area_light = *area_light;
+            if let Some(cookie) = cookie {
+                area_light.cookie = textures.add_texture(cookie);
+            }
+            area_light
+        })
+        .collect();
+
+    let buffer = render_assets.area_light_buffer.get_mut();
+    buffer.count = extracted.lights.len() as u32;
+    buffer.data = lights;
+
+    let buffer = render_assets.light_profile_buffer.get_mut();
+    buffer.count = extracted.profiles.len() as u32;
+    buffer.data = extracted.profiles.clone();
+
+    render_assets
+        .area_light_buffer
+        .write_buffer(&render_device, &render_queue);
+    render_assets
+        .light_profile_buffer
+        .write_buffer(&render_device, &render_queue);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn area_light(kind: AreaLightKind, axis_x: Vec3, axis_y: Vec3, threshold: f32) -> GpuAreaLight {
+        GpuAreaLight::new(
+            kind,
+            Color::WHITE,
+            10.0,
+            &GlobalTransform::IDENTITY,
+            axis_x,
+            axis_y,
+            false,
+            threshold,
+        )
+    }
+
+    #[test]
+    fn area_of_shapes() {
+        let rect = area_light(AreaLightKind::Rect, Vec3::X, 2.0 * Vec3::Y, 1.0);
+        assert!((rect.area - 8.0).abs() < 1e-5);
+
+        let disk = area_light(AreaLightKind::Disk, 0.5 * Vec3::X, 0.5 * Vec3::Z, 1.0);
+        assert!((disk.area - 0.25 * PI).abs() < 1e-5);
+
+        let sphere = area_light(AreaLightKind::Sphere, 2.0 * Vec3::X, 2.0 * Vec3::Y, 1.0);
+        assert!((sphere.area - 16.0 * PI).abs() < 1e-4);
+    }
+
+    #[test]
+    fn range_from_luminance_and_area() {
+        // A white light of strength 10 has a luminance of 10
+        let rect = area_light(AreaLightKind::Rect, 0.5 * Vec3::X, 0.5 * Vec3::Y, 0.1);
+        let extent = 0.5 * 2.0_f32.sqrt();
+        assert!((rect.range - (extent + 10.0)).abs() < 1e-4);
+
+        // Quadrupling the area doubles the distance to the threshold
+        let rect = area_light(AreaLightKind::Rect, Vec3::X, Vec3::Y, 0.1);
+        let extent = 2.0_f32.sqrt();
+        assert!((rect.range - (extent + 20.0)).abs() < 1e-4);
+
+        let sphere = area_light(AreaLightKind::Sphere, Vec3::X, Vec3::Y, 4.0 * PI);
+        assert!((sphere.range - (1.0 + 10.0_f32.sqrt())).abs() < 1e-4);
+    }
+}
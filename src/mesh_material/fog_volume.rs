@@ -0,0 +1,143 @@
+use super::instance::HikariLightLink;
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        Extract, RenderApp, RenderStage,
+    },
+};
+
+pub struct FogVolumePlugin;
+impl Plugin for FogVolumePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<HikariFogVolume>()
+            .register_type::<FogVolumeShape>();
+
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .init_resource::<ExtractedFogVolumes>()
+                .init_resource::<FogVolumeRenderAssets>()
+                .add_system_to_stage(RenderStage::Extract, extract_fog_volumes)
+                .add_system_to_stage(RenderStage::Prepare, prepare_fog_volumes);
+        }
+    }
+}
+
+/// Extent of a [`HikariFogVolume`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Reflect)]
+pub enum FogVolumeShape {
+    /// Fills the bounding box of the whole scene; the transform is ignored.
+    #[default]
+    Global,
+    /// A unit cube centered at the origin, before the transform is applied.
+    Box,
+}
+
+/// A homogeneous participating medium, such as fog, haze or smoke.
+/// Light passing through it is attenuated and scattered towards the camera.
+/// Spawn it with a [`SpatialBundle`] so that it has a transform and visibility.
+/// Add a [`HikariLightLink`] to choose the light groups it receives light as.
+#[derive(Debug, Clone, Component, Reflect)]
+#[reflect(Component)]
+pub struct HikariFogVolume {
+    pub shape: FogVolumeShape,
+    /// Extinction coefficient, i.e., the probability per world unit that light interacts with the medium.
+    pub density: f32,
+    /// Fraction of the extinguished light that is scattered rather than absorbed.
+    pub albedo: Color,
+    /// Henyey-Greenstein asymmetry, from -1.0 (back scattering) to 1.0 (forward scattering).
+    pub anisotropy: f32,
+}
+
+impl Default for HikariFogVolume {
+    fn default() -> Self {
+        Self {
+            shape: FogVolumeShape::Global,
+            density: 0.02,
+            albedo: Color::WHITE,
+            anisotropy: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, ShaderType)]
+pub struct GpuFogVolume {
+    /// Transforms world space into the unit cube of a box volume.
+    pub inverse_model: Mat4,
+    /// Linear scattering albedo.
+    pub albedo: Vec3,
+    pub density: f32,
+    pub anisotropy: f32,
+    /// Shape of the volume, see [`FogVolumeShape`].
+    pub shape: u32,
+    /// Light groups the medium receives light as, see [`HikariLightLink::groups`].
+    pub light_groups: u32,
+}
+
+#[derive(Default, ShaderType)]
+pub struct GpuFogVolumeBuffer {
+    pub count: u32,
+    #[size(runtime)]
+    pub data: Vec<GpuFogVolume>,
+}
+
+#[derive(Default, Resource)]
+pub struct ExtractedFogVolumes(pub Vec<GpuFogVolume>);
+
+#[derive(Default, Resource)]
+pub struct FogVolumeRenderAssets {
+    pub fog_volume_buffer: StorageBuffer<GpuFogVolumeBuffer>,
+}
+
+impl FogVolumeRenderAssets {
+    /// Whether there is any fog volume to render this frame.
+    pub fn is_empty(&self) -> bool {
+        self.fog_volume_buffer.get().count == 0
+    }
+}
+
+fn extract_fog_volumes(
+    mut extracted: ResMut<ExtractedFogVolumes>,
+    volumes: Extract<
+        Query<(
+            &HikariFogVolume,
+            &GlobalTransform,
+            &ComputedVisibility,
+            Option<&HikariLightLink>,
+        )>,
+    >,
+) {
+    extracted.0 = volumes
+        .iter()
+        .filter(|(volume, _, visibility, _)| {
+            visibility.is_visible_in_hierarchy() && volume.density > 0.0
+        })
+        .map(|(volume, transform, _, link)| GpuFogVolume {
+            inverse_model: transform.compute_matrix().inverse(),
+            albedo: Vec4::from(volume.albedo.as_linear_rgba_f32()).truncate(),
+            density: volume.density,
+            anisotropy: volume.anisotropy.clamp(-0.99, 0.99),
+            shape: match volume.shape {
+                FogVolumeShape::Global => 0,
+                FogVolumeShape::Box => 1,
+            },
+            light_groups: link.copied().unwrap_or_default().groups,
+        })
+        .collect();
+}
+
+fn prepare_fog_volumes(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    extracted: Res<ExtractedFogVolumes>,
+    mut render_assets: ResMut<FogVolumeRenderAssets>,
+) {
+    let buffer = render_assets.fog_volume_buffer.get_mut();
+    buffer.count = extracted.0.len() as u32;
+    buffer.data = extracted.0.clone();
+
+    render_assets
+        .fog_volume_buffer
+        .write_buffer(&render_device, &render_queue);
+}
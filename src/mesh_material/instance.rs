@@ -1,6 +1,8 @@
 use super::{
-    material::GpuStandardMaterials, mesh::GpuMeshes, GpuAliasEntry, GpuAliasTableBuffer,
-    GpuEmissive, GpuEmissiveBuffer, GpuMesh, GpuStandardMaterial, MeshMaterialSystems,
+    material::{EmissiveTextures, GpuStandardMaterials, MaterialTextures},
+    mesh::GpuMeshes,
+    GpuAliasEntry, GpuAliasTableBuffer, GpuEmissive, GpuEmissiveBuffer, GpuMesh,
+    GpuStandardMaterial, MeshMaterialSystems,
 };
 use crate::{
     mesh_material::{GpuInstance, GpuInstanceBuffer, GpuNode, GpuNodeBuffer},
@@ -21,6 +23,7 @@ use bevy::{
         Extract, RenderApp, RenderStage,
     },
     transform::TransformSystem,
+    utils::HashSet,
 };
 use bvh::bvh::BVH;
 use itertools::Itertools;
@@ -29,7 +32,9 @@ use std::{collections::BTreeMap, marker::PhantomData};
 pub struct InstancePlugin;
 impl Plugin for InstancePlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugin(ExtractComponentPlugin::<PreviousMeshUniform>::default())
+        app.register_type::<HikariLightLink>()
+            .register_type::<HikariSubsurface>()
+            .add_plugin(ExtractComponentPlugin::<PreviousMeshUniform>::default())
             .add_plugin(UniformComponentPlugin::<PreviousMeshUniform>::default());
 
         if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
@@ -108,6 +113,54 @@ impl InstanceRenderAssets {
     }
 }
 
+/// Restricts which lights affect which instances.
+///
+/// On an instance or a fog volume, `groups` are the light groups it belongs to as a receiver.
+/// On an emissive instance or an area light, the light affects a receiver only if the receiver
+/// is in any of the `include` groups and in none of the `exclude` groups.
+#[derive(Debug, Clone, Copy, Component, Reflect)]
+#[reflect(Component)]
+pub struct HikariLightLink {
+    /// Bitmask of light groups this entity receives light as.
+    pub groups: u32,
+    /// Bitmask of receiver groups this light affects.
+    pub include: u32,
+    /// Bitmask of receiver groups this light never affects.
+    pub exclude: u32,
+}
+
+impl Default for HikariLightLink {
+    fn default() -> Self {
+        Self {
+            groups: 1,
+            include: u32::MAX,
+            exclude: 0,
+        }
+    }
+}
+
+/// Marks an instance for approximate subsurface scattering, e.g., for skin or wax.
+///
+/// Diffuse light at a point is gathered from around it, following a diffusion profile.
+/// Only instances with this component pay for the extra rays.
+#[derive(Debug, Clone, Copy, Component, Reflect)]
+#[reflect(Component)]
+pub struct HikariSubsurface {
+    /// Relative distance light of each color channel travels beneath the surface.
+    pub color: Color,
+    /// Scatter distance in world units, scaled by `color` per channel.
+    pub radius: f32,
+}
+
+impl Default for HikariSubsurface {
+    fn default() -> Self {
+        Self {
+            color: Color::rgb(1.0, 0.35, 0.2),
+            radius: 0.05,
+        }
+    }
+}
+
 #[derive(Default, Component, Clone, ShaderType)]
 pub struct PreviousMeshUniform {
     pub transform: Mat4,
@@ -149,9 +202,21 @@ fn instance_event_system<M: Into<StandardMaterial> + Asset>(
                 Changed<Handle<Mesh>>,
                 Changed<Handle<M>>,
                 Changed<ComputedVisibility>,
+                Changed<HikariLightLink>,
+                Changed<HikariSubsurface>,
             )>,
         >,
+        Query<(Entity, &Handle<Mesh>, &Handle<M>, &ComputedVisibility)>,
+        Query<
+            Entity,
+            (
+                With<Handle<Mesh>>,
+                With<Handle<M>>,
+                Changed<GlobalTransform>,
+            ),
+        >,
     )>,
+    mut moving: Local<HashSet<Entity>>,
 ) {
     for entity in removed.iter() {
         events.send(InstanceEvent::Removed(entity));
@@ -172,6 +237,19 @@ fn instance_event_system<M: Into<StandardMaterial> + Asset>(
             visibility.clone(),
         ));
     }
+    // An instance that just stopped moving still has a stale previous transform.
+    let moved: HashSet<Entity> = set.p3().iter().collect();
+    for entity in moving.difference(&moved) {
+        if let Ok((entity, mesh, material, visibility)) = set.p2().get(*entity) {
+            events.send(InstanceEvent::Modified(
+                entity,
+                mesh.clone_weak(),
+                material.clone_weak(),
+                visibility.clone(),
+            ));
+        }
+    }
+    *moving = moved;
 }
 
 #[allow(clippy::type_complexity)]
@@ -181,16 +259,27 @@ pub struct ExtractedInstances {
         Entity,
         Aabb,
         GlobalTransform,
+        Mat4,
         Handle<Mesh>,
         HandleUntyped,
         ComputedVisibility,
+        HikariLightLink,
+        Option<HikariSubsurface>,
     )>,
     removed: Vec<Entity>,
 }
 
 fn extract_instances<M: Into<StandardMaterial> + Asset>(
     mut events: Extract<EventReader<InstanceEvent<M>>>,
-    query: Extract<Query<(&Aabb, &GlobalTransform)>>,
+    query: Extract<
+        Query<(
+            &Aabb,
+            &GlobalTransform,
+            Option<&GlobalTransformQueue>,
+            Option<&HikariLightLink>,
+            Option<&HikariSubsurface>,
+        )>,
+    >,
     mut extracted_instances: ResMut<ExtractedInstances>,
 ) {
     let mut extracted = vec![];
@@ -200,14 +289,19 @@ fn extract_instances<M: Into<StandardMaterial> + Asset>(
         match event {
             InstanceEvent::Created(entity, mesh, material, visibility)
             | InstanceEvent::Modified(entity, mesh, material, visibility) => {
-                if let Ok((aabb, transform)) = query.get(*entity) {
+                if let Ok((aabb, transform, queue, link, subsurface)) = query.get(*entity) {
+                    let previous_transform =
+                        queue.map_or_else(|| transform.compute_matrix(), |queue| queue[1]);
                     extracted.push((
                         *entity,
                         aabb.clone(),
                         *transform,
+                        previous_transform,
                         mesh.clone_weak(),
                         material.clone_weak_untyped(),
                         visibility.clone(),
+                        link.copied().unwrap_or_default(),
+                        subsurface.copied(),
                     ));
                 }
             }
@@ -252,6 +346,8 @@ fn prepare_instances(
     mut alias_table_cache: Local<AlisaTableCache>,
     meshes: Res<GpuMeshes>,
     materials: Res<GpuStandardMaterials>,
+    textures: Res<MaterialTextures>,
+    emissive_textures: Res<EmissiveTextures>,
     universal_settings: Res<HikariUniversalSettings>,
 ) {
     if !universal_settings.build_instance_acceleration_structure {
@@ -268,41 +364,44 @@ fn prepare_instances(
 
     let mut prepare_next_frame = vec![];
 
-    for (entity, aabb, transform, mesh, material, visibility) in extracted_instances
-        .extracted
-        .drain(..)
-        .filter_map(|(entity, aabb, transform, mesh, material, visibility)| {
-            match (meshes.get(&mesh), materials.get(&material)) {
-                (Some(mesh), Some(material)) => {
-                    Some((entity, aabb, transform, mesh, material, visibility))
-                }
-                _ => {
-                    prepare_next_frame.push((entity, aabb, transform, mesh, material, visibility));
-                    None
-                }
+    for extracted in extracted_instances.extracted.drain(..) {
+        let (mesh, material) = match (meshes.get(&extracted.4), materials.get(&extracted.5)) {
+            (Some(mesh), Some(material)) => (mesh, material),
+            _ => {
+                prepare_next_frame.push(extracted);
+                continue;
             }
-        })
-    {
-        let transform = transform.compute_matrix();
-        let center = transform.transform_point3a(aabb.center);
-        let vertices: Vec<_> = (0..8i32)
-            .map(|index| {
-                let x = 2 * (index & 1) - 1;
-                let y = 2 * ((index >> 1) & 1) - 1;
-                let z = 2 * ((index >> 2) & 1) - 1;
-                let vertex = aabb.half_extents * Vec3A::new(x as f32, y as f32, z as f32);
-                transform.transform_vector3a(vertex)
-            })
-            .collect();
+        };
+        let (entity, aabb, transform, previous_transform, _, _, visibility, link, subsurface) =
+            extracted;
+
+        let bounds = |transform: Mat4| {
+            let center = transform.transform_point3a(aabb.center);
+            let vertices: Vec<_> = (0..8i32)
+                .map(|index| {
+                    let x = 2 * (index & 1) - 1;
+                    let y = 2 * ((index >> 1) & 1) - 1;
+                    let z = 2 * ((index >> 2) & 1) - 1;
+                    let vertex = aabb.half_extents * Vec3A::new(x as f32, y as f32, z as f32);
+                    transform.transform_vector3a(vertex)
+                })
+                .collect();
+
+            let mut min = Vec3A::ZERO;
+            let mut max = Vec3A::ZERO;
+            for vertex in vertices {
+                min = min.min(vertex);
+                max = max.max(vertex);
+            }
+            (min + center, max + center)
+        };
 
-        let mut min = Vec3A::ZERO;
-        let mut max = Vec3A::ZERO;
-        for vertex in vertices {
-            min = min.min(vertex);
-            max = max.max(vertex);
-        }
-        min += center;
-        max += center;
+        // The bounds enclose the whole motion of the frame, for time sampled rays.
+        let transform = transform.compute_matrix();
+        let (min, max) = bounds(transform);
+        let (previous_min, previous_max) = bounds(previous_transform);
+        let min = min.min(previous_min);
+        let max = max.max(previous_max);
 
         // Note that the `GpuInstance` is partially constructed:
         // since node index is unknown at this point.
@@ -316,8 +415,16 @@ fn prepare_instances(
                     max,
                     transform,
                     inverse_transpose_model: transform.inverse().transpose(),
+                    previous_model: previous_transform,
                     mesh: mesh.1,
                     material: material.1,
+                    light_groups: link.groups,
+                    light_include: link.include,
+                    light_exclude: link.exclude,
+                    subsurface: subsurface.map_or(Vec4::ZERO, |subsurface| {
+                        let color = Vec4::from(subsurface.color.as_linear_rgba_f32());
+                        color.truncate().extend(subsurface.radius.max(0.0))
+                    }),
                     ..Default::default()
                 },
                 mesh.0.clone(),
@@ -349,7 +456,17 @@ fn prepare_instances(
         commands.insert_or_spawn_batch(command_batch);
     };
 
-    if instance_changed || meshes.is_changed() || materials.is_changed() {
+    if materials.is_changed() || emissive_textures.is_changed() {
+        // Alias tables depend on emissive textures of materials.
+        alias_table_cache.clear();
+    }
+
+    if instance_changed
+        || meshes.is_changed()
+        || materials.is_changed()
+        || emissive_textures.is_changed()
+        || universal_settings.is_changed()
+    {
         // Important: update mesh and material info for every instance
         let mut emissives = vec![];
         let mut alias_table = vec![];
@@ -379,8 +496,10 @@ fn prepare_instances(
 
         for (id, (entity, (instance, mesh, material, _))) in collection.iter().enumerate() {
             let emissive = material.emissive;
-            let intensity = 255.0 * emissive.w * emissive.xyz().length();
-            if intensity > 0.0 {
+            let luminance = emissive.w * emissive.xyz().dot(Vec3::new(0.2126, 0.7152, 0.0722));
+            // Unlit surfaces emit their base color, which is gathered where paths hit them instead.
+            let unlit = material.flags & GpuStandardMaterial::UNLIT != 0;
+            if luminance > 0.0 && !unlit {
                 // Compute alias table for light sampling
                 let instance_scale = instance.transform.to_scale_rotation_translation().0;
                 let alias_table = {
@@ -388,25 +507,48 @@ fn prepare_instances(
                         scale.abs_diff_eq(instance_scale, 0.01).then_some(table)
                     });
                     let cache_hit = cached_table.is_some();
-                    let mut instance_table = cached_table
-                        .map_or_else(|| mesh.build_alias_table(instance.transform), Clone::clone);
+                    let mut instance_table = cached_table.map_or_else(
+                        || {
+                            let emissive_texture = textures
+                                .data
+                                .get(material.emissive_texture as usize)
+                                .and_then(|handle| emissive_textures.get(handle));
+                            mesh.build_alias_table(
+                                instance.transform,
+                                emissive_texture,
+                                material.uv_transform,
+                            )
+                        },
+                        Clone::clone,
+                    );
                     if !cache_hit {
                         alias_table_cache.insert(*entity, (instance_scale, instance_table.clone()));
                     }
 
+                    if instance_table.is_empty() {
+                        // No primitive of the instance actually emits light.
+                        continue;
+                    }
+
                     let index = UVec2::new(alias_table.len() as u32, instance_table.len() as u32);
                     alias_table.append(&mut instance_table);
                     index
                 };
 
-                let surface_area = mesh
+                let surface_area: f32 = mesh
                     .transformed_primitive_areas(instance.transform)
                     .iter()
                     .sum();
 
+                // The range is where the illuminance from the emitter, approximated by
+                // `luminance * area / distance^2`, falls below the threshold.
+                let intensity: f32 = luminance * surface_area;
+                let threshold = universal_settings.emissive_threshold.max(f32::EPSILON);
+                let range = (intensity / threshold).sqrt();
+
                 // Add to emissive list.
                 let position = 0.5 * (instance.max + instance.min);
-                let radius = 0.5 * (instance.max - instance.min).length() + intensity.sqrt();
+                let radius = 0.5 * (instance.max - instance.min).length() + range;
                 emissives.push(GpuEmissive {
                     emissive,
                     position,
@@ -414,6 +556,8 @@ fn prepare_instances(
                     instance: id as u32,
                     alias_table,
                     surface_area,
+                    light_include: instance.light_include,
+                    light_exclude: instance.light_exclude,
                     node_index: 0,
                 });
             }
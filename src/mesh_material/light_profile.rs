@@ -0,0 +1,323 @@
+use bevy::{
+    asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    render::render_resource::ShaderType,
+};
+use std::{error::Error, fmt::Display};
+
+/// Number of vertical angles in the resampled intensity table, covering 0 - 180 degrees.
+pub const IES_VERTICAL_RESOLUTION: usize = 32;
+/// Number of horizontal angles in the resampled intensity table, covering 0 - 360 degrees.
+pub const IES_HORIZONTAL_RESOLUTION: usize = 16;
+/// Largest count of angles accepted in a file, well above the 0.1 degree steps of the finest profiles.
+const IES_MAX_ANGLE_COUNT: usize = 4096;
+
+pub struct LightProfilePlugin;
+impl Plugin for LightProfilePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<IesProfile>().init_asset_loader::<IesLoader>();
+    }
+}
+
+/// Modulates the radiance of an area light on the same entity.
+///
+/// Only area lights are supported, since the directional light is the only punctual light traced.
+#[derive(Debug, Clone, Component)]
+pub enum HikariLightProfile {
+    /// Photometric distribution of a fixture, loaded from an IES (LM-63) file.
+    /// Vertical angle 0 points along the emitting direction (local -Z) of the light,
+    /// and horizontal angle 0 along its local X axis.
+    Ies(Handle<IesProfile>),
+    /// Image projected along the emitting direction (local -Z) of the light.
+    Cookie {
+        image: Handle<Image>,
+        /// Full projection angle in radians.
+        fov: f32,
+    },
+}
+
+/// Candela distribution of a light fixture, resampled onto a regular grid and
+/// normalized by its peak intensity.
+#[derive(Debug, Clone, TypeUuid)]
+#[uuid = "4ad1c5a4-8d0e-4d55-b4b1-6f6a1e6b2c91"]
+pub struct IesProfile {
+    /// Relative intensities, stored horizontal angle major.
+    pub table: Vec<f32>,
+}
+
+impl IesProfile {
+    /// Parses the text of an LM-63 file.
+    pub fn parse(text: &str) -> Result<Self, IesError> {
+        let mut lines = text.lines();
+        lines
+            .by_ref()
+            .find(|line| line.trim_start().starts_with("TILT="))
+            .ok_or(IesError::MissingTilt)
+            .and_then(|line| match line.trim().trim_start_matches("TILT=") {
+                "NONE" | "INCLUDE" => Ok(line.contains("INCLUDE")),
+                _ => Err(IesError::UnsupportedTilt),
+            })
+            .and_then(|include_tilt| {
+                let mut values = lines
+                    .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+                    .filter(|token| !token.is_empty())
+                    .map(|token| token.parse::<f32>().map_err(|_| IesError::InvalidNumber));
+
+                let mut next = || values.next().unwrap_or(Err(IesError::UnexpectedEof));
+
+                if include_tilt {
+                    // Lamp to luminaire geometry, followed by pairs of angles and factors.
+                    next()?;
+                    let count = next()? as usize;
+                    if count > IES_MAX_ANGLE_COUNT {
+                        return Err(IesError::TooManyAngles);
+                    }
+                    for _ in 0..count.checked_mul(2).ok_or(IesError::TooManyAngles)? {
+                        next()?;
+                    }
+                }
+
+                let _lamp_count = next()?;
+                let _lumens_per_lamp = next()?;
+                let _multiplier = next()?;
+                let vertical_count = next()? as usize;
+                let horizontal_count = next()? as usize;
+                let photometric_type = next()? as u32;
+                let _units = next()?;
+                let _width = next()?;
+                let _length = next()?;
+                let _height = next()?;
+                let _ballast_factor = next()?;
+                let _future_use = next()?;
+                let _input_watts = next()?;
+
+                if photometric_type != 1 {
+                    return Err(IesError::UnsupportedPhotometricType);
+                }
+                if vertical_count == 0 || horizontal_count == 0 {
+                    return Err(IesError::NoCandela);
+                }
+                if vertical_count > IES_MAX_ANGLE_COUNT || horizontal_count > IES_MAX_ANGLE_COUNT {
+                    return Err(IesError::TooManyAngles);
+                }
+                let candela_count = vertical_count
+                    .checked_mul(horizontal_count)
+                    .ok_or(IesError::TooManyAngles)?;
+
+                let vertical_angles = (0..vertical_count)
+                    .map(|_| next())
+                    .collect::<Result<Vec<_>, _>>()?;
+                let horizontal_angles = (0..horizontal_count)
+                    .map(|_| next())
+                    .collect::<Result<Vec<_>, _>>()?;
+                let candela = (0..candela_count)
+                    .map(|_| next())
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(Self::resample(&vertical_angles, &horizontal_angles, &candela))
+            })
+    }
+
+    fn resample(vertical_angles: &[f32], horizontal_angles: &[f32], candela: &[f32]) -> Self {
+        let vertical_count = vertical_angles.len();
+        let max_horizontal = horizontal_angles.last().copied().unwrap_or(0.0);
+
+        // Linear interpolation weights of `value` within the sorted `angles`.
+        let locate = |angles: &[f32], value: f32| -> Option<(usize, usize, f32)> {
+            let last = angles.len() - 1;
+            if value < angles[0] || value > angles[last] {
+                return None;
+            }
+            let upper = angles.partition_point(|angle| *angle < value).min(last);
+            let lower = upper.saturating_sub(1);
+            let span = angles[upper] - angles[lower];
+            let t = if span > 0.0 {
+                (value - angles[lower]) / span
+            } else {
+                0.0
+            };
+            Some((lower, upper, t))
+        };
+
+        let lookup = |vertical: usize, horizontal: usize| {
+            candela[horizontal * vertical_count + vertical]
+        };
+
+        let mut table = Vec::with_capacity(IES_VERTICAL_RESOLUTION * IES_HORIZONTAL_RESOLUTION);
+        for horizontal in 0..IES_HORIZONTAL_RESOLUTION {
+            // Fold the horizontal angle according to the symmetry of the profile.
+            let mut phi = 360.0 * horizontal as f32 / IES_HORIZONTAL_RESOLUTION as f32;
+            if max_horizontal <= 180.0 && phi > 180.0 {
+                phi = 360.0 - phi;
+            }
+            if max_horizontal <= 90.0 && phi > 90.0 {
+                phi = 180.0 - phi;
+            }
+            if max_horizontal <= 0.0 {
+                phi = 0.0;
+            }
+
+            for vertical in 0..IES_VERTICAL_RESOLUTION {
+                let theta = 180.0 * vertical as f32 / (IES_VERTICAL_RESOLUTION - 1) as f32;
+
+                let vertical_weights = locate(vertical_angles, theta);
+                let horizontal_weights = locate(horizontal_angles, phi);
+                let value = match (vertical_weights, horizontal_weights) {
+                    (Some((v0, v1, s)), Some((h0, h1, t))) => {
+                        let c0 = lookup(v0, h0) + s * (lookup(v1, h0) - lookup(v0, h0));
+                        let c1 = lookup(v0, h1) + s * (lookup(v1, h1) - lookup(v0, h1));
+                        c0 + t * (c1 - c0)
+                    }
+                    _ => 0.0,
+                };
+                table.push(value.max(0.0));
+            }
+        }
+
+        let peak = table.iter().copied().fold(0.0, f32::max);
+        if peak > 0.0 {
+            table.iter_mut().for_each(|value| *value /= peak);
+        }
+
+        Self { table }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IesError {
+    MissingTilt,
+    UnsupportedTilt,
+    UnsupportedPhotometricType,
+    InvalidNumber,
+    UnexpectedEof,
+    NoCandela,
+    TooManyAngles,
+}
+
+impl Display for IesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid IES profile: {self:?}")
+    }
+}
+
+impl Error for IesError {}
+
+#[derive(Default)]
+pub struct IesLoader;
+
+impl AssetLoader for IesLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let text = String::from_utf8_lossy(bytes);
+            let profile = IesProfile::parse(&text)?;
+            load_context.set_default_asset(LoadedAsset::new(profile));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ies"]
+    }
+}
+
+#[derive(Default, ShaderType)]
+pub struct GpuLightProfileBuffer {
+    pub count: u32,
+    #[size(runtime)]
+    pub data: Vec<f32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A type C profile symmetric about the 0 - 180 degree plane, brighter towards 90 degrees horizontally.
+    const HALF_SYMMETRIC: &str = "IESNA:LM-63-2002
+[TEST] Made up
+TILT=NONE
+1 1000 1 3 3 1 1 0 0 0
+1 1 100
+0 90 180
+0 90 180
+100 50 0
+200 100 0
+100 50 0
+";
+
+    fn intensity(profile: &IesProfile, vertical: usize, horizontal: usize) -> f32 {
+        profile.table[horizontal * IES_VERTICAL_RESOLUTION + vertical]
+    }
+
+    #[test]
+    fn fold_symmetric_horizontal_angles() {
+        let profile = IesProfile::parse(HALF_SYMMETRIC).unwrap();
+        assert_eq!(
+            profile.table.len(),
+            IES_VERTICAL_RESOLUTION * IES_HORIZONTAL_RESOLUTION
+        );
+
+        // Normalized by the peak, at 0 degrees vertically and 90 degrees horizontally
+        let quarter = IES_HORIZONTAL_RESOLUTION / 4;
+        assert_eq!(intensity(&profile, 0, quarter), 1.0);
+        assert_eq!(intensity(&profile, 0, 0), 0.5);
+        assert_eq!(intensity(&profile, IES_VERTICAL_RESOLUTION - 1, 0), 0.0);
+
+        // Angles past 180 degrees mirror those below
+        for horizontal in 1..IES_HORIZONTAL_RESOLUTION / 2 {
+            let mirrored = IES_HORIZONTAL_RESOLUTION - horizontal;
+            for vertical in 0..IES_VERTICAL_RESOLUTION {
+                assert_eq!(
+                    intensity(&profile, vertical, horizontal),
+                    intensity(&profile, vertical, mirrored)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn skip_included_tilt() {
+        let text =
+            HALF_SYMMETRIC.replace("TILT=NONE\n", "TILT=INCLUDE\n1\n3\n0 45 90\n1.0 0.9 0.8\n");
+        let tilted = IesProfile::parse(&text).unwrap();
+        let profile = IesProfile::parse(HALF_SYMMETRIC).unwrap();
+        assert_eq!(tilted.table, profile.table);
+    }
+
+    #[test]
+    fn reject_truncated() {
+        let text = HALF_SYMMETRIC.trim_end().trim_end_matches("100 50 0");
+        assert_eq!(
+            IesProfile::parse(text).unwrap_err(),
+            IesError::UnexpectedEof
+        );
+
+        let header = HALF_SYMMETRIC.split("1 1 100").next().unwrap();
+        assert_eq!(
+            IesProfile::parse(header).unwrap_err(),
+            IesError::UnexpectedEof
+        );
+
+        let text = HALF_SYMMETRIC.replace("TILT=NONE", "");
+        assert_eq!(IesProfile::parse(&text).unwrap_err(), IesError::MissingTilt);
+    }
+
+    #[test]
+    fn reject_oversized_counts() {
+        let text = HALF_SYMMETRIC.replace("1 1000 1 3 3", "1 1000 1 1e30 1e30");
+        assert_eq!(
+            IesProfile::parse(&text).unwrap_err(),
+            IesError::TooManyAngles
+        );
+
+        let text = HALF_SYMMETRIC.replace("TILT=NONE", "TILT=INCLUDE\n1\n1e30");
+        assert_eq!(
+            IesProfile::parse(&text).unwrap_err(),
+            IesError::TooManyAngles
+        );
+    }
+}
@@ -1,7 +1,9 @@
 use super::{GpuStandardMaterial, GpuStandardMaterialBuffer, MeshMaterialSystems};
 use bevy::{
     asset::{Asset, HandleId},
+    math::Affine2,
     prelude::*,
+    reflect::TypeUuid,
     render::{
         render_resource::*,
         renderer::{RenderDevice, RenderQueue},
@@ -14,12 +16,17 @@ use std::{collections::BTreeMap, marker::PhantomData};
 pub struct MaterialPlugin;
 impl Plugin for MaterialPlugin {
     fn build(&self, app: &mut App) {
+        app.add_asset::<HikariMaterial>();
+
         if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
             render_app
                 .init_resource::<ExtractedMaterials>()
                 .init_resource::<MaterialRenderAssets>()
                 .init_resource::<MaterialTextures>()
+                .init_resource::<EmissiveTextures>()
                 .init_resource::<GpuStandardMaterials>()
+                .add_system_to_stage(RenderStage::Extract, extract_emissive_textures)
+                .add_system_to_stage(RenderStage::Extract, extract_hikari_material_assets)
                 .add_system_to_stage(
                     RenderStage::Prepare,
                     prepare_material_textures
@@ -48,6 +55,68 @@ where
     }
 }
 
+/// A [`StandardMaterial`] with the additional lobes of `KHR_materials_clearcoat`,
+/// `KHR_materials_sheen` and `KHR_materials_anisotropy`, and the emissive strength of
+/// `KHR_materials_emissive_strength`.
+///
+/// Since meshes carry no tangents, the anisotropy direction is relative to a tangent frame derived from the normal.
+#[derive(Debug, Clone, TypeUuid)]
+#[uuid = "1e03ceda-72fb-4c15-93e5-a5be1f87435a"]
+pub struct HikariMaterial {
+    pub base: StandardMaterial,
+    /// Multiplier of the linear emissive color of `base`. The alpha channel of the emissive color is ignored.
+    pub emissive_strength: f32,
+    /// Strength of the clearcoat layer, from 0.0 to 1.0.
+    pub clearcoat: f32,
+    pub clearcoat_perceptual_roughness: f32,
+    /// Color of the sheen layer. Black disables the layer.
+    pub sheen_color: Color,
+    pub sheen_perceptual_roughness: f32,
+    /// Stretch of the specular highlight along the anisotropy direction, from 0.0 to 1.0.
+    pub anisotropy_strength: f32,
+    /// Rotation of the anisotropy direction around the normal, in radians.
+    pub anisotropy_rotation: f32,
+    /// Transform applied to UVs before any texture lookup, e.g., for tiling or atlasing.
+    /// Tiling needs textures with a repeating sampler.
+    pub uv_transform: Affine2,
+}
+
+impl HikariMaterial {
+    /// Builds a UV transform from the parameters of `KHR_texture_transform`.
+    pub fn texture_transform(offset: Vec2, rotation: f32, scale: Vec2) -> Affine2 {
+        // The extension rotates UVs clockwise for positive angles
+        Affine2::from_scale_angle_translation(scale, -rotation, offset)
+    }
+}
+
+impl Default for HikariMaterial {
+    fn default() -> Self {
+        StandardMaterial::default().into()
+    }
+}
+
+impl From<StandardMaterial> for HikariMaterial {
+    fn from(base: StandardMaterial) -> Self {
+        Self {
+            base,
+            emissive_strength: 1.0,
+            clearcoat: 0.0,
+            clearcoat_perceptual_roughness: 0.0,
+            sheen_color: Color::BLACK,
+            sheen_perceptual_roughness: 0.0,
+            anisotropy_strength: 0.0,
+            anisotropy_rotation: 0.0,
+            uv_transform: Affine2::IDENTITY,
+        }
+    }
+}
+
+impl From<HikariMaterial> for StandardMaterial {
+    fn from(material: HikariMaterial) -> Self {
+        material.base
+    }
+}
+
 #[derive(Default, Resource, Deref, DerefMut)]
 pub struct MaterialRenderAssets(pub StorageBuffer<GpuStandardMaterialBuffer>);
 
@@ -55,6 +124,8 @@ pub struct MaterialRenderAssets(pub StorageBuffer<GpuStandardMaterialBuffer>);
 pub struct MaterialTextures {
     pub data: Vec<Handle<Image>>,
     pub index: HashMap<Handle<Image>, usize>,
+    /// Textures that are used as emissive textures by any material.
+    pub emissive: HashSet<Handle<Image>>,
 }
 
 impl MaterialTextures {
@@ -73,6 +144,21 @@ impl MaterialTextures {
         add_texture!(metallic_roughness_texture);
         add_texture!(normal_map_texture);
         add_texture!(occlusion_texture);
+
+        if let Some(texture) = &material.emissive_texture {
+            self.emissive.insert(texture.clone_weak());
+        }
+    }
+
+    /// Adds a texture that is not referenced by any material, e.g., a light cookie.
+    pub fn add_texture(&mut self, handle: &Handle<Image>) -> u32 {
+        if let Some(index) = self.index.get(handle) {
+            return *index as u32;
+        }
+        let index = self.data.len();
+        self.index.insert(handle.clone_weak(), index);
+        self.data.push(handle.clone_weak());
+        index as u32
     }
 
     pub fn id(&self, maybe_handle: &Option<Handle<Image>>) -> u32 {
@@ -86,19 +172,136 @@ impl MaterialTextures {
     }
 }
 
+/// Luminance of an emissive texture, kept on CPU for building light sampling tables.
+#[derive(Debug, Default, Clone)]
+pub struct EmissiveTexture {
+    pub size: UVec2,
+    pub luminance: Vec<f32>,
+}
+
+impl EmissiveTexture {
+    /// Max number of subdivisions along a primitive edge when averaging luminance.
+    pub const MAX_SUBDIVISIONS: u32 = 8;
+
+    /// Returns `None` if the image format is not supported.
+    pub fn from_image(image: &Image) -> Option<Self> {
+        let size = image.size().as_uvec2();
+        let texel_count = (size.x * size.y) as usize;
+        if texel_count == 0 {
+            return None;
+        }
+
+        let luminance = |[r, g, b, a]: [f32; 4]| (0.2126 * r + 0.7152 * g + 0.0722 * b) * a;
+        let srgb_to_linear = |value: f32| {
+            if value <= 0.04045 {
+                value / 12.92
+            } else {
+                ((value + 0.055) / 1.055).powf(2.4)
+            }
+        };
+
+        let luminance: Vec<_> = match image.texture_descriptor.format {
+            TextureFormat::Rgba8Unorm => image
+                .data
+                .chunks_exact(4)
+                .take(texel_count)
+                .map(|texel| luminance([0, 1, 2, 3].map(|id| texel[id] as f32 / 255.0)))
+                .collect(),
+            TextureFormat::Rgba8UnormSrgb => image
+                .data
+                .chunks_exact(4)
+                .take(texel_count)
+                .map(|texel| {
+                    let [r, g, b, a] = [0, 1, 2, 3].map(|id| texel[id] as f32 / 255.0);
+                    luminance([srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a])
+                })
+                .collect(),
+            TextureFormat::Rgba32Float => image
+                .data
+                .chunks_exact(16)
+                .take(texel_count)
+                .map(|texel| {
+                    luminance([0, 1, 2, 3].map(|id| {
+                        let bytes = [0, 1, 2, 3].map(|byte| texel[4 * id + byte]);
+                        f32::from_le_bytes(bytes)
+                    }))
+                })
+                .collect(),
+            _ => return None,
+        };
+
+        (luminance.len() == texel_count).then_some(Self { size, luminance })
+    }
+
+    /// Nearest sampling with repeat addressing.
+    pub fn sample(&self, uv: Vec2) -> f32 {
+        let uv = uv - uv.floor();
+        let coords = (uv * self.size.as_vec2()).as_uvec2().min(self.size - 1);
+        self.luminance[(coords.x + coords.y * self.size.x) as usize]
+    }
+
+    /// Average luminance over a primitive given the texture coordinates of its vertices.
+    pub fn average_luminance(&self, uvs: [Vec2; 3]) -> f32 {
+        // Subdivide the primitive so that each piece covers roughly one texel,
+        // and sample at the centroids of the pieces.
+        let [p0, p1, p2] = uvs.map(|uv| uv * self.size.as_vec2());
+        let edge = (p1 - p0).length().max((p2 - p0).length()).max((p2 - p1).length());
+        let n = (edge.ceil() as u32).clamp(1, Self::MAX_SUBDIVISIONS);
+
+        let sample = |x: f32, y: f32| {
+            let (u, v) = (x / n as f32, y / n as f32);
+            self.sample(uvs[0] + u * (uvs[1] - uvs[0]) + v * (uvs[2] - uvs[0]))
+        };
+
+        let mut sum = 0.0;
+        for i in 0..n {
+            for j in 0..n - i {
+                sum += sample(i as f32 + 1.0 / 3.0, j as f32 + 1.0 / 3.0);
+                if i + j + 1 < n {
+                    sum += sample(i as f32 + 2.0 / 3.0, j as f32 + 2.0 / 3.0);
+                }
+            }
+        }
+        sum / (n * n) as f32
+    }
+}
+
+#[derive(Default, Resource, Deref, DerefMut)]
+pub struct EmissiveTextures(HashMap<Handle<Image>, EmissiveTexture>);
+
 #[derive(Default, Resource, Deref, DerefMut)]
 pub struct GpuStandardMaterials(HashMap<HandleUntyped, (GpuStandardMaterial, u32)>);
 
 #[derive(Default, Resource)]
 pub struct ExtractedMaterials {
-    extracted: Vec<(HandleUntyped, StandardMaterial)>,
+    extracted: Vec<(HandleUntyped, HikariMaterial)>,
     removed: Vec<HandleUntyped>,
 }
 
 fn extract_material_assets<M: Into<StandardMaterial> + Clone + Asset>(
+    events: Extract<EventReader<AssetEvent<M>>>,
+    assets: Extract<Res<Assets<M>>>,
+    extracted_assets: ResMut<ExtractedMaterials>,
+) {
+    extract_changed_assets(events, assets, extracted_assets, |material| {
+        let material: StandardMaterial = material.into();
+        material.into()
+    });
+}
+
+fn extract_hikari_material_assets(
+    events: Extract<EventReader<AssetEvent<HikariMaterial>>>,
+    assets: Extract<Res<Assets<HikariMaterial>>>,
+    extracted_assets: ResMut<ExtractedMaterials>,
+) {
+    extract_changed_assets(events, assets, extracted_assets, |material| material);
+}
+
+fn extract_changed_assets<M: Clone + Asset>(
     mut events: Extract<EventReader<AssetEvent<M>>>,
     assets: Extract<Res<Assets<M>>>,
     mut extracted_assets: ResMut<ExtractedMaterials>,
+    convert: impl Fn(M) -> HikariMaterial,
 ) {
     let mut changed_assets = HashSet::default();
     let mut removed = Vec::new();
@@ -118,7 +321,7 @@ fn extract_material_assets<M: Into<StandardMaterial> + Clone + Asset>(
     for handle in changed_assets.drain() {
         if let Some(material) = assets.get(handle) {
             let handle = handle.clone_weak_untyped();
-            let material = material.clone().into();
+            let material = convert(material.clone());
             extracted.push((handle, material));
         }
     }
@@ -127,18 +330,48 @@ fn extract_material_assets<M: Into<StandardMaterial> + Clone + Asset>(
     extracted_assets.removed.append(&mut removed);
 }
 
+fn extract_emissive_textures(
+    mut events: Extract<EventReader<AssetEvent<Image>>>,
+    images: Extract<Res<Assets<Image>>>,
+    textures: Res<MaterialTextures>,
+    mut emissive_textures: ResMut<EmissiveTextures>,
+) {
+    for event in events.iter() {
+        match event {
+            AssetEvent::Modified { handle } | AssetEvent::Removed { handle } => {
+                if emissive_textures.contains_key(handle) {
+                    emissive_textures.remove(handle);
+                }
+            }
+            AssetEvent::Created { .. } => {}
+        }
+    }
+
+    let pending: Vec<_> = textures
+        .emissive
+        .iter()
+        .filter(|handle| !emissive_textures.contains_key(*handle))
+        .cloned()
+        .collect();
+    for handle in pending {
+        if let Some(texture) = images.get(&handle).and_then(EmissiveTexture::from_image) {
+            emissive_textures.insert(handle, texture);
+        }
+    }
+}
+
 fn prepare_material_textures(
     extracted_assets: Res<ExtractedMaterials>,
     mut textures: ResMut<MaterialTextures>,
 ) {
     for (_, material) in &extracted_assets.extracted {
-        textures.add_standard_material_textures(material);
+        textures.add_standard_material_textures(&material.base);
     }
 }
 
 fn prepare_material_assets(
     mut extracted_assets: ResMut<ExtractedMaterials>,
-    mut assets: Local<BTreeMap<HandleId, StandardMaterial>>,
+    mut assets: Local<BTreeMap<HandleId, HikariMaterial>>,
     mut materials: ResMut<GpuStandardMaterials>,
     mut render_assets: ResMut<MaterialRenderAssets>,
     textures: Res<MaterialTextures>,
@@ -162,13 +395,17 @@ fn prepare_material_assets(
     let materials = assets
         .iter()
         .enumerate()
-        .map(|(offset, (handle, material))| {
+        .map(|(offset, (handle, extended))| {
             let handle = HandleUntyped::weak(*handle);
+            let material = &extended.base;
 
             let base_color = material.base_color.into();
             let base_color_texture = textures.id(&material.base_color_texture);
 
-            let emissive = material.emissive.into();
+            // Linear emissive color, with the emissive strength in the alpha channel.
+            let emissive = Vec4::from(material.emissive.as_linear_rgba_f32())
+                .truncate()
+                .extend(extended.emissive_strength.max(0.0));
             let emissive_texture = textures.id(&material.emissive_texture);
 
             let metallic_roughness_texture = textures.id(&material.metallic_roughness_texture);
@@ -181,6 +418,18 @@ fn prepare_material_assets(
                 material.reflectance,
             );
 
+            let mut flags = match material.cull_mode {
+                Some(Face::Back) => GpuStandardMaterial::CULL_BACK,
+                Some(Face::Front) => GpuStandardMaterial::CULL_FRONT,
+                None => 0,
+            };
+            if material.unlit {
+                flags |= GpuStandardMaterial::UNLIT;
+            }
+            if material.double_sided {
+                flags |= GpuStandardMaterial::DOUBLE_SIDED;
+            }
+
             let material = GpuStandardMaterial {
                 base_color,
                 base_color_texture,
@@ -192,6 +441,14 @@ fn prepare_material_assets(
                 reflectance,
                 normal_map_texture,
                 occlusion_texture,
+                clearcoat: extended.clearcoat.clamp(0.0, 1.0),
+                clearcoat_perceptual_roughness: extended.clearcoat_perceptual_roughness,
+                anisotropy_strength: extended.anisotropy_strength.clamp(0.0, 1.0),
+                anisotropy_rotation: extended.anisotropy_rotation,
+                sheen_color: Vec4::from(extended.sheen_color.as_linear_rgba_f32()).truncate(),
+                sheen_perceptual_roughness: extended.sheen_perceptual_roughness,
+                flags,
+                uv_transform: extended.uv_transform.into(),
             };
             materials.insert(handle, (material.clone(), offset as u32));
             material
@@ -201,3 +458,79 @@ fn prepare_material_assets(
     render_assets.get_mut().data = materials;
     render_assets.write_buffer(&render_device, &render_queue);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32, data: Vec<u8>, format: TextureFormat) -> Image {
+        let size = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        Image::new(size, TextureDimension::D2, data, format)
+    }
+
+    fn uniform(size: UVec2, luminance: f32) -> EmissiveTexture {
+        EmissiveTexture {
+            size,
+            luminance: vec![luminance; (size.x * size.y) as usize],
+        }
+    }
+
+    #[test]
+    fn decode_srgb_texels() {
+        let data = vec![
+            0, 0, 0, 255, 255, 255, 255, 255, 188, 188, 188, 255, 255, 255, 255, 0,
+        ];
+        let texture =
+            EmissiveTexture::from_image(&image(4, 1, data, TextureFormat::Rgba8UnormSrgb)).unwrap();
+        assert_eq!(texture.size, UVec2::new(4, 1));
+        assert_eq!(texture.luminance[0], 0.0);
+        assert!((texture.luminance[1] - 1.0).abs() < 1e-5);
+        // Mid gray in sRGB is about half the linear luminance of white
+        assert!((texture.luminance[2] - 0.5029).abs() < 1e-3);
+        // Transparent texels emit nothing
+        assert_eq!(texture.luminance[3], 0.0);
+
+        // The same bytes in a linear format are not decoded
+        let data = vec![188, 188, 188, 255];
+        let texture =
+            EmissiveTexture::from_image(&image(1, 1, data, TextureFormat::Rgba8Unorm)).unwrap();
+        assert!((texture.luminance[0] - 188.0 / 255.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn reject_unsupported_format() {
+        let texture = EmissiveTexture::from_image(&image(2, 2, vec![0; 4], TextureFormat::R8Unorm));
+        assert!(texture.is_none());
+    }
+
+    #[test]
+    fn average_uniform_luminance() {
+        let texture = uniform(UVec2::new(4, 4), 0.7);
+        let uvs = [Vec2::ZERO, Vec2::new(3.0, 0.5), Vec2::new(-1.0, 2.0)];
+        assert!((texture.average_luminance(uvs) - 0.7).abs() < 1e-5);
+    }
+
+    #[test]
+    fn average_over_sub_triangles() {
+        // Only the right half emits; a quarter of the triangle lies there
+        let texture = EmissiveTexture {
+            size: UVec2::new(2, 1),
+            luminance: vec![0.0, 1.0],
+        };
+        let uvs = [Vec2::ZERO, Vec2::X, Vec2::Y];
+        let average = texture.average_luminance(uvs);
+        assert!((average - 0.25).abs() < 0.05, "{average}");
+
+        // A triangle inside a single texel takes that texel's luminance
+        let uvs = [
+            Vec2::new(0.6, 0.1),
+            Vec2::new(0.9, 0.1),
+            Vec2::new(0.6, 0.9),
+        ];
+        assert_eq!(texture.average_luminance(uvs), 1.0);
+    }
+}
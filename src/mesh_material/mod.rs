@@ -1,6 +1,9 @@
 use self::{
+    area_light::{AreaLightPlugin, AreaLightRenderAssets, GpuAreaLightBuffer},
+    fog_volume::{FogVolumePlugin, FogVolumeRenderAssets, GpuFogVolumeBuffer},
     instance::InstancePlugin,
-    material::{MaterialPlugin, MaterialTextures},
+    light_profile::{GpuLightProfileBuffer, LightProfilePlugin},
+    material::{EmissiveTexture, MaterialPlugin, MaterialTextures},
     mesh::MeshPlugin,
 };
 use bevy::{
@@ -8,7 +11,7 @@ use bevy::{
     pbr::MeshPipeline,
     prelude::*,
     render::{
-        mesh::VertexAttributeValues,
+        mesh::{MeshVertexAttribute, VertexAttributeValues},
         render_asset::RenderAssets,
         render_phase::{EntityRenderCommand, RenderCommandResult, TrackedRenderPass},
         render_resource::*,
@@ -24,25 +27,40 @@ use bvh::{
 use itertools::Itertools;
 use std::num::NonZeroU32;
 
+pub mod area_light;
+pub mod fog_volume;
 pub mod instance;
+pub mod light_profile;
 pub mod material;
 pub mod mesh;
 
+pub use area_light::{HikariDiskLight, HikariRectLight, HikariSphereLight};
+pub use fog_volume::{FogVolumeShape, HikariFogVolume};
 pub use instance::{
-    DynamicInstanceIndex, GenericInstancePlugin, InstanceIndex, InstanceRenderAssets,
-    PreviousMeshUniform,
+    DynamicInstanceIndex, GenericInstancePlugin, HikariLightLink, HikariSubsurface, InstanceIndex,
+    InstanceRenderAssets, PreviousMeshUniform,
 };
-pub use material::{GenericMaterialPlugin, MaterialRenderAssets};
+pub use light_profile::{HikariLightProfile, IesProfile};
+pub use material::{GenericMaterialPlugin, HikariMaterial, MaterialRenderAssets};
 pub use mesh::MeshRenderAssets;
 
+/// Second UV set of a mesh, which occlusion textures are sampled with.
+/// Falls back to [`Mesh::ATTRIBUTE_UV_0`] if absent.
+pub const ATTRIBUTE_UV_1: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_Uv_1", 3288104217, VertexFormat::Float32x2);
+
 pub struct MeshMaterialPlugin;
 impl Plugin for MeshMaterialPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(MeshPlugin)
             .add_plugin(MaterialPlugin)
             .add_plugin(InstancePlugin)
+            .add_plugin(AreaLightPlugin)
+            .add_plugin(LightProfilePlugin)
+            .add_plugin(FogVolumePlugin)
             .add_plugin(GenericMaterialPlugin::<StandardMaterial>::default())
-            .add_plugin(GenericInstancePlugin::<StandardMaterial>::default());
+            .add_plugin(GenericInstancePlugin::<StandardMaterial>::default())
+            .add_plugin(GenericInstancePlugin::<HikariMaterial>::default());
 
         if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
             render_app
@@ -62,6 +80,9 @@ pub struct GpuVertex {
     pub position: Vec3,
     pub normal: Vec3,
     pub uv: Vec2,
+    pub uv_1: Vec2,
+    /// Linear vertex color, multiplied into the base color.
+    pub color: Vec4,
 }
 
 #[derive(Debug, Default, Clone, Copy, ShaderType)]
@@ -70,19 +91,63 @@ pub struct GpuVertexCompact {
     pub u: f32,
     pub normal: Vec3,
     pub v: f32,
+    /// Vertex color clamped to `[0, 1]`, as packed by `pack4x8unorm`.
+    pub color: u32,
+    /// Second UV set as half floats, as packed by `pack2x16float`.
+    pub uv_1: u32,
 }
 
 impl From<GpuVertex> for GpuVertexCompact {
     fn from(vertex: GpuVertex) -> Self {
+        let [r, g, b, a] = (vertex.color.clamp(Vec4::ZERO, Vec4::ONE) * 255.0)
+            .round()
+            .to_array()
+            .map(|value| value as u8);
+        let uv_1 = [vertex.uv_1.x, vertex.uv_1.y].map(f32_to_f16);
+
         Self {
             position: vertex.position,
             normal: vertex.normal,
             u: vertex.uv.x,
             v: vertex.uv.y,
+            color: u32::from_le_bytes([r, g, b, a]),
+            uv_1: uv_1[0] as u32 | (uv_1[1] as u32) << 16,
         }
     }
 }
 
+/// Converts to the bits of a half float, rounding the mantissa to nearest.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xFF) as i32;
+    let mantissa = bits & 0x007F_FFFF;
+
+    if exponent == 0xFF {
+        // Infinity stays infinity, and NaN stays NaN
+        return sign | 0x7C00 | if mantissa == 0 { 0 } else { 0x0200 };
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1F {
+        return sign | 0x7C00;
+    }
+    if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        // Subnormal, with the implicit leading bit shifted into the mantissa
+        let mantissa = mantissa | 0x0080_0000;
+        let shift = (14 - exponent) as u32;
+        let round = (mantissa >> (shift - 1)) & 1;
+        return sign | ((mantissa >> shift) + round) as u16;
+    }
+
+    // A carry out of the mantissa correctly rounds up into the exponent
+    let round = (mantissa >> 12) & 1;
+    sign | ((((exponent as u32) << 10) | (mantissa >> 13)) + round) as u16
+}
+
 #[derive(Debug, Default, Clone, Copy)]
 pub struct GpuPrimitive {
     /// Global positions of vertices.
@@ -152,7 +217,18 @@ pub struct GpuInstance {
     node_index: u32,
     pub transform: Mat4,
     pub inverse_transpose_model: Mat4,
+    /// Model matrix of the previous frame, for motion blur.
+    pub previous_model: Mat4,
     pub mesh: GpuMeshIndex,
+    /// Light groups the instance receives light as, see [`HikariLightLink`].
+    pub light_groups: u32,
+    /// Receiver groups the instance lights if emissive.
+    pub light_include: u32,
+    /// Receiver groups the instance never lights if emissive.
+    pub light_exclude: u32,
+    /// Subsurface scatter color in `rgb` and radius in `a`, see [`HikariSubsurface`].
+    /// Zero radius means no subsurface scattering.
+    pub subsurface: Vec4,
 }
 
 impl Bounded for GpuInstance {
@@ -215,6 +291,25 @@ pub struct GpuStandardMaterial {
 
     pub normal_map_texture: u32,
     pub occlusion_texture: u32,
+
+    pub clearcoat: f32,
+    pub clearcoat_perceptual_roughness: f32,
+    pub anisotropy_strength: f32,
+    pub anisotropy_rotation: f32,
+    /// Linear sheen color.
+    pub sheen_color: Vec3,
+    pub sheen_perceptual_roughness: f32,
+    /// Bit flags of [`GpuStandardMaterial::UNLIT`], [`GpuStandardMaterial::DOUBLE_SIDED`] and the cull modes.
+    pub flags: u32,
+    /// Transform applied to UVs before texture lookups.
+    pub uv_transform: Mat3,
+}
+
+impl GpuStandardMaterial {
+    pub const UNLIT: u32 = 1;
+    pub const DOUBLE_SIDED: u32 = 1 << 1;
+    pub const CULL_BACK: u32 = 1 << 2;
+    pub const CULL_FRONT: u32 = 1 << 3;
 }
 
 #[derive(Debug, Default, Clone, Copy, ShaderType)]
@@ -223,16 +318,32 @@ pub struct GpuAliasEntry {
     pub prob: f32,
     /// The index of the other one in the bucket.
     pub index: u32,
+    /// Index of the primitive this bucket stands for.
+    pub primitive: u32,
+    /// Probability density (per unit area) of sampling a point on the primitive.
+    pub density: f32,
 }
 
 #[derive(Debug, Default, Clone, ShaderType)]
 pub struct GpuEmissive {
+    /// Linear emissive color in `rgb`, and [`HikariMaterial::emissive_strength`] in `a`.
+    /// The emitted radiance is `rgb * a`.
     pub emissive: Vec4,
+    /// Center of the world space bounding box of the instance.
     pub position: Vec3,
+    /// Radius of influence: the extent of the instance plus the distance at which
+    /// its illuminance falls below [`HikariUniversalSettings::emissive_threshold`](crate::HikariUniversalSettings).
     pub radius: f32,
+    /// Index of the instance in the instance buffer.
     pub instance: u32,
+    /// Offset and length of the alias table of the instance.
     pub alias_table: UVec2,
+    /// Total world space surface area of the instance.
     pub surface_area: f32,
+    /// Receiver groups this emissive lights, see [`HikariLightLink`].
+    pub light_include: u32,
+    /// Receiver groups this emissive never lights.
+    pub light_exclude: u32,
     node_index: u32,
 }
 
@@ -302,7 +413,6 @@ pub struct GpuEmissiveBuffer {
 pub enum PrepareMeshError {
     MissingAttributePosition,
     MissingAttributeNormal,
-    MissingAttributeUV,
     IncompatiblePrimitiveTopology,
     NoPrimitive,
 }
@@ -327,26 +437,54 @@ impl GpuMesh {
             .collect()
     }
 
-    pub fn build_alias_table(&self, transform: Mat4) -> Vec<GpuAliasEntry> {
-        let primitive_count = self.primitives.len();
+    /// Builds the alias table for sampling primitives, weighted by area and
+    /// the average luminance of the emissive texture over each primitive.
+    /// Primitives that emit nothing are left out of the table.
+    pub fn build_alias_table(
+        &self,
+        transform: Mat4,
+        emissive_texture: Option<&EmissiveTexture>,
+        uv_transform: Mat3,
+    ) -> Vec<GpuAliasEntry> {
         let areas = self.transformed_primitive_areas(transform);
-        let surface_area: f32 = areas.iter().sum();
+        let buckets: Vec<_> = self
+            .primitives
+            .iter()
+            .zip_eq(areas.iter())
+            .enumerate()
+            .map(|(id, (primitive, area))| {
+                let luminance = emissive_texture.map_or(1.0, |texture| {
+                    let uvs = primitive
+                        .indices
+                        .map(|index| self.vertices[index as usize].uv)
+                        .map(|uv| uv_transform.transform_point2(uv));
+                    texture.average_luminance(uvs)
+                });
+                (id, area * luminance)
+            })
+            .filter(|(_, weight)| *weight > 0.0)
+            .collect();
+        let total_weight: f32 = buckets.iter().map(|(_, weight)| weight).sum();
 
-        if primitive_count == 0 {
+        if buckets.is_empty() || total_weight <= 0.0 {
             vec![]
         } else {
-            let mean_area = surface_area / (primitive_count as f32);
-            let probabilities = areas
+            let mean_weight = total_weight / (buckets.len() as f32);
+            let probabilities = buckets
                 .iter()
                 .enumerate()
-                .map(|(id, area)| (id, area / mean_area));
+                .map(|(id, (_, weight))| (id, weight / mean_weight));
             let mut over: Vec<_> = probabilities.clone().filter(|prob| prob.1 > 1.0).collect();
             let mut under: Vec<_> = probabilities.filter(|prob| prob.1 < 1.0).collect();
 
-            let mut alias_table: Vec<_> = (0..primitive_count)
-                .map(|id| GpuAliasEntry {
+            let mut alias_table: Vec<_> = buckets
+                .iter()
+                .enumerate()
+                .map(|(id, (primitive, weight))| GpuAliasEntry {
                     prob: 0.0,
                     index: id as u32,
+                    primitive: *primitive as u32,
+                    density: weight / (total_weight * areas[*primitive]),
                 })
                 .collect();
 
@@ -365,10 +503,9 @@ impl GpuMesh {
                     under.push(over_bucket);
                 }
 
-                alias_table[under_bucket.0] = GpuAliasEntry {
-                    prob: delta,
-                    index: over_bucket.0 as u32,
-                };
+                let entry = &mut alias_table[under_bucket.0];
+                entry.prob = delta;
+                entry.index = over_bucket.0 as u32;
             }
 
             alias_table
@@ -388,20 +525,34 @@ impl TryFrom<Mesh> for GpuMesh {
             .attribute(Mesh::ATTRIBUTE_NORMAL)
             .and_then(VertexAttributeValues::as_float3)
             .ok_or(PrepareMeshError::MissingAttributeNormal)?;
-        let uvs = mesh
-            .attribute(Mesh::ATTRIBUTE_UV_0)
-            .and_then(|attribute| match attribute {
-                VertexAttributeValues::Float32x2(value) => Some(value),
-                _ => None,
-            })
-            .ok_or(PrepareMeshError::MissingAttributeUV)?;
+        let float2 = |attribute: MeshVertexAttribute| match mesh.attribute(attribute) {
+            Some(VertexAttributeValues::Float32x2(value)) => Some(value),
+            _ => None,
+        };
+        let uvs = float2(Mesh::ATTRIBUTE_UV_0);
+        let uvs_1 = float2(ATTRIBUTE_UV_1).or(uvs);
+        let colors = match mesh.attribute(Mesh::ATTRIBUTE_COLOR) {
+            Some(VertexAttributeValues::Float32x4(value)) => Some(value),
+            _ => None,
+        };
+
+        // Missing optional attributes default to zero UVs and white color
+        let uv = |values: Option<&Vec<[f32; 2]>>, id: usize| {
+            values
+                .and_then(|values| values.get(id))
+                .map_or(Vec2::ZERO, |uv| Vec2::from_slice(uv))
+        };
 
         let mut vertices = vec![];
-        for (position, normal, uv) in itertools::multizip((positions, normals, uvs)) {
+        for (id, (position, normal)) in positions.iter().zip(normals).enumerate() {
             vertices.push(GpuVertex {
                 position: Vec3::from_slice(position),
                 normal: Vec3::from_slice(normal),
-                uv: Vec2::from_slice(uv),
+                uv: uv(uvs, id),
+                uv_1: uv(uvs_1, id),
+                color: colors
+                    .and_then(|colors| colors.get(id))
+                    .map_or(Vec4::ONE, |color| Vec4::from_slice(color)),
             });
         }
 
@@ -478,6 +629,7 @@ pub struct GpuMeshIndex {
 #[derive(Debug, Hash, PartialEq, Eq, Clone, SystemLabel)]
 pub enum MeshMaterialSystems {
     PrepareTextures,
+    PrepareAreaLights,
     PrepareAssets,
     PrepareInstances,
 }
@@ -590,6 +742,39 @@ impl FromWorld for MeshMaterialBindGroupLayout {
                     },
                     count: None,
                 },
+                // Area lights
+                BindGroupLayoutEntry {
+                    binding: 9,
+                    visibility: ShaderStages::all(),
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(GpuAreaLightBuffer::min_size()),
+                    },
+                    count: None,
+                },
+                // Light profiles
+                BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: ShaderStages::all(),
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(GpuLightProfileBuffer::min_size()),
+                    },
+                    count: None,
+                },
+                // Fog volumes
+                BindGroupLayoutEntry {
+                    binding: 11,
+                    visibility: ShaderStages::all(),
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(GpuFogVolumeBuffer::min_size()),
+                    },
+                    count: None,
+                },
             ],
         });
 
@@ -689,6 +874,8 @@ fn queue_mesh_material_bind_group(
     textures: Res<MaterialTextures>,
     materials: Res<MaterialRenderAssets>,
     instances: Res<InstanceRenderAssets>,
+    area_lights: Res<AreaLightRenderAssets>,
+    fog_volumes: Res<FogVolumeRenderAssets>,
     images: Res<RenderAssets<Image>>,
     mesh_material_layout: Res<MeshMaterialBindGroupLayout>,
     texture_layout: Res<TextureBindGroupLayout>,
@@ -703,6 +890,9 @@ fn queue_mesh_material_bind_group(
         Some(emissive_binding),
         Some(emissive_node_binding),
         Some(alias_table_binding),
+        Some(area_light_binding),
+        Some(light_profile_binding),
+        Some(fog_volume_binding),
     ) = (
         meshes.vertex_buffer.binding(),
         meshes.primitive_buffer.binding(),
@@ -713,6 +903,9 @@ fn queue_mesh_material_bind_group(
         instances.emissive_buffer.binding(),
         instances.emissive_node_buffer.binding(),
         instances.alias_table_buffer.binding(),
+        area_lights.area_light_buffer.binding(),
+        area_lights.light_profile_buffer.binding(),
+        fog_volumes.fog_volume_buffer.binding(),
     ) {
         let mesh_material = render_device.create_bind_group(&BindGroupDescriptor {
             label: None,
@@ -754,6 +947,18 @@ fn queue_mesh_material_bind_group(
                     binding: 8,
                     resource: emissive_binding,
                 },
+                BindGroupEntry {
+                    binding: 9,
+                    resource: area_light_binding,
+                },
+                BindGroupEntry {
+                    binding: 10,
+                    resource: light_profile_binding,
+                },
+                BindGroupEntry {
+                    binding: 11,
+                    resource: fog_volume_binding,
+                },
             ],
         });
 
@@ -822,3 +1027,136 @@ impl<const I: usize> EntityRenderCommand for SetMeshMaterialBindGroup<I> {
         RenderCommandResult::Success
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A mesh in the XY plane whose positions are its texture coordinates.
+    fn planar_mesh(triangles: &[[Vec2; 3]]) -> GpuMesh {
+        let vertices = triangles
+            .iter()
+            .flatten()
+            .map(|uv| GpuVertex {
+                position: uv.extend(0.0),
+                normal: Vec3::Z,
+                uv: *uv,
+                ..Default::default()
+            })
+            .collect();
+        let primitives = triangles
+            .iter()
+            .enumerate()
+            .map(|(id, triangle)| GpuPrimitive {
+                vertices: triangle.map(|uv| uv.extend(0.0)),
+                indices: [0, 1, 2].map(|vertex| (3 * id + vertex) as u32),
+                node_index: 0,
+            })
+            .collect();
+        GpuMesh {
+            vertices,
+            primitives,
+            nodes: vec![],
+        }
+    }
+
+    // Probability of sampling each bucket: kept with `1 - prob`, or reached as the alias of another.
+    fn bucket_probabilities(table: &[GpuAliasEntry]) -> Vec<f32> {
+        let n = table.len() as f32;
+        let mut probabilities: Vec<_> = table.iter().map(|entry| (1.0 - entry.prob) / n).collect();
+        for entry in table {
+            probabilities[entry.index as usize] += entry.prob / n;
+        }
+        probabilities
+    }
+
+    #[test]
+    fn alias_table_by_area() {
+        let mesh = planar_mesh(&[
+            [Vec2::ZERO, Vec2::X, Vec2::Y],
+            [Vec2::ZERO, 2.0 * Vec2::X, Vec2::Y],
+            [Vec2::ZERO, Vec2::X, 5.0 * Vec2::Y],
+        ]);
+        let table = mesh.build_alias_table(Mat4::IDENTITY, None, Mat3::IDENTITY);
+        assert_eq!(table.len(), 3);
+
+        let probabilities = bucket_probabilities(&table);
+        for (probability, expected) in probabilities.iter().zip([0.125, 0.25, 0.625]) {
+            assert!((probability - expected).abs() < 1e-5);
+        }
+        // Area sampling is uniform over the whole mesh
+        for entry in &table {
+            assert!((entry.density - 0.25).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn alias_table_drops_dark_primitives() {
+        // Only the right half of the texture emits
+        let texture = EmissiveTexture {
+            size: UVec2::new(2, 1),
+            luminance: vec![0.0, 1.0],
+        };
+        let mesh = planar_mesh(&[
+            [
+                Vec2::new(0.1, 0.1),
+                Vec2::new(0.4, 0.1),
+                Vec2::new(0.1, 0.4),
+            ],
+            [
+                Vec2::new(0.6, 0.1),
+                Vec2::new(0.9, 0.1),
+                Vec2::new(0.6, 0.4),
+            ],
+            [
+                Vec2::new(0.6, 0.1),
+                Vec2::new(0.9, 0.1),
+                Vec2::new(0.6, 0.7),
+            ],
+        ]);
+        let table = mesh.build_alias_table(Mat4::IDENTITY, Some(&texture), Mat3::IDENTITY);
+
+        let primitives: Vec<_> = table.iter().map(|entry| entry.primitive).collect();
+        assert_eq!(primitives, vec![1, 2]);
+
+        let probabilities = bucket_probabilities(&table);
+        assert!((probabilities[0] - 1.0 / 3.0).abs() < 1e-5);
+        assert!((probabilities[1] - 2.0 / 3.0).abs() < 1e-5);
+        for entry in &table {
+            assert!((entry.density - 1.0 / 0.135).abs() < 1e-3);
+        }
+
+        // Nothing is left to sample on a mesh that emits nowhere
+        let dark = EmissiveTexture {
+            size: UVec2::ONE,
+            luminance: vec![0.0],
+        };
+        assert!(mesh
+            .build_alias_table(Mat4::IDENTITY, Some(&dark), Mat3::IDENTITY)
+            .is_empty());
+    }
+
+    #[test]
+    fn half_float_conversion() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+        assert_eq!(f32_to_f16(1.0), 0x3C00);
+        assert_eq!(f32_to_f16(-2.0), 0xC000);
+        assert_eq!(f32_to_f16(0.333), 0x3554);
+
+        // Largest finite half, and the first value that rounds past it
+        assert_eq!(f32_to_f16(65504.0), 0x7BFF);
+        assert_eq!(f32_to_f16(65520.0), 0x7C00);
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7C00);
+        assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xFC00);
+
+        // Smallest normal and subnormal halves, and a value that flushes to zero
+        assert_eq!(f32_to_f16(2.0_f32.powi(-14)), 0x0400);
+        assert_eq!(f32_to_f16(2.0_f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_f16(1e-9), 0x0000);
+
+        let nan = f32_to_f16(f32::NAN);
+        assert_eq!(nan & 0x7C00, 0x7C00);
+        assert_ne!(nan & 0x03FF, 0);
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{
     light::LightTextures, post_process::PostProcessTextures, prepass::PrepassBindGroup,
-    HikariSettings, Taa, Upscale, OVERLAY_SHADER_HANDLE, QUAD_MESH_HANDLE,
+    HikariSettings, HikariTonemapping, Taa, Upscale, OVERLAY_SHADER_HANDLE, QUAD_MESH_HANDLE,
 };
 use bevy::{
     core_pipeline::clear_color::ClearColorConfig,
@@ -96,8 +96,15 @@ impl FromWorld for OverlayPipeline {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OverlayPipelineKey {
+    pub mesh_key: MeshPipelineKey,
+    /// Operator the post process output is tone mapped with, which is inverted on HDR cameras.
+    pub tonemapping: HikariTonemapping,
+}
+
 impl SpecializedMeshPipeline for OverlayPipeline {
-    type Key = MeshPipelineKey;
+    type Key = OverlayPipelineKey;
 
     fn specialize(
         &self,
@@ -110,11 +117,21 @@ impl SpecializedMeshPipeline for OverlayPipeline {
 
         let mut shader_defs = vec![];
         let mut format = TextureFormat::bevy_default();
-        if key.contains(MeshPipelineKey::HDR) {
+        if key.mesh_key.contains(MeshPipelineKey::HDR) {
             shader_defs.push("HDR".into());
             format = ViewTarget::TEXTURE_FORMAT_HDR;
         }
 
+        match key.tonemapping {
+            HikariTonemapping::None => {}
+            HikariTonemapping::Reinhard => shader_defs.push("TONEMAPPING_REINHARD".into()),
+            HikariTonemapping::AcesFitted => shader_defs.push("TONEMAPPING_ACES_FITTED".into()),
+            HikariTonemapping::AgX => shader_defs.push("TONEMAPPING_AGX".into()),
+            HikariTonemapping::HuePreserving => {
+                shader_defs.push("TONEMAPPING_HUE_PRESERVING".into())
+            }
+        }
+
         Ok(RenderPipelineDescriptor {
             label: None,
             layout: Some(bind_group_layout),
@@ -135,7 +152,7 @@ impl SpecializedMeshPipeline for OverlayPipeline {
                 })],
             }),
             primitive: PrimitiveState {
-                topology: key.primitive_topology(),
+                topology: key.mesh_key.primitive_topology(),
                 strip_index_format: None,
                 front_face: FrontFace::Ccw,
                 cull_mode: None,
@@ -171,19 +188,24 @@ fn queue_overlay_meshes(
     overlay_pipeline: Res<OverlayPipeline>,
     mut pipelines: ResMut<SpecializedMeshPipelines<OverlayPipeline>>,
     mut pipeline_cache: ResMut<PipelineCache>,
-    mut views: Query<(&mut RenderPhase<Overlay>, &ExtractedView)>,
+    mut views: Query<(&mut RenderPhase<Overlay>, &ExtractedView, &HikariSettings)>,
 ) {
     let draw_function = draw_functions.read().get_id::<DrawOverlay>().unwrap();
-    for (mut overlay_phase, view) in &mut views {
+    for (mut overlay_phase, view, settings) in &mut views {
         let mesh_handle = QUAD_MESH_HANDLE.typed::<Mesh>();
         if let Some(mesh) = render_meshes.get(&mesh_handle) {
-            let mut key = MeshPipelineKey::from_msaa_samples(msaa.samples)
+            let mut mesh_key = MeshPipelineKey::from_msaa_samples(msaa.samples)
                 | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology);
 
             if view.hdr {
-                key |= MeshPipelineKey::HDR;
+                mesh_key |= MeshPipelineKey::HDR;
             }
 
+            let key = OverlayPipelineKey {
+                mesh_key,
+                tonemapping: settings.tonemapping,
+            };
+
             let pipeline_id =
                 pipelines.specialize(&mut pipeline_cache, &overlay_pipeline, key, &mesh.layout);
             let pipeline_id = match pipeline_id {
@@ -226,8 +248,10 @@ fn queue_overlay_bind_groups(
 
         let input_texture = match (settings.upscale, settings.taa) {
             (Upscale::Fsr1 { .. }, _) => &post_process.upscale_output[1],
+            (upscale, _) if upscale.smaa_easu() => &post_process.upscale_output[1],
             (Upscale::SmaaTu4x { .. }, Taa::None) => &post_process.upscale_output[0],
-            (Upscale::SmaaTu4x { .. }, Taa::Jasmine) => &post_process.taa_output[current],
+            (_, Taa::Jasmine) => &post_process.taa_output[current],
+            (Upscale::None, Taa::None) => &post_process.tone_mapping_output[current],
         };
 
         let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
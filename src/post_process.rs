@@ -1,10 +1,14 @@
 use crate::{
+    color_grading::ColorGradingUniform,
+    denoiser::{ActiveDenoiser, DenoiseTarget, DenoiserInput, SvgfDenoiser},
     light::{LightTextures, VARIANCE_TEXTURE_FORMAT},
+    mesh_material::fog_volume::FogVolumeRenderAssets,
     prepass::{DeferredBindGroup, PrepassBindGroup, PrepassPipeline, PrepassTextures},
     view::{FrameCounter, FrameUniform, PreviousViewUniformOffset},
-    HikariSettings, Taa, Upscale, DENOISE_SHADER_HANDLE, FSR1_EASU_SHADER_HANDLE,
-    FSR1_RCAS_SHADER_HANDLE, SMAA_SHADER_HANDLE, TAA_SHADER_HANDLE, TONE_MAPPING_SHADER_HANDLE,
-    WORKGROUP_SIZE,
+    HikariAutoExposure, HikariColorGrading, HikariSettings, IndirectResolution, Taa, Upscale,
+    COLOR_GRADING_SHADER_HANDLE, DENOISE_SHADER_HANDLE, FSR1_EASU_SHADER_HANDLE,
+    FSR1_RCAS_SHADER_HANDLE, IDENTITY_LUT_HANDLE, SMAA_SHADER_HANDLE, TAA_SHADER_HANDLE,
+    TONE_MAPPING_SHADER_HANDLE, WORKGROUP_SIZE,
 };
 use bevy::{
     ecs::query::QueryItem,
@@ -16,28 +20,39 @@ use bevy::{
             ComponentUniforms, DynamicUniformIndex, ExtractComponent, ExtractComponentPlugin,
             UniformComponentPlugin,
         },
+        render_asset::RenderAssets,
         render_graph::{Node, NodeRunError, RenderGraphContext, SlotInfo, SlotType},
         render_resource::*,
-        renderer::{RenderContext, RenderDevice},
-        texture::TextureCache,
-        view::ViewUniformOffset,
-        RenderApp, RenderStage,
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        texture::{FallbackImage, TextureCache},
+        view::{ExtractedView, ViewUniformOffset},
+        Extract, RenderApp, RenderStage,
     },
+    utils::HashMap,
 };
 use serde::Serialize;
 
 pub const HDR_TEXTURE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+pub const MOMENTS_TEXTURE_FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+pub const LUMINANCE_HISTOGRAM_BIN_COUNT: usize = 256;
 
 pub struct PostProcessPlugin;
 impl Plugin for PostProcessPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(ExtractComponentPlugin::<FsrConstantsUniform>::default())
-            .add_plugin(UniformComponentPlugin::<FsrConstantsUniform>::default());
+            .add_plugin(UniformComponentPlugin::<FsrConstantsUniform>::default())
+            .add_plugin(UniformComponentPlugin::<AutoExposureUniform>::default());
 
         if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            if !render_app.world.contains_resource::<ActiveDenoiser>() {
+                render_app.insert_resource(ActiveDenoiser(Box::new(SvgfDenoiser)));
+            }
+
             render_app
                 .init_resource::<PostProcessPipeline>()
                 .init_resource::<SpecializedComputePipelines<PostProcessPipeline>>()
+                .init_resource::<AutoExposureBuffers>()
+                .add_system_to_stage(RenderStage::Extract, extract_auto_exposure)
                 .add_system_to_stage(RenderStage::Prepare, prepare_post_process_textures)
                 .add_system_to_stage(RenderStage::Queue, queue_post_process_pipelines)
                 .add_system_to_stage(RenderStage::Queue, queue_post_process_bind_groups);
@@ -52,7 +67,10 @@ pub struct PostProcessPipeline {
     pub sampler_layout: BindGroupLayout,
     pub denoise_internal_layout: BindGroupLayout,
     pub denoise_render_layout: BindGroupLayout,
+    pub denoise_history_layout: BindGroupLayout,
     pub tone_mapping_layout: BindGroupLayout,
+    pub auto_exposure_layout: BindGroupLayout,
+    pub color_grading_layout: BindGroupLayout,
     pub smaa_layout: BindGroupLayout,
     pub taa_layout: BindGroupLayout,
     pub upscale_layout: BindGroupLayout,
@@ -196,6 +214,57 @@ impl FromWorld for PostProcessPipeline {
                 ],
             });
 
+        let denoise_history_layout =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    // Previous History
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    // Previous Moments
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    // History
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::WriteOnly,
+                            format: HDR_TEXTURE_FORMAT,
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    // Moments
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::WriteOnly,
+                            format: MOMENTS_TEXTURE_FORMAT,
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
         let tone_mapping_layout =
             render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
                 label: None,
@@ -233,6 +302,86 @@ impl FromWorld for PostProcessPipeline {
                         },
                         count: None,
                     },
+                    // Fog Render
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: true },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let auto_exposure_layout =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    // Auto Exposure Uniform
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: Some(AutoExposureUniform::min_size()),
+                        },
+                        count: None,
+                    },
+                    // Histogram and Adapted Exposure
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: Some(GpuAutoExposureBuffer::min_size()),
+                        },
+                        count: None,
+                    },
+                    // Metering Mask
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: true },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let color_grading_layout =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    // Color Grading Uniform
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: Some(ColorGradingUniform::min_size()),
+                        },
+                        count: None,
+                    },
+                    // LUT
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D3,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
@@ -338,7 +487,10 @@ impl FromWorld for PostProcessPipeline {
             sampler_layout,
             denoise_internal_layout,
             denoise_render_layout,
+            denoise_history_layout,
             tone_mapping_layout,
+            auto_exposure_layout,
+            color_grading_layout,
             smaa_layout,
             taa_layout,
             upscale_layout,
@@ -360,6 +512,12 @@ pub enum PostProcessEntryPoint {
     SmaaTu4xExtrapolate = 5,
     Upscale = 6,
     UpscaleSharpen = 7,
+    TemporalAccumulation = 8,
+    Remodulation = 9,
+    IndirectUpsample = 10,
+    LuminanceHistogram = 11,
+    AutoExposure = 12,
+    ColorGrading = 13,
 }
 
 bitflags::bitflags! {
@@ -367,6 +525,7 @@ bitflags::bitflags! {
     pub struct PostProcessPipelineKey: u32 {
         const ENTRY_POINT_BITS          = PostProcessPipelineKey::ENTRY_POINT_MASK_BITS;
         const FIREFLY_FILTERING_BITS    = 1 << PostProcessPipelineKey::FIREFLY_FILTERING_SHIFT_BITS;
+        const MEDIUM_BITS               = 1 << PostProcessPipelineKey::MEDIUM_SHIFT_BITS;
         const DENOISE_LEVEL_BITS        = PostProcessPipelineKey::DENOISE_LEVEL_MASK_BITS << PostProcessPipelineKey::DENOISE_LEVEL_SHIFT_BITS;
     }
 }
@@ -376,6 +535,7 @@ impl PostProcessPipelineKey {
     const DENOISE_LEVEL_MASK_BITS: u32 = 0b11;
     const DENOISE_LEVEL_SHIFT_BITS: u32 = 32 - 2;
     const FIREFLY_FILTERING_SHIFT_BITS: u32 = 8;
+    const MEDIUM_SHIFT_BITS: u32 = 9;
 
     pub fn from_entry_point(entry_point: PostProcessEntryPoint) -> Self {
         let entry_point_bits = (entry_point as u32) & Self::ENTRY_POINT_MASK_BITS;
@@ -410,9 +570,15 @@ impl SpecializedComputePipeline for PostProcessPipeline {
         if key.contains(PostProcessPipelineKey::FIREFLY_FILTERING_BITS) {
             shader_defs.push("FIREFLY_FILTERING".into());
         }
+        if key.contains(PostProcessPipelineKey::MEDIUM_BITS) {
+            shader_defs.push("MEDIUM".into());
+        }
 
         let (layout, shader) = match key.entry_point() {
-            PostProcessEntryPoint::Demodulation | PostProcessEntryPoint::Denoise => {
+            PostProcessEntryPoint::Demodulation
+            | PostProcessEntryPoint::Denoise
+            | PostProcessEntryPoint::Remodulation
+            | PostProcessEntryPoint::IndirectUpsample => {
                 let layout = vec![
                     self.view_layout.clone(),
                     self.deferred_layout.clone(),
@@ -424,17 +590,44 @@ impl SpecializedComputePipeline for PostProcessPipeline {
                 let shader = DENOISE_SHADER_HANDLE.typed();
                 (layout, shader)
             }
-            PostProcessEntryPoint::ToneMapping => {
+            PostProcessEntryPoint::TemporalAccumulation => {
+                let layout = vec![
+                    self.view_layout.clone(),
+                    self.deferred_layout.clone(),
+                    self.sampler_layout.clone(),
+                    self.denoise_internal_layout.clone(),
+                    self.denoise_render_layout.clone(),
+                    self.denoise_history_layout.clone(),
+                ];
+                shader_defs.push("DENOISE_LEVEL_0".into());
+                let shader = DENOISE_SHADER_HANDLE.typed();
+                (layout, shader)
+            }
+            PostProcessEntryPoint::ToneMapping
+            | PostProcessEntryPoint::LuminanceHistogram
+            | PostProcessEntryPoint::AutoExposure => {
                 let layout = vec![
                     self.view_layout.clone(),
                     self.deferred_layout.clone(),
                     self.sampler_layout.clone(),
                     self.tone_mapping_layout.clone(),
                     self.output_layout.clone(),
+                    self.auto_exposure_layout.clone(),
                 ];
                 let shader = TONE_MAPPING_SHADER_HANDLE.typed();
                 (layout, shader)
             }
+            PostProcessEntryPoint::ColorGrading => {
+                let layout = vec![
+                    self.view_layout.clone(),
+                    self.deferred_layout.clone(),
+                    self.sampler_layout.clone(),
+                    self.color_grading_layout.clone(),
+                    self.output_layout.clone(),
+                ];
+                let shader = COLOR_GRADING_SHADER_HANDLE.typed();
+                (layout, shader)
+            }
             PostProcessEntryPoint::TaaJasmine => {
                 let layout = vec![
                     self.view_layout.clone(),
@@ -522,7 +715,11 @@ impl ExtractComponent for FsrConstantsUniform {
     fn extract_component((camera, settings): QueryItem<Self::Query>) -> Self {
         let size = camera.physical_target_size().unwrap_or_default();
         let scale = settings.upscale.ratio().recip();
-        let scaled_size = (scale * size.as_vec2()).ceil();
+        let mut scaled_size = (scale * size.as_vec2()).ceil();
+        if settings.upscale.smaa_easu() {
+            // EASU reads the SMAA output, which is twice the render size.
+            scaled_size *= 2.0;
+        }
         Self {
             input_viewport_in_pixels: scaled_size,
             input_size_in_pixels: scaled_size,
@@ -533,6 +730,69 @@ impl ExtractComponent for FsrConstantsUniform {
     }
 }
 
+#[derive(Debug, Default, Clone, Copy, Component, ShaderType)]
+pub struct AutoExposureUniform {
+    pub enabled: u32,
+    pub min_ev: f32,
+    pub max_ev: f32,
+    pub speed: f32,
+    /// Seconds since the previous frame.
+    pub delta_time: f32,
+}
+
+/// Luminance histogram of the current frame, and the exposure adapted to it over time.
+#[derive(Clone, ShaderType)]
+pub struct GpuAutoExposureBuffer {
+    /// Weighted pixel counts per EV bin; the first bin holds pixels darker than the minimum EV.
+    pub histogram: [u32; LUMINANCE_HISTOGRAM_BIN_COUNT],
+    /// Adapted average scene luminance, in EV.
+    pub ev: f32,
+    /// Whether `ev` has been metered once, so that the first frame does not fade in.
+    pub initialized: u32,
+}
+
+impl Default for GpuAutoExposureBuffer {
+    fn default() -> Self {
+        Self {
+            histogram: [0; LUMINANCE_HISTOGRAM_BIN_COUNT],
+            ev: 0.0,
+            initialized: 0,
+        }
+    }
+}
+
+#[derive(Default, Resource, Deref, DerefMut)]
+pub struct AutoExposureBuffers(HashMap<Entity, StorageBuffer<GpuAutoExposureBuffer>>);
+
+fn extract_auto_exposure(
+    mut commands: Commands,
+    time: Extract<Res<Time>>,
+    cameras: Extract<Query<(Entity, &Camera, Option<&HikariAutoExposure>), With<HikariSettings>>>,
+) {
+    for (entity, camera, auto_exposure) in cameras.iter() {
+        if !camera.is_active {
+            continue;
+        }
+
+        let uniform = match auto_exposure {
+            Some(auto_exposure) if auto_exposure.enabled => AutoExposureUniform {
+                enabled: 1,
+                min_ev: auto_exposure.min_ev,
+                max_ev: auto_exposure.max_ev.max(auto_exposure.min_ev),
+                speed: auto_exposure.speed.max(0.0),
+                delta_time: time.delta_seconds(),
+            },
+            _ => AutoExposureUniform::default(),
+        };
+
+        let mut entity_commands = commands.get_or_spawn(entity);
+        entity_commands.insert(uniform);
+        if let Some(auto_exposure) = auto_exposure {
+            entity_commands.insert(auto_exposure.clone());
+        }
+    }
+}
+
 // NOTE! Don't delete, might be used soon, instead of calulating this on GPU
 // fn get_fsr_constants(ratio: f32, hdr_rcas: bool, camera: &ExtractedCamera) -> FSRConstantsUniform {
 //     let mut fsr_constant = FSRConstantsUniform::default();
@@ -626,7 +886,12 @@ pub struct PostProcessTextures {
     pub fallback: TextureView,
     pub denoise_internal: [TextureView; 4],
     pub denoise_internal_variance: TextureView,
-    pub denoise_render: [TextureView; 3],
+    /// Denoised direct, emissive, indirect and fog renders.
+    pub denoise_render: [TextureView; 4],
+    /// Temporally accumulated irradiance of each render, of the current and the previous frames.
+    pub denoise_history: [[TextureView; 2]; 4],
+    /// Luminance moments and history lengths of each render, of the current and the previous frames.
+    pub denoise_moments: [[TextureView; 2]; 4],
     pub tone_mapping_output: [TextureView; 2],
     pub taa_output: [TextureView; 2],
     pub upscale_output: [TextureView; 2],
@@ -635,7 +900,9 @@ pub struct PostProcessTextures {
 fn prepare_post_process_textures(
     mut commands: Commands,
     render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
     mut texture_cache: ResMut<TextureCache>,
+    mut auto_exposure_buffers: ResMut<AutoExposureBuffers>,
     cameras: Query<(Entity, &ExtractedCamera, &FrameCounter, &HikariSettings)>,
 ) {
     let texture_usage = TextureUsages::TEXTURE_BINDING | TextureUsages::STORAGE_BINDING;
@@ -660,10 +927,16 @@ fn prepare_post_process_textures(
 
     for (entity, camera, counter, settings) in &cameras {
         if let Some(size) = camera.physical_target_size {
-            let mut create_texture = |texture_format, scale: f32| {
+            if !auto_exposure_buffers.contains_key(&entity) {
+                let mut buffer = StorageBuffer::from(GpuAutoExposureBuffer::default());
+                buffer.write_buffer(&render_device, &render_queue);
+                auto_exposure_buffers.insert(entity, buffer);
+            }
+
+            let mut create_texture = |texture_format, size: UVec2| {
                 let extent = Extent3d {
-                    width: (size.x as f32 * scale).ceil() as u32,
-                    height: (size.y as f32 * scale).ceil() as u32,
+                    width: size.x,
+                    height: size.y,
                     depth_or_array_layers: 1,
                 };
                 texture_cache
@@ -683,11 +956,8 @@ fn prepare_post_process_textures(
             };
 
             macro_rules! create_texture_array {
-                [$texture_format:ident, $scale:ident; $count:literal] => {
-                    [(); $count].map(|_| create_texture($texture_format, $scale))
-                };
-                [$texture_format:ident, $scale:literal; $count:literal] => {
-                    [(); $count].map(|_| create_texture($texture_format, $scale))
+                [$texture_format:ident, $size:ident; $count:literal] => {
+                    [(); $count].map(|_| create_texture($texture_format, $size))
                 };
                 [$texture:ident; $count:literal] => {
                     [(); $count].map(|_| $texture.clone())
@@ -707,25 +977,39 @@ fn prepare_post_process_textures(
                 ..Default::default()
             });
 
-            let mut scale = settings.upscale.ratio().recip();
+            let scale = settings.upscale.ratio().recip();
+            let mut scaled_size = (scale * size.as_vec2()).ceil().as_uvec2();
 
-            let denoise_internal_variance = create_texture(VARIANCE_TEXTURE_FORMAT, scale);
-            let denoise_internal = create_texture_array![HDR_TEXTURE_FORMAT, scale; 4];
-            let denoise_render = create_texture_array![HDR_TEXTURE_FORMAT, scale; 3];
+            let denoise_internal_variance = create_texture(VARIANCE_TEXTURE_FORMAT, scaled_size);
+            let denoise_internal = create_texture_array![HDR_TEXTURE_FORMAT, scaled_size; 4];
+            let denoise_render = create_texture_array![HDR_TEXTURE_FORMAT, scaled_size; 4];
+            let denoise_history =
+                [(); 4].map(|_| create_texture_array![HDR_TEXTURE_FORMAT, scaled_size; 2]);
+            let denoise_moments =
+                [(); 4].map(|_| create_texture_array![MOMENTS_TEXTURE_FORMAT, scaled_size; 2]);
 
-            let tone_mapping_output = create_texture_array![HDR_TEXTURE_FORMAT, scale; 2];
+            let tone_mapping_output = create_texture_array![HDR_TEXTURE_FORMAT, scaled_size; 2];
 
+            // The SMAA output is exactly twice the render size, so that no texel is cut off at odd ratios.
+            // Larger ratios are finished by EASU, which writes the second texture at the target size.
             let upscale_output = match settings.upscale {
+                Upscale::None => create_texture_array![fallback; 2],
                 Upscale::SmaaTu4x { .. } => {
-                    scale *= 2.0;
-                    create_texture_array![HDR_TEXTURE_FORMAT, scale; 2]
+                    scaled_size *= 2;
+                    let output = create_texture(HDR_TEXTURE_FORMAT, scaled_size);
+                    let easu_output = if settings.upscale.smaa_easu() {
+                        create_texture(HDR_TEXTURE_FORMAT, size)
+                    } else {
+                        fallback.clone()
+                    };
+                    [output, easu_output]
                 }
-                Upscale::Fsr1 { .. } => create_texture_array![HDR_TEXTURE_FORMAT, 1.0; 2],
+                Upscale::Fsr1 { .. } => create_texture_array![HDR_TEXTURE_FORMAT, size; 2],
             };
 
             let taa_output = match settings.taa {
                 Taa::Jasmine => {
-                    create_texture_array![HDR_TEXTURE_FORMAT, scale; 2]
+                    create_texture_array![HDR_TEXTURE_FORMAT, scaled_size; 2]
                 }
                 Taa::None => create_texture_array![fallback; 2],
             };
@@ -738,6 +1022,8 @@ fn prepare_post_process_textures(
                 denoise_internal,
                 denoise_internal_variance,
                 denoise_render,
+                denoise_history,
+                denoise_moments,
                 tone_mapping_output,
                 taa_output,
                 upscale_output,
@@ -746,18 +1032,26 @@ fn prepare_post_process_textures(
     }
 }
 
-#[allow(dead_code)]
 #[derive(Resource)]
 pub struct CachedPostProcessPipelines {
-    demodulation: CachedComputePipelineId,
-    denoise_direct: [CachedComputePipelineId; 4],
-    denoise: [CachedComputePipelineId; 4],
-    tone_mapping: CachedComputePipelineId,
-    taa_jasmine: CachedComputePipelineId,
-    smaa_tu4x: CachedComputePipelineId,
-    smaa_tu4x_extrapolate: CachedComputePipelineId,
-    upscale: CachedComputePipelineId,
-    upscale_sharpen: CachedComputePipelineId,
+    pub indirect_upsample: CachedComputePipelineId,
+    pub demodulation: CachedComputePipelineId,
+    pub temporal_accumulation: CachedComputePipelineId,
+    pub denoise_direct: [CachedComputePipelineId; 4],
+    pub denoise: [CachedComputePipelineId; 4],
+    pub remodulation: CachedComputePipelineId,
+    pub demodulation_medium: CachedComputePipelineId,
+    pub temporal_accumulation_medium: CachedComputePipelineId,
+    pub denoise_medium: [CachedComputePipelineId; 4],
+    pub luminance_histogram: CachedComputePipelineId,
+    pub auto_exposure: CachedComputePipelineId,
+    pub tone_mapping: CachedComputePipelineId,
+    pub color_grading: CachedComputePipelineId,
+    pub taa_jasmine: CachedComputePipelineId,
+    pub smaa_tu4x: CachedComputePipelineId,
+    pub smaa_tu4x_extrapolate: CachedComputePipelineId,
+    pub upscale: CachedComputePipelineId,
+    pub upscale_sharpen: CachedComputePipelineId,
 }
 
 fn queue_post_process_pipelines(
@@ -766,10 +1060,19 @@ fn queue_post_process_pipelines(
     mut pipelines: ResMut<SpecializedComputePipelines<PostProcessPipeline>>,
     mut pipeline_cache: ResMut<PipelineCache>,
 ) {
+    let indirect_upsample = {
+        let key = PostProcessPipelineKey::from_entry_point(PostProcessEntryPoint::IndirectUpsample);
+        pipelines.specialize(&mut pipeline_cache, &pipeline, key)
+    };
     let demodulation = {
         let key = PostProcessPipelineKey::from_entry_point(PostProcessEntryPoint::Demodulation);
         pipelines.specialize(&mut pipeline_cache, &pipeline, key)
     };
+    let temporal_accumulation = {
+        let key =
+            PostProcessPipelineKey::from_entry_point(PostProcessEntryPoint::TemporalAccumulation);
+        pipelines.specialize(&mut pipeline_cache, &pipeline, key)
+    };
     let denoise_direct = [0, 1, 2, 3].map(|level| {
         let mut key = PostProcessPipelineKey::from_entry_point(PostProcessEntryPoint::Denoise);
         key |= PostProcessPipelineKey::from_denoise_level(level);
@@ -781,11 +1084,47 @@ fn queue_post_process_pipelines(
         key |= PostProcessPipelineKey::FIREFLY_FILTERING_BITS;
         pipelines.specialize(&mut pipeline_cache, &pipeline, key)
     });
+    let remodulation = {
+        let key = PostProcessPipelineKey::from_entry_point(PostProcessEntryPoint::Remodulation);
+        pipelines.specialize(&mut pipeline_cache, &pipeline, key)
+    };
 
+    // Media are neither demodulated by the surface albedo nor masked by the background.
+    let demodulation_medium = {
+        let mut key = PostProcessPipelineKey::from_entry_point(PostProcessEntryPoint::Demodulation);
+        key |= PostProcessPipelineKey::MEDIUM_BITS;
+        pipelines.specialize(&mut pipeline_cache, &pipeline, key)
+    };
+    let temporal_accumulation_medium = {
+        let mut key =
+            PostProcessPipelineKey::from_entry_point(PostProcessEntryPoint::TemporalAccumulation);
+        key |= PostProcessPipelineKey::MEDIUM_BITS;
+        pipelines.specialize(&mut pipeline_cache, &pipeline, key)
+    };
+    let denoise_medium = [0, 1, 2, 3].map(|level| {
+        let mut key = PostProcessPipelineKey::from_entry_point(PostProcessEntryPoint::Denoise);
+        key |= PostProcessPipelineKey::from_denoise_level(level);
+        key |= PostProcessPipelineKey::MEDIUM_BITS;
+        pipelines.specialize(&mut pipeline_cache, &pipeline, key)
+    });
+
+    let luminance_histogram = {
+        let key =
+            PostProcessPipelineKey::from_entry_point(PostProcessEntryPoint::LuminanceHistogram);
+        pipelines.specialize(&mut pipeline_cache, &pipeline, key)
+    };
+    let auto_exposure = {
+        let key = PostProcessPipelineKey::from_entry_point(PostProcessEntryPoint::AutoExposure);
+        pipelines.specialize(&mut pipeline_cache, &pipeline, key)
+    };
     let tone_mapping = {
         let key = PostProcessPipelineKey::from_entry_point(PostProcessEntryPoint::ToneMapping);
         pipelines.specialize(&mut pipeline_cache, &pipeline, key)
     };
+    let color_grading = {
+        let key = PostProcessPipelineKey::from_entry_point(PostProcessEntryPoint::ColorGrading);
+        pipelines.specialize(&mut pipeline_cache, &pipeline, key)
+    };
 
     let taa_jasmine = {
         let key = PostProcessPipelineKey::from_entry_point(PostProcessEntryPoint::TaaJasmine);
@@ -812,10 +1151,19 @@ fn queue_post_process_pipelines(
     };
 
     commands.insert_resource(CachedPostProcessPipelines {
+        indirect_upsample,
         demodulation,
+        temporal_accumulation,
         denoise_direct,
         denoise,
+        remodulation,
+        demodulation_medium,
+        temporal_accumulation_medium,
+        denoise_medium,
+        luminance_histogram,
+        auto_exposure,
         tone_mapping,
+        color_grading,
         taa_jasmine,
         smaa_tu4x,
         smaa_tu4x_extrapolate,
@@ -828,9 +1176,18 @@ fn queue_post_process_pipelines(
 pub struct PostProcessBindGroup {
     pub sampler: BindGroup,
     pub denoise_internal: BindGroup,
+    /// Present only if indirect lighting is traced at a reduced resolution.
+    pub indirect_upsample: Option<BindGroup>,
     pub denoise_render: Vec<BindGroup>,
+    /// Present only if there are fog volumes to denoise.
+    pub denoise_fog: Option<BindGroup>,
+    /// Temporal accumulation histories of direct, emissive, indirect and fog renders.
+    pub denoise_history: [BindGroup; 4],
     pub tone_mapping: BindGroup,
     pub tone_mapping_output: BindGroup,
+    pub auto_exposure: BindGroup,
+    /// Present only if the camera has enabled [`HikariColorGrading`].
+    pub color_grading: Option<BindGroup>,
     pub smaa: BindGroup,
     pub smaa_output: BindGroup,
     pub taa: BindGroup,
@@ -841,28 +1198,74 @@ pub struct PostProcessBindGroup {
     pub upscale_sharpen_output: BindGroup,
 }
 
+impl PostProcessBindGroup {
+    /// The render bind group of a denoise target, if that render is denoised this frame.
+    pub fn denoise_render(&self, target: DenoiseTarget) -> Option<&BindGroup> {
+        match target {
+            DenoiseTarget::Fog => self.denoise_fog.as_ref(),
+            _ => self.denoise_render.get(target.index()),
+        }
+    }
+}
+
+/// Sets the view, deferred and sampler bind groups shared by all post process pipelines.
+pub(crate) fn set_view_bind_groups<'a>(
+    pass: &mut ComputePass<'a>,
+    view_bind_group: &'a BindGroup,
+    view_offsets: &[u32],
+    deferred_bind_group: &'a BindGroup,
+    sampler_bind_group: &'a BindGroup,
+) {
+    pass.set_bind_group(0, view_bind_group, view_offsets);
+    pass.set_bind_group(1, deferred_bind_group, &[]);
+    pass.set_bind_group(2, sampler_bind_group, &[]);
+}
+
 #[allow(clippy::type_complexity)]
 fn queue_post_process_bind_groups(
     mut commands: Commands,
     render_device: Res<RenderDevice>,
     pipeline: Res<PostProcessPipeline>,
     fsr_constants_uniforms: Res<ComponentUniforms<FsrConstantsUniform>>,
+    auto_exposure_uniforms: Res<ComponentUniforms<AutoExposureUniform>>,
+    color_grading_uniforms: Res<ComponentUniforms<ColorGradingUniform>>,
+    auto_exposure_buffers: Res<AutoExposureBuffers>,
+    fog_volumes: Res<FogVolumeRenderAssets>,
+    images: Res<RenderAssets<Image>>,
+    fallback: Res<FallbackImage>,
     query: Query<
         (
             Entity,
             &LightTextures,
             &PostProcessTextures,
             &HikariSettings,
+            Option<&HikariAutoExposure>,
+            Option<&HikariColorGrading>,
+            &ExtractedView,
         ),
         With<ExtractedCamera>,
     >,
 ) {
-    let fsr_constants_binding = match fsr_constants_uniforms.binding() {
-        Some(binding) => binding,
-        None => return,
+    let (fsr_constants_binding, auto_exposure_binding, color_grading_binding) = match (
+        fsr_constants_uniforms.binding(),
+        auto_exposure_uniforms.binding(),
+        color_grading_uniforms.binding(),
+    ) {
+        (Some(fsr_constants), Some(auto_exposure), Some(color_grading)) => {
+            (fsr_constants, auto_exposure, color_grading)
+        }
+        _ => return,
     };
 
-    for (entity, light, post_process, settings) in &query {
+    for (entity, light, post_process, settings, auto_exposure, color_grading, view) in &query {
+        let auto_exposure_buffer = match auto_exposure_buffers
+            .get(&entity)
+            .and_then(|buffer| buffer.binding())
+        {
+            Some(binding) => binding,
+            None => continue,
+        };
+
         let current = post_process.head;
         let previous = 1 - current;
 
@@ -908,7 +1311,7 @@ fn queue_post_process_bind_groups(
             ],
         });
 
-        let mut denoise_render = [0, 1, 2]
+        let mut denoise_render = [0, 1, 2, 3]
             .map(|id| {
                 render_device.create_bind_group(&BindGroupDescriptor {
                     label: None,
@@ -936,6 +1339,67 @@ fn queue_post_process_bind_groups(
                 })
             })
             .to_vec();
+        let denoise_fog = denoise_render.pop().filter(|_| !fog_volumes.is_empty());
+
+        let denoise_history = [0, 1, 2, 3].map(|id| {
+            render_device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                layout: &pipeline.denoise_history_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(
+                            &post_process.denoise_history[id][previous],
+                        ),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::TextureView(
+                            &post_process.denoise_moments[id][previous],
+                        ),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::TextureView(
+                            &post_process.denoise_history[id][current],
+                        ),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: BindingResource::TextureView(
+                            &post_process.denoise_moments[id][current],
+                        ),
+                    },
+                ],
+            })
+        });
+
+        let indirect_upsample = match settings.indirect_resolution {
+            IndirectResolution::Full => None,
+            _ if settings.indirect_bounces == 0 => None,
+            _ => Some(render_device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                layout: &pipeline.denoise_render_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&light.albedo),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::TextureView(&light.variance[2]),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::TextureView(&light.indirect_render),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: BindingResource::TextureView(&light.render[2]),
+                    },
+                ],
+            })),
+        };
 
         let (direct_render, emissive_render, mut indirect_render) = match settings.denoise {
             false => (&light.render[0], &light.render[1], &light.render[2]),
@@ -945,6 +1409,10 @@ fn queue_post_process_bind_groups(
                 &post_process.denoise_render[2],
             ),
         };
+        let fog_render = match denoise_fog {
+            Some(_) if settings.denoise => &post_process.denoise_render[3],
+            _ => &light.render[3],
+        };
 
         if settings.indirect_bounces == 0 {
             // Do not denoise when there is no indirect rendering pass.
@@ -969,6 +1437,10 @@ fn queue_post_process_bind_groups(
                     binding: 2,
                     resource: BindingResource::TextureView(indirect_render),
                 },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(fog_render),
+                },
             ],
         });
         let tone_mapping_output = render_device.create_bind_group(&BindGroupDescriptor {
@@ -980,6 +1452,57 @@ fn queue_post_process_bind_groups(
             }],
         });
 
+        let metering_mask = match auto_exposure
+            .and_then(|auto_exposure| images.get(&auto_exposure.metering_mask))
+        {
+            Some(image) => &image.texture_view,
+            None => &fallback.texture_view,
+        };
+        let auto_exposure = render_device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.auto_exposure_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: auto_exposure_binding.clone(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: auto_exposure_buffer,
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(metering_mask),
+                },
+            ],
+        });
+
+        // Fall back to the identity LUT until the one of the camera is loaded.
+        // The overlay inverts the tone mapping operator on HDR cameras, which a graded render would break.
+        let color_grading = color_grading
+            .filter(|color_grading| color_grading.enabled && !view.hdr)
+            .and_then(|color_grading| {
+                images
+                    .get(&color_grading.lut)
+                    .or_else(|| images.get(&IDENTITY_LUT_HANDLE.typed()))
+            })
+            .map(|lut| {
+                render_device.create_bind_group(&BindGroupDescriptor {
+                    label: None,
+                    layout: &pipeline.color_grading_layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: color_grading_binding.clone(),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::TextureView(&lut.texture_view),
+                        },
+                    ],
+                })
+            });
+
         let smaa = render_device.create_bind_group(&BindGroupDescriptor {
             label: None,
             layout: &pipeline.smaa_layout,
@@ -1009,7 +1532,7 @@ fn queue_post_process_bind_groups(
 
         let taa_input_texture = match settings.upscale {
             Upscale::SmaaTu4x { .. } => &post_process.upscale_output[0],
-            _ => &post_process.tone_mapping_output[current],
+            Upscale::None | Upscale::Fsr1 { .. } => &post_process.tone_mapping_output[current],
         };
         let taa = render_device.create_bind_group(&BindGroupDescriptor {
             label: None,
@@ -1034,9 +1557,15 @@ fn queue_post_process_bind_groups(
             }],
         });
 
-        let upscale_input_texture = match settings.taa {
-            Taa::Jasmine => &post_process.taa_output[current],
-            Taa::None => &post_process.tone_mapping_output[current],
+        let upscale_input_texture = match (settings.upscale, settings.taa) {
+            (_, Taa::Jasmine) => &post_process.taa_output[current],
+            (Upscale::SmaaTu4x { .. }, Taa::None) => &post_process.upscale_output[0],
+            (_, Taa::None) => &post_process.tone_mapping_output[current],
+        };
+        // Without sharpening, EASU after SMAA writes straight into the final texture.
+        let upscale_output_texture = match settings.upscale {
+            Upscale::SmaaTu4x { .. } => &post_process.upscale_output[1],
+            _ => &post_process.upscale_output[0],
         };
 
         let upscale = render_device.create_bind_group(&BindGroupDescriptor {
@@ -1058,7 +1587,7 @@ fn queue_post_process_bind_groups(
             layout: &pipeline.output_layout,
             entries: &[BindGroupEntry {
                 binding: 0,
-                resource: BindingResource::TextureView(&post_process.upscale_output[0]),
+                resource: BindingResource::TextureView(upscale_output_texture),
             }],
         });
 
@@ -1088,9 +1617,14 @@ fn queue_post_process_bind_groups(
         commands.entity(entity).insert(PostProcessBindGroup {
             sampler,
             denoise_internal,
+            indirect_upsample,
             denoise_render,
+            denoise_fog,
+            denoise_history,
             tone_mapping,
             tone_mapping_output,
+            auto_exposure,
+            color_grading,
             smaa,
             smaa_output,
             taa,
@@ -1112,9 +1646,14 @@ pub struct PostProcessNode {
         &'static PreviousViewUniformOffset,
         &'static ViewLightsUniformOffset,
         &'static DeferredBindGroup,
+        &'static LightTextures,
+        &'static PostProcessTextures,
         &'static PostProcessBindGroup,
         &'static DynamicUniformIndex<FsrConstantsUniform>,
+        &'static DynamicUniformIndex<AutoExposureUniform>,
+        &'static DynamicUniformIndex<ColorGradingUniform>,
         &'static HikariSettings,
+        Option<&'static HikariAutoExposure>,
     )>,
 }
 
@@ -1151,9 +1690,14 @@ impl Node for PostProcessNode {
             previous_view_uniform,
             view_lights,
             deferred_bind_group,
+            light,
+            post_process,
             post_process_bind_group,
             fsr_constants_uniform,
+            auto_exposure_uniform,
+            color_grading_uniform,
             settings,
+            auto_exposure,
         ) = match self.query.get_manual(world, entity) {
             Ok(query) => query,
             Err(_) => return Ok(()),
@@ -1170,51 +1714,105 @@ impl Node for PostProcessNode {
         let scale = settings.upscale.ratio().recip();
         let mut scaled_size = (scale * size.as_vec2()).ceil().as_uvec2();
 
-        let mut pass = render_context
-            .command_encoder
-            .begin_compute_pass(&ComputePassDescriptor::default());
-
-        pass.set_bind_group(
-            0,
-            view_bind_group,
-            &[
-                frame_uniform.index(),
-                view_uniform.offset,
-                previous_view_uniform.offset,
-                view_lights.offset,
-            ],
-        );
-        pass.set_bind_group(1, &deferred_bind_group.0, &[]);
-        pass.set_bind_group(2, &post_process_bind_group.sampler, &[]);
-
-        if settings.denoise {
+        let view_offsets = [
+            frame_uniform.index(),
+            view_uniform.offset,
+            previous_view_uniform.offset,
+            view_lights.offset,
+        ];
+
+        if let Some(indirect_upsample) = &post_process_bind_group.indirect_upsample {
+            let mut pass = render_context
+                .command_encoder
+                .begin_compute_pass(&ComputePassDescriptor::default());
+
+            set_view_bind_groups(
+                &mut pass,
+                view_bind_group,
+                &view_offsets,
+                &deferred_bind_group.0,
+                &post_process_bind_group.sampler,
+            );
             pass.set_bind_group(3, &post_process_bind_group.denoise_internal, &[]);
+            pass.set_bind_group(4, indirect_upsample, &[]);
 
-            let denoise_pipelines = [
-                pipelines.denoise_direct,
-                pipelines.denoise,
-                pipelines.denoise,
-            ];
-
-            for (render_bind_group, denoise) in post_process_bind_group
-                .denoise_render
-                .iter()
-                .zip(denoise_pipelines.iter())
+            if let Some(pipeline) = pipeline_cache.get_compute_pipeline(pipelines.indirect_upsample)
             {
-                pass.set_bind_group(4, render_bind_group, &[]);
+                pass.set_pipeline(pipeline);
 
-                if let Some(pipeline) = pipeline_cache.get_compute_pipeline(pipelines.demodulation)
-                {
-                    pass.set_pipeline(pipeline);
+                let count = (scaled_size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+                pass.dispatch_workgroups(count.x, count.y, 1);
+            }
+        }
 
-                    let count = (scaled_size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
-                    pass.dispatch_workgroups(count.x, count.y, 1);
-                }
+        if settings.denoise {
+            for target in DenoiseTarget::ALL {
+                let render_bind_group = match post_process_bind_group.denoise_render(target) {
+                    Some(bind_group) => bind_group,
+                    None => continue,
+                };
+                let (demodulation, remodulation) = match target {
+                    DenoiseTarget::Fog => (pipelines.demodulation_medium, None),
+                    _ => (pipelines.demodulation, Some(pipelines.remodulation)),
+                };
 
-                for pipeline in denoise
-                    .iter()
-                    .filter_map(|pipeline| pipeline_cache.get_compute_pipeline(*pipeline))
                 {
+                    let mut pass = render_context
+                        .command_encoder
+                        .begin_compute_pass(&ComputePassDescriptor::default());
+
+                    set_view_bind_groups(
+                        &mut pass,
+                        view_bind_group,
+                        &view_offsets,
+                        &deferred_bind_group.0,
+                        &post_process_bind_group.sampler,
+                    );
+                    pass.set_bind_group(3, &post_process_bind_group.denoise_internal, &[]);
+                    pass.set_bind_group(4, render_bind_group, &[]);
+
+                    if let Some(pipeline) = pipeline_cache.get_compute_pipeline(demodulation) {
+                        pass.set_pipeline(pipeline);
+
+                        let count = (scaled_size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+                        pass.dispatch_workgroups(count.x, count.y, 1);
+                    }
+                }
+
+                let input = DenoiserInput {
+                    view: entity,
+                    target,
+                    size: scaled_size,
+                    demodulated: &post_process.denoise_internal[0],
+                    variance: &post_process.denoise_internal_variance,
+                    albedo: &light.albedo,
+                    output: &post_process.denoise_render[target.index()],
+                };
+                world
+                    .resource::<ActiveDenoiser>()
+                    .denoise(world, render_context, &input)?;
+
+                // Media are not demodulated, so there is nothing to put back.
+                let remodulation = match remodulation {
+                    Some(remodulation) => remodulation,
+                    None => continue,
+                };
+
+                let mut pass = render_context
+                    .command_encoder
+                    .begin_compute_pass(&ComputePassDescriptor::default());
+
+                set_view_bind_groups(
+                    &mut pass,
+                    view_bind_group,
+                    &view_offsets,
+                    &deferred_bind_group.0,
+                    &post_process_bind_group.sampler,
+                );
+                pass.set_bind_group(3, &post_process_bind_group.denoise_internal, &[]);
+                pass.set_bind_group(4, render_bind_group, &[]);
+
+                if let Some(pipeline) = pipeline_cache.get_compute_pipeline(remodulation) {
                     pass.set_pipeline(pipeline);
 
                     let count = (scaled_size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
@@ -1223,8 +1821,42 @@ impl Node for PostProcessNode {
             }
         }
 
+        let mut pass = render_context
+            .command_encoder
+            .begin_compute_pass(&ComputePassDescriptor::default());
+
+        set_view_bind_groups(
+            &mut pass,
+            view_bind_group,
+            &view_offsets,
+            &deferred_bind_group.0,
+            &post_process_bind_group.sampler,
+        );
+
         pass.set_bind_group(3, &post_process_bind_group.tone_mapping, &[]);
         pass.set_bind_group(4, &post_process_bind_group.tone_mapping_output, &[]);
+        pass.set_bind_group(
+            5,
+            &post_process_bind_group.auto_exposure,
+            &[auto_exposure_uniform.index()],
+        );
+
+        if matches!(auto_exposure, Some(auto_exposure) if auto_exposure.enabled) {
+            if let Some(pipeline) =
+                pipeline_cache.get_compute_pipeline(pipelines.luminance_histogram)
+            {
+                pass.set_pipeline(pipeline);
+
+                let count = (scaled_size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+                pass.dispatch_workgroups(count.x, count.y, 1);
+            }
+
+            // A single workgroup reduces the histogram and adapts the exposure.
+            if let Some(pipeline) = pipeline_cache.get_compute_pipeline(pipelines.auto_exposure) {
+                pass.set_pipeline(pipeline);
+                pass.dispatch_workgroups(1, 1, 1);
+            }
+        }
 
         if let Some(pipeline) = pipeline_cache.get_compute_pipeline(pipelines.tone_mapping) {
             pass.set_pipeline(pipeline);
@@ -1233,6 +1865,18 @@ impl Node for PostProcessNode {
             pass.dispatch_workgroups(count.x, count.y, 1);
         }
 
+        // Grade the tone mapped render in place.
+        if let Some(color_grading) = &post_process_bind_group.color_grading {
+            pass.set_bind_group(3, color_grading, &[color_grading_uniform.index()]);
+
+            if let Some(pipeline) = pipeline_cache.get_compute_pipeline(pipelines.color_grading) {
+                pass.set_pipeline(pipeline);
+
+                let count = (scaled_size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+                pass.dispatch_workgroups(count.x, count.y, 1);
+            }
+        }
+
         if matches!(settings.upscale, Upscale::SmaaTu4x { .. }) {
             pass.set_bind_group(3, &post_process_bind_group.smaa, &[]);
             pass.set_bind_group(4, &post_process_bind_group.smaa_output, &[]);
@@ -1276,7 +1920,8 @@ impl Node for PostProcessNode {
             }
         }
 
-        if matches!(settings.upscale, Upscale::Fsr1 { .. }) {
+        let fsr1 = matches!(settings.upscale, Upscale::Fsr1 { .. });
+        if fsr1 || settings.upscale.smaa_easu() {
             pass.set_bind_group(0, &post_process_bind_group.sampler, &[]);
             pass.set_bind_group(
                 1,
@@ -1292,18 +1937,23 @@ impl Node for PostProcessNode {
                 pass.dispatch_workgroups(count.x, count.y, 1);
             }
 
-            pass.set_bind_group(
-                1,
-                &post_process_bind_group.upscale_sharpen,
-                &[fsr_constants_uniform.index()],
-            );
-            pass.set_bind_group(2, &post_process_bind_group.upscale_sharpen_output, &[]);
-
-            if let Some(pipeline) = pipeline_cache.get_compute_pipeline(pipelines.upscale_sharpen) {
-                pass.set_pipeline(pipeline);
+            // RCAS is skipped after SMAA, since the extrapolated edges are already sharp.
+            if fsr1 {
+                pass.set_bind_group(
+                    1,
+                    &post_process_bind_group.upscale_sharpen,
+                    &[fsr_constants_uniform.index()],
+                );
+                pass.set_bind_group(2, &post_process_bind_group.upscale_sharpen_output, &[]);
+
+                if let Some(pipeline) =
+                    pipeline_cache.get_compute_pipeline(pipelines.upscale_sharpen)
+                {
+                    pass.set_pipeline(pipeline);
 
-                let count = (size * 2 + 15) / 16;
-                pass.dispatch_workgroups(count.x, count.y, 1);
+                    let count = (size * 2 + 15) / 16;
+                    pass.dispatch_workgroups(count.x, count.y, 1);
+                }
             }
         }
 
@@ -1,4 +1,11 @@
 pub use crate::{
-    mesh_material::{GenericInstancePlugin, GenericMaterialPlugin},
-    HikariPlugin, HikariSettings, HikariUniversalSettings, Taa, Upscale,
+    denoiser::{HikariDenoiser, HikariDenoiserPlugin},
+    mesh_material::{
+        FogVolumeShape, GenericInstancePlugin, GenericMaterialPlugin, HikariDiskLight,
+        HikariFogVolume, HikariLightLink, HikariLightProfile, HikariMaterial, HikariRectLight,
+        HikariSphereLight, HikariSubsurface, IesProfile,
+    },
+    AdaptiveSampling, HikariAutoExposure, HikariColorGrading, HikariPlugin, HikariSettings,
+    HikariThinLens, HikariTonemapping, HikariUniversalSettings, IndirectResolution, MotionBlur,
+    PathDepth, RadianceCache, SpatialReuse, Taa, Upscale,
 };
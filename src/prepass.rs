@@ -1,6 +1,7 @@
 use crate::{
     mesh_material::{
         DynamicInstanceIndex, InstanceIndex, InstanceRenderAssets, PreviousMeshUniform,
+        ATTRIBUTE_UV_1,
     },
     view::{FrameUniform, PreviousViewUniform, PreviousViewUniformOffset, PreviousViewUniforms},
     HikariSettings, Taa, Upscale, PREPASS_SHADER_HANDLE,
@@ -45,6 +46,7 @@ pub const NORMAL_FORMAT: TextureFormat = TextureFormat::Rgba8Snorm;
 pub const DEPTH_GRADIENT_FORMAT: TextureFormat = TextureFormat::Rg32Float;
 pub const INSTANCE_MATERIAL_FORMAT: TextureFormat = TextureFormat::Rg32Float;
 pub const VELOCITY_UV_FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+pub const VERTEX_COLOR_UV_FORMAT: TextureFormat = TextureFormat::Rgba32Uint;
 
 pub struct PrepassPlugin;
 impl Plugin for PrepassPlugin {
@@ -182,15 +184,28 @@ impl SpecializedMeshPipeline for PrepassPipeline {
         key: Self::Key,
         layout: &MeshVertexBufferLayout,
     ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
-        let vertex_attributes = vec![
+        let mut vertex_attributes = vec![
             Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
             Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
-            Mesh::ATTRIBUTE_UV_0.at_shader_location(2),
         ];
+        let mut shader_defs = vec![];
+
+        if layout.contains(Mesh::ATTRIBUTE_UV_0) {
+            shader_defs.push("VERTEX_UVS".into());
+            vertex_attributes.push(Mesh::ATTRIBUTE_UV_0.at_shader_location(2));
+        }
+        if layout.contains(ATTRIBUTE_UV_1) {
+            shader_defs.push("VERTEX_UVS_1".into());
+            vertex_attributes.push(ATTRIBUTE_UV_1.at_shader_location(3));
+        }
+        if layout.contains(Mesh::ATTRIBUTE_COLOR) {
+            shader_defs.push("VERTEX_COLORS".into());
+            vertex_attributes.push(Mesh::ATTRIBUTE_COLOR.at_shader_location(4));
+        }
+
         let vertex_buffer_layout = layout.get_layout(&vertex_attributes)?;
         let bind_group_layout = vec![self.view_layout.clone(), self.mesh_layout.clone()];
 
-        let mut shader_defs = vec![];
         if key.temporal_anti_aliasing {
             shader_defs.push("TEMPORAL_ANTI_ALIASING".into());
         }
@@ -237,6 +252,11 @@ impl SpecializedMeshPipeline for PrepassPipeline {
                         blend: None,
                         write_mask: ColorWrites::ALL,
                     }),
+                    Some(ColorTargetState {
+                        format: VERTEX_COLOR_UV_FORMAT,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    }),
                 ],
             }),
             primitive: PrimitiveState {
@@ -303,6 +323,9 @@ pub struct PrepassTextures {
     pub previous_instance_material: Handle<Image>,
     #[texture(8, visibility(all))]
     pub previous_velocity_uv: Handle<Image>,
+    /// Vertex color as half floats in `xy`, and the second UV set in `zw`.
+    #[texture(9, visibility(all), sample_type = "u_int")]
+    pub vertex_color_uv: Handle<Image>,
 }
 
 impl PrepassTextures {
@@ -332,6 +355,7 @@ pub struct PreparedPrepassTextures<'a> {
     pub depth_gradient: &'a GpuImage,
     pub instance_material: &'a GpuImage,
     pub velocity_uv: &'a GpuImage,
+    pub vertex_color_uv: &'a GpuImage,
 }
 
 impl PrepassTextures {
@@ -345,6 +369,7 @@ impl PrepassTextures {
             depth_gradient: assets.get(&self.depth_gradient)?,
             instance_material: assets.get(&self.instance_material)?,
             velocity_uv: assets.get(&self.velocity_uv)?,
+            vertex_color_uv: assets.get(&self.vertex_color_uv)?,
         };
         Some(prepared)
     }
@@ -370,6 +395,7 @@ fn prepass_textures_system(
             };
             let texture_usage = TextureUsages::COPY_DST
                 | TextureUsages::TEXTURE_BINDING
+                | TextureUsages::STORAGE_BINDING
                 | TextureUsages::RENDER_ATTACHMENT;
 
             let create_texture = |texture_format| -> Image {
@@ -403,6 +429,7 @@ fn prepass_textures_system(
             let depth_gradient = images.add(create_texture(DEPTH_GRADIENT_FORMAT));
             let instance_material = images.add(create_texture(INSTANCE_MATERIAL_FORMAT));
             let velocity_uv = images.add(create_texture(VELOCITY_UV_FORMAT));
+            let vertex_color_uv = images.add(create_texture(VERTEX_COLOR_UV_FORMAT));
 
             let previous_position = images.add(create_texture(POSITION_FORMAT));
             let previous_normal = images.add(create_texture(NORMAL_FORMAT));
@@ -420,6 +447,7 @@ fn prepass_textures_system(
                 previous_normal,
                 previous_instance_material,
                 previous_velocity_uv,
+                vertex_color_uv,
             });
         }
     }
@@ -820,6 +848,11 @@ impl Node for PrepassNode {
                         resolve_target: None,
                         ops,
                     }),
+                    Some(RenderPassColorAttachment {
+                        view: &textures.vertex_color_uv.texture_view,
+                        resolve_target: None,
+                        ops,
+                    }),
                 ],
                 depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
                     view: depth,
@@ -0,0 +1,59 @@
+struct ColorGrading {
+    lift: vec3<f32>,
+    gamma: vec3<f32>,
+    gain: vec3<f32>,
+};
+
+@group(3) @binding(0)
+var<uniform> grading: ColorGrading;
+@group(3) @binding(1)
+var lut_texture: texture_3d<f32>;
+
+@group(4) @binding(0)
+var output_texture: texture_storage_2d<rgba16float, read_write>;
+
+fn load_lut(coords: vec3<i32>) -> vec3<f32> {
+    return textureLoad(lut_texture, coords, 0).rgb;
+}
+
+// Display grades, e.g. `.cube` files exported from Resolve, expect sRGB encoded input
+fn srgb_encode(color: vec3<f32>) -> vec3<f32> {
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, 12.92 * color, color <= vec3<f32>(0.0031308));
+}
+
+fn srgb_decode(color: vec3<f32>) -> vec3<f32> {
+    let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, color / 12.92, color <= vec3<f32>(0.04045));
+}
+
+// Float textures are not filterable everywhere, so interpolate the 8 nearest entries by hand
+fn sample_lut(color: vec3<f32>) -> vec3<f32> {
+    let size = textureDimensions(lut_texture);
+    let position = saturate(color) * vec3<f32>(size - vec3<i32>(1));
+    let lower = min(vec3<i32>(position), size - vec3<i32>(1));
+    let upper = min(lower + vec3<i32>(1), size - vec3<i32>(1));
+    let t = position - vec3<f32>(lower);
+
+    let c00 = mix(load_lut(lower), load_lut(vec3<i32>(upper.x, lower.y, lower.z)), t.x);
+    let c10 = mix(load_lut(vec3<i32>(lower.x, upper.y, lower.z)), load_lut(vec3<i32>(upper.x, upper.y, lower.z)), t.x);
+    let c01 = mix(load_lut(vec3<i32>(lower.x, lower.y, upper.z)), load_lut(vec3<i32>(upper.x, lower.y, upper.z)), t.x);
+    let c11 = mix(load_lut(vec3<i32>(lower.x, upper.y, upper.z)), load_lut(upper), t.x);
+    return mix(mix(c00, c10, t.y), mix(c01, c11, t.y), t.z);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn color_grading(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let size = textureDimensions(output_texture);
+    let coords = vec2<i32>(invocation_id.xy);
+    if any(coords >= size) {
+        return;
+    }
+
+    // Grading works on display encoded colors, and the output stays linear
+    let color = textureLoad(output_texture, coords);
+    var graded = sample_lut(srgb_encode(saturate(color.rgb)));
+    graded = grading.gain * (graded + grading.lift * (1.0 - graded));
+    graded = pow(max(graded, vec3<f32>(0.0)), 1.0 / grading.gamma);
+    textureStore(output_texture, coords, vec4<f32>(srgb_decode(graded), color.a));
+}
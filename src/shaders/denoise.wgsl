@@ -27,6 +27,15 @@ var render_texture: texture_2d<f32>;
 @group(4) @binding(3)
 var output_texture: texture_storage_2d<rgba16float, read_write>;
 
+@group(5) @binding(0)
+var previous_history_texture: texture_2d<f32>;
+@group(5) @binding(1)
+var previous_moments_texture: texture_2d<f32>;
+@group(5) @binding(2)
+var history_texture: texture_storage_2d<rgba16float, write>;
+@group(5) @binding(3)
+var moments_texture: texture_storage_2d<rgba32float, write>;
+
 let TAU: f32 = 6.283185307;
 let GOLDEN_RATIO: f32 = 1.618033989;
 
@@ -34,6 +43,10 @@ let F32_EPSILON: f32 = 1.1920929E-7;
 let F32_MAX: f32 = 3.402823466E+38;
 let U32_MAX: u32 = 0xFFFFFFFFu;
 
+let HISTORY_MAX_LENGTH: f32 = 32.0;
+let HISTORY_MIN_ALPHA: f32 = 0.1;
+let HISTORY_MIN_VARIANCE_LENGTH: f32 = 4.0;
+
 fn jittered_deferred_uv(uv: vec2<f32>) -> vec2<f32> {
     let texel_size = 1.0 / vec2<f32>(textureDimensions(position_texture));
     let ratio = frame.upscale_ratio - 1.0;
@@ -132,6 +145,66 @@ fn accumulate_variance(
     *sum_variance += frame.kernel[offset.y + 1][offset.x + 1] * max(variance, 0.0);
 }
 
+// Joint bilateral upsample of indirect lighting traced at a reduced resolution
+@compute @workgroup_size(8, 8, 1)
+fn indirect_upsample(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let input_size = textureDimensions(render_texture);
+    let output_size = textureDimensions(output_texture);
+    let coords = vec2<i32>(invocation_id.xy);
+    if any(coords >= output_size) {
+        return;
+    }
+
+    let uv = coords_to_uv(coords, output_size);
+    let deferred_uv = jittered_deferred_uv(uv);
+
+    let depth = textureSampleLevel(position_texture, nearest_sampler, deferred_uv, 0.0).w;
+    if depth < F32_EPSILON {
+        textureStore(output_texture, coords, vec4<f32>(0.0));
+        return;
+    }
+
+    let depth_gradient = textureSampleLevel(depth_gradient_texture, nearest_sampler, deferred_uv, 0.0).xy;
+    let normal = normalize(textureSampleLevel(normal_texture, nearest_sampler, deferred_uv, 0.0).xyz);
+
+    // Each input pixel was traced at one pixel of its block, picked by the same stratification as the light pass
+    let input_coords = uv * vec2<f32>(input_size) - 0.5;
+    let base_coords = vec2<i32>(floor(input_coords));
+    let bilinear = fract(input_coords);
+
+    var sum_radiance = vec3<f32>(0.0);
+    var sum_w = 0.0;
+    var fallback = vec3<f32>(0.0);
+    var fallback_w = 0.0;
+    for (var i = 0u; i < 4u; i += 1u) {
+        let offset = vec2<i32>(vec2<u32>(i & 1u, i >> 1u));
+        let sample_coords = clamp(base_coords + offset, vec2<i32>(0), input_size - 1);
+        let sample_uv = stratified_uv(sample_coords, input_size, frame.indirect_scale, frame.number);
+        let sample_deferred_uv = jittered_deferred_uv(sample_uv);
+
+        let sample_depth = textureSampleLevel(position_texture, nearest_sampler, sample_deferred_uv, 0.0).w;
+        let sample_normal = normalize(textureSampleLevel(normal_texture, nearest_sampler, sample_deferred_uv, 0.0).xyz);
+        let radiance = textureLoad(render_texture, sample_coords, 0).rgb;
+
+        let f = mix(1.0 - bilinear, bilinear, vec2<f32>(offset));
+        let w_bilinear = f.x * f.y;
+        let pixel_offset = (sample_uv - uv) * vec2<f32>(output_size);
+        let w = w_bilinear * normal_weight(normal, sample_normal) * depth_weight(depth, sample_depth, depth_gradient, pixel_offset);
+
+        if sample_depth > F32_EPSILON && w_bilinear > fallback_w {
+            fallback = radiance;
+            fallback_w = w_bilinear;
+        }
+
+        sum_radiance += w * radiance;
+        sum_w += w;
+    }
+
+    // Fall back to the nearest valid input when no neighbor lies on the same surface
+    let radiance = select(fallback, sum_radiance / sum_w, sum_w > 0.0001);
+    textureStore(output_texture, coords, vec4<f32>(radiance, 1.0));
+}
+
 @compute @workgroup_size(8, 8, 1)
 fn demodulation(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     let input_size = textureDimensions(render_texture);
@@ -141,11 +214,18 @@ fn demodulation(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     let uv = coords_to_uv(coords, output_size);
     let deferred_uv = jittered_deferred_uv(uv);
 
+    let render = textureSampleLevel(render_texture, nearest_sampler, uv, 0.0);
+
+#ifdef MEDIUM
+    // Scattered radiance doesn't depend on the surface albedo, and the transmittance in alpha is noise-free
+    let color = render;
+#else
     let albedo = textureSampleLevel(albedo_texture, nearest_sampler, deferred_uv, 0.0).rgb;
-    var irradiance = textureSampleLevel(render_texture, nearest_sampler, uv, 0.0).rgb;
+    var irradiance = render.rgb;
     irradiance = select(irradiance / albedo, vec3<f32>(0.0), albedo < vec3<f32>(0.01));
 
     let color = vec4<f32>(irradiance, 1.0);
+#endif
     textureStore(internal_texture_0, coords, color);
 
     var sum_variance = 0.0;
@@ -161,6 +241,116 @@ fn demodulation(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     textureStore(internal_variance, coords, vec4<f32>(sum_variance));
 }
 
+// Whether the previous frame saw the same surface at the given texel of the history
+fn history_hit(
+    coords: vec2<i32>,
+    size: vec2<i32>,
+    position_depth: vec4<f32>,
+    normal: vec3<f32>,
+    instance: f32,
+) -> bool {
+    if any(coords < vec2<i32>(0)) || any(coords >= size) {
+        return false;
+    }
+
+    let uv = coords_to_uv(coords, size);
+    let previous_position_depth = textureSampleLevel(previous_position_texture, nearest_sampler, uv, 0.0);
+
+#ifdef MEDIUM
+    // The background is lit by media as well, and is only missed when it becomes a surface or vice versa
+    if position_depth.w < F32_EPSILON || previous_position_depth.w < F32_EPSILON {
+        return position_depth.w < F32_EPSILON && previous_position_depth.w < F32_EPSILON;
+    }
+#else
+    if previous_position_depth.w < F32_EPSILON {
+        return false;
+    }
+
+    let previous_normal = normalize(textureSampleLevel(previous_normal_texture, nearest_sampler, uv, 0.0).xyz);
+    if dot(normal, previous_normal) < 0.9 {
+        return false;
+    }
+#endif
+
+    let previous_instance = textureSampleLevel(previous_instance_material_texture, nearest_sampler, uv, 0.0).x;
+    let position_miss_threshold = frame.position_miss_threshold * distance(position_depth.xyz, view.world_position.xyz);
+    let position_miss = distance(position_depth.xyz, previous_position_depth.xyz) > position_miss_threshold;
+    return instance_weight(instance, previous_instance) > 0.5 && !position_miss;
+}
+
+// Temporal accumulation of the demodulated irradiance, with moments-based variance estimation (SVGF 4.1, 4.2)
+@compute @workgroup_size(8, 8, 1)
+fn temporal_accumulation(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let output_size = textureDimensions(output_texture);
+    let coords = vec2<i32>(invocation_id.xy);
+    let uv = coords_to_uv(coords, output_size);
+    let deferred_uv = jittered_deferred_uv(uv);
+
+    let position_depth = textureSampleLevel(position_texture, nearest_sampler, deferred_uv, 0.0);
+    let normal = normalize(textureSampleLevel(normal_texture, nearest_sampler, deferred_uv, 0.0).xyz);
+    let instance = textureSampleLevel(instance_material_texture, nearest_sampler, deferred_uv, 0.0).x;
+    let velocity = textureSampleLevel(velocity_uv_texture, nearest_sampler, deferred_uv, 0.0).xy;
+
+    var color = textureLoad(internal_texture_0, coords);
+    if any_is_nan_vec4(color) || any(color > vec4<f32>(F32_MAX)) {
+        color = vec4<f32>(0.0);
+    }
+
+#ifndef MEDIUM
+    if position_depth.w < F32_EPSILON {
+        textureStore(history_texture, coords, vec4<f32>(0.0));
+        textureStore(moments_texture, coords, vec4<f32>(0.0));
+        return;
+    }
+#endif
+
+    // Bilinear reprojection, with the taps on other surfaces left out
+    let previous_texel = (uv - velocity) * vec2<f32>(output_size) - 0.5;
+    let base_coords = vec2<i32>(floor(previous_texel));
+    let f = fract(previous_texel);
+
+    var previous_color = vec4<f32>(0.0);
+    var previous_moments = vec3<f32>(0.0);
+    var sum_w = 0.0;
+    for (var i = 0u; i < 4u; i += 1u) {
+        let offset = vec2<i32>(i32(i & 1u), i32(i >> 1u));
+        let tap_coords = base_coords + offset;
+        if !history_hit(tap_coords, output_size, position_depth, normal, instance) {
+            continue;
+        }
+
+        let bilinear = select(1.0 - f, f, offset == vec2<i32>(1));
+        let w = bilinear.x * bilinear.y;
+        previous_color += w * textureLoad(previous_history_texture, tap_coords, 0);
+        previous_moments += w * textureLoad(previous_moments_texture, tap_coords, 0).xyz;
+        sum_w += w;
+    }
+
+    let lum = luminance(color.rgb);
+    var moments = vec2<f32>(lum, lum * lum);
+    var history_length = 1.0;
+
+    if sum_w > 0.01 {
+        previous_color /= sum_w;
+        previous_moments /= sum_w;
+
+        history_length = min(previous_moments.z + 1.0, HISTORY_MAX_LENGTH);
+        let alpha = max(1.0 / history_length, HISTORY_MIN_ALPHA);
+        color = mix(previous_color, color, alpha);
+        moments = mix(previous_moments.xy, moments, alpha);
+    }
+
+    textureStore(internal_texture_0, coords, color);
+    textureStore(history_texture, coords, color);
+    textureStore(moments_texture, coords, vec4<f32>(moments, history_length, 0.0));
+
+    // Young histories keep the spatial variance estimate from demodulation
+    if history_length >= HISTORY_MIN_VARIANCE_LENGTH {
+        let variance = max(moments.y - moments.x * moments.x, 0.0);
+        textureStore(internal_variance, coords, vec4<f32>(variance));
+    }
+}
+
 fn accumulate_irradiance(
     output_size: vec2<i32>,
     coords: vec2<i32>,
@@ -196,7 +386,12 @@ fn accumulate_irradiance(
     let sample_instance = textureSampleLevel(instance_material_texture, nearest_sampler, sample_deferred_uv, 0.0).x;
     let sample_luminance = luminance(irradiance);
 
+#ifdef MEDIUM
+    // Media in front of the surface don't follow its orientation
+    let w_normal = 1.0;
+#else
     let w_normal = normal_weight(normal, sample_normal);
+#endif
     let w_depth = depth_weight(depth, sample_depth, depth_gradient, vec2<f32>(offset));
     let w_instance = instance_weight(instance, sample_instance);
     let w_luminance = luminance_weight(lum, sample_luminance, variance);
@@ -225,13 +420,16 @@ fn denoise(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     let normal = normalize(textureSampleLevel(normal_texture, nearest_sampler, deferred_uv, 0.0).xyz);
     let instance = textureSampleLevel(instance_material_texture, nearest_sampler, deferred_uv, 0.0).x;
 
+#ifndef MEDIUM
     if depth < F32_EPSILON {
         store_output(coords, vec4<f32>(0.0));
         return;
     }
+#endif
 
     let variance = textureLoad(internal_variance, coords).x;
-    var irradiance = load_input(coords).rgb;
+    let center = load_input(coords);
+    var irradiance = center.rgb;
 
     var sum_irradiance = irradiance * frame.kernel[1][1];
     var sum_w = frame.kernel[1][1];
@@ -279,7 +477,11 @@ fn denoise(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     }
 #endif
 
+#ifdef MEDIUM
+    var color = vec4<f32>(irradiance, center.a);
+#else
     var color = vec4<f32>(irradiance, 1.0);
+#endif
 
 #ifdef DENOISE_LEVEL_3
     // let velocity = textureLoad(velocity_uv_texture, deferred_coords, 0).xy;
@@ -310,10 +512,23 @@ fn denoise(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     // let mixed_color = mix(color, previous_color, 0.5);
     // color = select(mixed_color, color, any_is_nan_vec4(mixed_color) || previous_color.a == 0.0);
     // textureStore(radiance_texture, coords, color);
-
-    let albedo = textureSampleLevel(albedo_texture, nearest_sampler, deferred_uv, 0.0);
-    color *= albedo;
 #endif
 
     store_output(coords, color);
 }
+
+@compute @workgroup_size(8, 8, 1)
+fn remodulation(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let output_size = textureDimensions(output_texture);
+    let coords = vec2<i32>(invocation_id.xy);
+    if any(coords >= output_size) {
+        return;
+    }
+
+    let uv = coords_to_uv(coords, output_size);
+    let deferred_uv = jittered_deferred_uv(uv);
+
+    let albedo = textureSampleLevel(albedo_texture, nearest_sampler, deferred_uv, 0.0);
+    let color = textureLoad(output_texture, coords) * albedo;
+    textureStore(output_texture, coords, color);
+}
@@ -23,6 +23,18 @@ var noise_texture: binding_array<texture_2d<f32>>;
 @group(4) @binding(1)
 var noise_sampler: sampler;
 
+// The prepass textures, overwritten by the primary tracing pass
+@group(1) @binding(10)
+var primary_position_texture: texture_storage_2d<rgba32float, read_write>;
+@group(1) @binding(11)
+var primary_normal_texture: texture_storage_2d<rgba8snorm, write>;
+@group(1) @binding(12)
+var primary_instance_material_texture: texture_storage_2d<rg32float, write>;
+@group(1) @binding(13)
+var primary_velocity_uv_texture: texture_storage_2d<rgba32float, write>;
+@group(1) @binding(14)
+var primary_vertex_color_uv_texture: texture_storage_2d<rgba32uint, write>;
+
 @group(5) @binding(0)
 var albedo_texture: texture_storage_2d<rgba16float, read_write>;
 @group(5) @binding(1)
@@ -74,6 +86,34 @@ var<storage, read_write> previous_spatial_reservoir_buffer: Reservoirs;
 @group(6) @binding(3)
 var<storage, read_write> spatial_reservoir_buffer: Reservoirs;
 
+struct RadianceCacheCell {
+    radiance: vec4<f32>,
+    checksum: u32,
+    // Checksum of the cell updated this frame, which may evict the resolved one
+    claim: atomic<u32>,
+    // Radiance sums in fixed point and the sample count of this frame
+    accumulation: array<atomic<u32>, 4>,
+};
+
+struct RadianceCache {
+    data: array<RadianceCacheCell>,
+};
+
+@group(6) @binding(4)
+var<storage, read_write> radiance_cache_buffer: RadianceCache;
+
+struct AdaptiveStatistics {
+    variance_sum: array<atomic<u32>, 2>,
+    pixel_count: array<atomic<u32>, 2>,
+};
+
+struct AdaptiveStatisticsBuffer {
+    data: array<AdaptiveStatistics, 2>,
+};
+
+@group(6) @binding(5)
+var<storage, read_write> adaptive_statistics: AdaptiveStatisticsBuffer;
+
 fn unpack_reservoir(packed: PackedReservoir) -> Reservoir {
     var r: Reservoir;
 
@@ -235,26 +275,51 @@ let RAY_BIAS: f32 = 0.02;
 let DISTANCE_MAX: f32 = 65535.0;
 let NOISE_TEXTURE_COUNT: u32 = 16u;
 let GOLDEN_RATIO: f32 = 1.618033989;
-let POSITION_MISS_THRESHOLD: f32 = 0.5;
-let MAX_VARIANCE: f32 = 10.0;
 
 let DONT_EXCLUDE: u32 = 0xFFFFFFFFu;
 let DONT_SAMPLE_DIRECTIONAL_LIGHT: u32 = 0xFFFFFFFFu;
 let DONT_SAMPLE_EMISSIVE: u32 = 0x80000000u;
 let SAMPLE_ALL_EMISSIVE: u32 = 0xFFFFFFFFu;
+let AREA_LIGHT_FLAG: u32 = 0x40000000u;
+// Marks the index of a fog volume as a light receiver
+let MEDIUM_RECEIVER_FLAG: u32 = 0x20000000u;
 
-#ifdef EMISSIVE_LIT
-let SPATIAL_REUSE_COUNT: u32 = 8u;
-let SPATIAL_REUSE_RANGE: f32 = 10.0;
-#else
-let SPATIAL_REUSE_COUNT: u32 = 16u;
-let SPATIAL_REUSE_RANGE: f32 = 20.0;
-#endif
-let SPATIAL_REUSE_TAPS: u32 = 4u;
+let AREA_LIGHT_RECT: u32 = 0u;
+let AREA_LIGHT_DISK: u32 = 1u;
+let AREA_LIGHT_SPHERE: u32 = 2u;
+
+let IES_VERTICAL_RESOLUTION: u32 = 32u;
+let IES_HORIZONTAL_RESOLUTION: u32 = 16u;
 
 let DIRECT_VALIDATION_FRAME_SAMPLE_THRESHOLD: u32 = 4u;
+
+let MATERIAL_UNLIT: u32 = 1u;
+let MATERIAL_DOUBLE_SIDED: u32 = 2u;
+let MATERIAL_CULL_BACK: u32 = 4u;
+let MATERIAL_CULL_FRONT: u32 = 8u;
+
+let LOBE_DIFFUSE: u32 = 0u;
+let LOBE_SPECULAR: u32 = 1u;
+let LOBE_TRANSMISSION: u32 = 2u;
+let LOBE_CLEARCOAT: u32 = 3u;
+let LOBE_SHEEN: u32 = 4u;
+let LOBE_COUNT: u32 = 5u;
+let RUSSIAN_ROULETTE_MAX_SURVIVAL: f32 = 0.95;
+
+let RADIANCE_CACHE_CAPACITY: u32 = 262144u;
+let RADIANCE_CACHE_MIN_SAMPLES: f32 = 4.0;
+let RADIANCE_CACHE_MAX_SAMPLES: f32 = 64.0;
+let RADIANCE_CACHE_FIXED_POINT_SCALE: f32 = 256.0;
 let SPATIAL_VARIANCE_SAMPLE_THRESHOLD: u32 = 4u;
 
+let ADAPTIVE_PASS_EMISSIVE: u32 = 0u;
+let ADAPTIVE_PASS_INDIRECT: u32 = 1u;
+let ADAPTIVE_VARIANCE_SCALE: f32 = 256.0;
+let ADAPTIVE_VARIANCE_WEIGHT: f32 = 0.9;
+
+let FOG_VOLUME_GLOBAL: u32 = 0u;
+let FOG_VOLUME_BOX: u32 = 1u;
+
 // -------- TRACING     ---------
 struct Ray {
     origin: vec3<f32>,
@@ -285,16 +350,30 @@ struct Surface {
     metallic: f32,
     roughness: f32,
     occlusion: f32,
+    clearcoat: f32,
+    clearcoat_roughness: f32,
+    sheen_color: vec3<f32>,
+    sheen_roughness: f32,
+    anisotropy: f32,
+    anisotropy_rotation: f32,
 };
 
 struct HitInfo {
     position: vec4<f32>,
     normal: vec3<f32>,
     uv: vec2<f32>,
+    uv_1: vec2<f32>,
+    color: vec4<f32>,
     instance_index: u32,
     material_index: u32,
 };
 
+struct AreaLightSample {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    p: f32,
+};
+
 struct LightCandidate {
     direction: vec3<f32>,
     max_distance: f32,
@@ -303,20 +382,64 @@ struct LightCandidate {
     p: f32,
 };
 
-fn instance_position_world_to_local(instance: Instance, p: vec3<f32>) -> vec3<f32> {
-    let inverse_model = transpose(instance.inverse_transpose_model);
+// Time of the current path within the shutter, from 0.0 (current frame) towards 1.0 (previous frame)
+var<private> shutter_time: f32;
+
+// Each pixel draws its own time for its paths, so that neighbouring pixels cover the whole shutter within one frame.
+// Seeded by the deferred pixel, so that paths continue at the time their primary hit was traced at.
+fn sample_shutter_time(coords: vec2<i32>, size: vec2<i32>) {
+    let seed = pcg_hash(u32(coords.x + size.x * coords.y) ^ pcg_hash(frame.number));
+    shutter_time = frame.shutter * random_float(seed ^ 3u);
+}
+
+// Blending the matrices moves every point linearly between its two poses,
+// so instance bounds that enclose both poses also enclose the whole motion.
+fn instance_model(instance: Instance) -> mat4x4<f32> {
+    return instance.model * (1.0 - shutter_time) + instance.previous_model * shutter_time;
+}
+
+fn affine_inverse(m: mat4x4<f32>) -> mat4x4<f32> {
+    let a = m[0].xyz;
+    let b = m[1].xyz;
+    let c = m[2].xyz;
+    let inverse = transpose(mat3x3<f32>(cross(b, c), cross(c, a), cross(a, b))) * (1.0 / dot(a, cross(b, c)));
+    let translation = -(inverse * m[3].xyz);
+    return mat4x4<f32>(
+        vec4<f32>(inverse[0], 0.0),
+        vec4<f32>(inverse[1], 0.0),
+        vec4<f32>(inverse[2], 0.0),
+        vec4<f32>(translation, 1.0)
+    );
+}
+
+fn instance_inverse_model(instance: Instance) -> mat4x4<f32> {
+    if shutter_time == 0.0 {
+        return transpose(instance.inverse_transpose_model);
+    }
+    return affine_inverse(instance_model(instance));
+}
+
+fn instance_position_world_to_local(inverse_model: mat4x4<f32>, p: vec3<f32>) -> vec3<f32> {
     let position = inverse_model * vec4<f32>(p, 1.0);
     return position.xyz / position.w;
 }
 
-fn instance_direction_world_to_local(instance: Instance, p: vec3<f32>) -> vec3<f32> {
-    let inverse_model = transpose(instance.inverse_transpose_model);
+fn instance_direction_world_to_local(inverse_model: mat4x4<f32>, p: vec3<f32>) -> vec3<f32> {
     let direction = inverse_model * vec4<f32>(p, 0.0);
     return direction.xyz;
 }
 
+// Faces of the instance to be skipped by rays, in terms of the cull flags of its material.
+fn instance_cull_mode(instance: Instance) -> u32 {
+    let flags = material_buffer[instance.material].flags;
+    if frame.backface_culling == 0u || (flags & MATERIAL_DOUBLE_SIDED) != 0u {
+        return 0u;
+    }
+    return flags & (MATERIAL_CULL_BACK | MATERIAL_CULL_FRONT);
+}
+
 fn instance_position_local_to_world(instance: Instance, p: vec3<f32>) -> vec3<f32> {
-    let model = instance.model;
+    let model = instance_model(instance);
     let position = model * vec4<f32>(p, 1.0);
     return position.xyz / position.w;
 }
@@ -328,11 +451,12 @@ fn instance_normal_local_to_world(instance: Instance, n: vec3<f32>) -> vec3<f32>
     // Unreal Engine, Godot, and more all use the mikktspace method. Do not change this code
     // unless you really know what you are doing.
     // http://www.mikktspace.com/
+    let inverse_transpose_model = transpose(instance_inverse_model(instance));
     return normalize(
         mat3x3<f32>(
-            instance.inverse_transpose_model[0].xyz,
-            instance.inverse_transpose_model[1].xyz,
-            instance.inverse_transpose_model[2].xyz
+            inverse_transpose_model[0].xyz,
+            inverse_transpose_model[1].xyz,
+            inverse_transpose_model[2].xyz
         ) * n
     );
 }
@@ -361,7 +485,7 @@ fn intersects_aabb(ray: Ray, aabb: Aabb) -> f32 {
     return t;
 }
 
-fn intersects_triangle(ray: Ray, tri: array<PrimitiveVertex, 3>) -> Intersection {
+fn intersects_triangle(ray: Ray, tri: array<PrimitiveVertex, 3>, cull_mode: u32) -> Intersection {
     var result: Intersection;
     result.distance = F32_MAX;
 
@@ -374,6 +498,11 @@ fn intersects_triangle(ray: Ray, tri: array<PrimitiveVertex, 3>) -> Intersection
         return result;
     }
 
+    // Counter-clockwise triangles are front facing, on which `det` is positive
+    if (det < 0.0 && (cull_mode & MATERIAL_CULL_BACK) != 0u) || (det > 0.0 && (cull_mode & MATERIAL_CULL_FRONT) != 0u) {
+        return result;
+    }
+
     let inv_det = 1.0 / det;
     let ao = ray.origin - tri[0].position;
     let u = dot(ao, u_vec) * inv_det;
@@ -397,7 +526,7 @@ fn intersects_triangle(ray: Ray, tri: array<PrimitiveVertex, 3>) -> Intersection
     return result;
 }
 
-fn traverse_bottom(hit: ptr<function, Hit>, ray: Ray, mesh: MeshIndex, early_distance: f32) -> bool {
+fn traverse_bottom(hit: ptr<function, Hit>, ray: Ray, mesh: MeshIndex, early_distance: f32, cull_mode: u32) -> bool {
     var intersected = false;
     var index = 0u;
     for (; index < mesh.node.y;) {
@@ -412,7 +541,7 @@ fn traverse_bottom(hit: ptr<function, Hit>, ray: Ray, mesh: MeshIndex, early_dis
             aabb.max = max(vertices[0].position, max(vertices[1].position, vertices[2].position));
 
             if intersects_aabb(ray, aabb) < (*hit).intersection.distance {
-                let intersection = intersects_triangle(ray, vertices);
+                let intersection = intersects_triangle(ray, vertices, cull_mode);
                 if intersection.distance < (*hit).intersection.distance {
                     (*hit).intersection = intersection;
                     (*hit).primitive_index = primitive_index;
@@ -457,12 +586,13 @@ fn traverse_top(ray: Ray, max_distance: f32, early_distance: f32, exclude_instan
             aabb.max = instance.max;
 
             if instance_index != exclude_instance && intersects_aabb(ray, aabb) < hit.intersection.distance {
+                let inverse_model = instance_inverse_model(instance);
                 var r: Ray;
-                r.origin = instance_position_world_to_local(instance, ray.origin);
-                r.direction = instance_direction_world_to_local(instance, ray.direction);
+                r.origin = instance_position_world_to_local(inverse_model, ray.origin);
+                r.direction = instance_direction_world_to_local(inverse_model, ray.direction);
                 r.inv_direction = 1.0 / r.direction;
 
-                if traverse_bottom(&hit, r, instance.mesh, early_distance) {
+                if traverse_bottom(&hit, r, instance.mesh, early_distance, instance_cull_mode(instance)) {
                     hit.instance_index = instance_index;
                     if hit.intersection.distance < early_distance {
                         return hit;
@@ -510,8 +640,19 @@ fn hit_info(ray: Ray, hit: Hit) -> HitInfo {
         let uv2 = vec2<f32>(v2.u, v2.v);
         let uv = hit.intersection.uv;
         info.uv = uv0 + uv.x * (uv1 - uv0) + uv.y * (uv2 - uv0);
+        let uv_1_0 = unpack2x16float(v0.uv_1);
+        let uv_1_1 = unpack2x16float(v1.uv_1);
+        let uv_1_2 = unpack2x16float(v2.uv_1);
+        info.uv_1 = uv_1_0 + uv.x * (uv_1_1 - uv_1_0) + uv.y * (uv_1_2 - uv_1_0);
+        let color0 = unpack4x8unorm(v0.color);
+        let color1 = unpack4x8unorm(v1.color);
+        let color2 = unpack4x8unorm(v2.color);
+        info.color = color0 + uv.x * (color1 - color0) + uv.y * (color2 - color0);
         info.normal = v0.normal + uv.x * (v1.normal - v0.normal) + uv.y * (v2.normal - v0.normal);
         info.normal = instance_normal_local_to_world(instance, info.normal);
+        if (material_buffer[instance.material].flags & MATERIAL_DOUBLE_SIDED) != 0u && dot(info.normal, ray.direction) > 0.0 {
+            info.normal = -info.normal;
+        }
 
         info.position = vec4<f32>(ray.origin + ray.direction * hit.intersection.distance, 1.0);
         info.material_index = instance.material;
@@ -540,6 +681,22 @@ fn sample_uniform_disk(rand: vec2<f32>) -> vec2<f32> {
     return vec2<f32>(r * cos(theta), r * sin(theta));
 }
 
+// Uniform point on the aperture of the thin lens, in view space.
+// A polygonal aperture is a fan of triangles around the center; pick one, then a point in it.
+fn sample_aperture(rand: vec2<f32>) -> vec2<f32> {
+    if frame.lens_blade_count < 3u {
+        return frame.lens_radius * sample_uniform_disk(rand);
+    }
+
+    let blades = f32(frame.lens_blade_count);
+    let blade = min(floor(rand.x * blades), blades - 1.0);
+    let s = rand.x * blades - blade;
+    let a = TAU * blade / blades;
+    let b = TAU * (blade + 1.0) / blades;
+    let edge = mix(vec2<f32>(cos(a), sin(a)), vec2<f32>(cos(b), sin(b)), rand.y);
+    return frame.lens_radius * sqrt(s) * edge;
+}
+
 // Cosine weight sampling, with pdf
 fn sample_cosine_hemisphere(rand: vec2<f32>) -> vec4<f32> {
     let t = sample_uniform_disk(rand);
@@ -548,6 +705,24 @@ fn sample_cosine_hemisphere(rand: vec2<f32>) -> vec4<f32> {
     return vec4<f32>(direction, pdf);
 }
 
+fn pcg_hash(input: u32) -> u32 {
+    let state = input * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Samples a reflected direction off the GGX distribution of normals,
+// with roughnesses along the tangent and the bitangent of the basis
+fn sample_ggx_reflection(rand: vec2<f32>, V: vec3<f32>, basis: mat3x3<f32>, roughness: vec2<f32>) -> vec3<f32> {
+    let phi = atan2(roughness.y * sin(TAU * rand.y), roughness.x * cos(TAU * rand.y));
+    let slope = vec2<f32>(cos(phi), sin(phi)) / roughness;
+    let tan2_theta = rand.x / max((1.0 - rand.x) * dot(slope, slope), F32_EPSILON);
+    let cos_theta = inverseSqrt(1.0 + tan2_theta);
+    let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+    let H = basis * vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+    return reflect(-V, H);
+}
+
 // Samples a random direction in a cone with given half apex, also returns pdf
 fn sample_uniform_cone(rand: vec2<f32>, cos_angle: f32) -> vec4<f32> {
     let z = 1.0 - (1.0 - cos_angle) * rand.x;  // [cos(angle), 1.0]
@@ -564,6 +739,137 @@ fn sample_uniform_triangle_barycentric(rand: vec2<f32>) -> vec2<f32> {
     return vec2<f32>(1.0 - srx, rand.y * srx);
 }
 
+// Samples a point on a rectangle by solid angle, also returns pdf
+// https://www.arnoldrenderer.com/research/egsr2013_spherical_rectangle.pdf
+fn sample_spherical_rectangle(
+    rand: vec2<f32>,
+    position: vec3<f32>,
+    corner: vec3<f32>,
+    edge_x: vec3<f32>,
+    edge_y: vec3<f32>,
+) -> vec4<f32> {
+    let length_x = length(edge_x);
+    let length_y = length(edge_y);
+    let x = edge_x / length_x;
+    let y = edge_y / length_y;
+    var z = cross(x, y);
+
+    let d = corner - position;
+    var z0 = dot(d, z);
+    if z0 > 0.0 {
+        z = -z;
+        z0 = -z0;
+    }
+    let x0 = dot(d, x);
+    let y0 = dot(d, y);
+    let x1 = x0 + length_x;
+    let y1 = y0 + length_y;
+
+    let v00 = vec3<f32>(x0, y0, z0);
+    let v01 = vec3<f32>(x0, y1, z0);
+    let v10 = vec3<f32>(x1, y0, z0);
+    let v11 = vec3<f32>(x1, y1, z0);
+
+    let n0 = normalize(cross(v00, v10));
+    let n1 = normalize(cross(v10, v11));
+    let n2 = normalize(cross(v11, v01));
+    let n3 = normalize(cross(v01, v00));
+
+    let g0 = acos(clamp(-dot(n0, n1), -1.0, 1.0));
+    let g1 = acos(clamp(-dot(n1, n2), -1.0, 1.0));
+    let g2 = acos(clamp(-dot(n2, n3), -1.0, 1.0));
+    let g3 = acos(clamp(-dot(n3, n0), -1.0, 1.0));
+
+    let k = TAU - g2 - g3;
+    let solid_angle = g0 + g1 - k;
+    if solid_angle <= F32_EPSILON {
+        return vec4<f32>(corner, 0.0);
+    }
+
+    // Sample the x coordinate
+    let au = rand.x * solid_angle + k;
+    let fu = (cos(au) * n0.z - n2.z) / sin(au);
+    let cu = clamp(select(-1.0, 1.0, fu > 0.0) / sqrt(fu * fu + n0.z * n0.z), -1.0, 1.0);
+    let xu = clamp(-(cu * z0) / max(sqrt(1.0 - cu * cu), F32_EPSILON), x0, x1);
+
+    // Sample the y coordinate
+    let dd = sqrt(xu * xu + z0 * z0);
+    let h0 = y0 / sqrt(dd * dd + y0 * y0);
+    let h1 = y1 / sqrt(dd * dd + y1 * y1);
+    let hv = h0 + rand.y * (h1 - h0);
+    let hv2 = hv * hv;
+    let yv = select(y1, hv * dd / sqrt(1.0 - hv2), hv2 < 1.0 - F32_EPSILON);
+
+    return vec4<f32>(position + xu * x + yv * y + z0 * z, 1.0 / solid_angle);
+}
+
+// Samples a point on an area light, with pdf in solid angle measure.
+// Spheres and rects are sampled by solid angle; disks are sampled by area.
+fn sample_area_light(rand: vec2<f32>, light: AreaLight, position: vec3<f32>) -> AreaLightSample {
+    var s: AreaLightSample;
+    s.p = 0.0;
+
+    if light.kind == AREA_LIGHT_SPHERE {
+        let delta = light.position - position;
+        let d2 = dot(delta, delta);
+        let r2 = dot(light.axis_x, light.axis_x);
+        if d2 <= r2 {
+            return s;
+        }
+
+        let cone = sample_uniform_cone(rand, sqrt((d2 - r2) / d2));
+        let direction = normal_basis(normalize(delta)) * cone.xyz;
+        let b = dot(direction, delta);
+        let t = b - sqrt(max(b * b - d2 + r2, 0.0));
+
+        s.position = position + t * direction;
+        s.normal = normalize(s.position - light.position);
+        s.p = cone.w;
+        return s;
+    }
+
+    // Planar lights emit towards local -Z
+    var normal = -normalize(cross(light.axis_x, light.axis_y));
+    if light.kind == AREA_LIGHT_RECT {
+        let corner = light.position - light.axis_x - light.axis_y;
+        let rect_sample = sample_spherical_rectangle(rand, position, corner, 2.0 * light.axis_x, 2.0 * light.axis_y);
+        s.position = rect_sample.xyz;
+        s.p = rect_sample.w;
+    } else {
+        let disk = sample_uniform_disk(rand);
+        s.position = light.position + disk.x * light.axis_x + disk.y * light.axis_y;
+        let delta = s.position - position;
+        let cos_light = abs(dot(normalize(delta), normal));
+        s.p = select(0.0, dot(delta, delta) / (light.area * cos_light), cos_light > F32_EPSILON);
+    }
+
+    let facing = dot(position - s.position, normal) > 0.0;
+    if !facing {
+        if light.two_sided == 0u {
+            s.p = 0.0;
+        }
+        normal = -normal;
+    }
+    s.normal = normal;
+    return s;
+}
+
+// Light groups of the receiving instance or fog volume; other receivers are in all groups
+fn receiver_light_groups(receiver: u32) -> u32 {
+    if receiver < arrayLength(&instance_buffer) {
+        return instance_buffer[receiver].light_groups;
+    }
+    let volume = receiver & ~MEDIUM_RECEIVER_FLAG;
+    if (receiver & MEDIUM_RECEIVER_FLAG) != 0u && volume < fog_volume_buffer.count {
+        return fog_volume_buffer.data[volume].light_groups;
+    }
+    return U32_MAX;
+}
+
+fn light_linked(groups: u32, include: u32, exclude: u32) -> bool {
+    return (groups & include) != 0u && (groups & exclude) == 0u;
+}
+
 fn cone_pdf(cone: vec4<f32>, direction: vec3<f32>) -> f32 {
     return select(INV_TAU / (1.0 - cone.w), 0.0, (cone.w - 1.0 > 0.0) || (dot(direction, cone.xyz) < cone.w));
 }
@@ -591,8 +897,9 @@ fn compute_emissive_cone(
     return cone;
 }
 
+// Emissive radiance is the linear emissive color scaled by the emissive strength
 fn compute_emissive_radiance(emissive: vec4<f32>) -> vec3<f32> {
-    return 255.0 * emissive.a * emissive.rgb;
+    return emissive.a * emissive.rgb;
 }
 
 // Choose a light source based on luminance
@@ -608,6 +915,9 @@ fn select_light_candidate(
     candidate.min_distance = DISTANCE_MAX;
     candidate.emissive_instance = DONT_SAMPLE_EMISSIVE;
 
+    // A zero normal stands for a point inside a medium, which receives light from all directions
+    let in_medium = all(normal == vec3<f32>(0.0));
+
     let directional = lights.directional_lights[0];
     let cone = compute_directional_cone(directional);
     let rand_direction = normal_basis(cone.xyz) * sample_uniform_cone(rand.zw, cone.w).xyz;
@@ -620,6 +930,8 @@ fn select_light_candidate(
         return candidate;
     }
 
+    let groups = receiver_light_groups(instance);
+
     // Traverse the LBVH to pick one emissive within range
     var emissive: Emissive;
     var count = 0.0;
@@ -635,7 +947,8 @@ fn select_light_candidate(
             aabb.min = current_emissive.position - current_emissive.radius;
             aabb.max = current_emissive.position + current_emissive.radius;
 
-            if instance != current_emissive.instance && inside_aabb(position, aabb) {
+            let linked = light_linked(groups, current_emissive.light_include, current_emissive.light_exclude);
+            if instance != current_emissive.instance && linked && inside_aabb(position, aabb) {
                 rand_1d = fract(rand_1d + GOLDEN_RATIO);
                 count += 1.0;
                 if rand_1d < 1.0 / count {
@@ -656,15 +969,60 @@ fn select_light_candidate(
         }
     }
 
-    if candidate.emissive_instance != DONT_SAMPLE_EMISSIVE {
+    // Area lights join the same candidate stream as emissive instances.
+    // They are not in the LBVH, so every call visits all of them: the cost is linear in the count of area lights,
+    // which suits scenes with a few of them. Many lights are better expressed as emissive instances.
+    var area_light: AreaLight;
+    for (var light_index = 0u; light_index < area_light_buffer.count; light_index += 1u) {
+        let current_light = area_light_buffer.data[light_index];
+        let delta = position - current_light.position;
+        let linked = light_linked(groups, current_light.light_include, current_light.light_exclude);
+        if linked && dot(delta, delta) < current_light.range * current_light.range {
+            rand_1d = fract(rand_1d + GOLDEN_RATIO);
+            count += 1.0;
+            if rand_1d < 1.0 / count {
+                candidate.emissive_instance = AREA_LIGHT_FLAG | light_index;
+                area_light = current_light;
+            }
+        }
+    }
+
+    if candidate.emissive_instance != DONT_SAMPLE_EMISSIVE && (candidate.emissive_instance & AREA_LIGHT_FLAG) != 0u {
+        let light_sample = sample_area_light(rand.zw, area_light, position);
+        let delta = light_sample.position - position;
+        let distance = length(delta);
+
+        candidate.direction = delta / distance;
+        let profile = area_light_profile(area_light, -candidate.direction);
+        let facing = in_medium || dot(candidate.direction, normal) > 0.0;
+        if light_sample.p > 0.0 && facing && any(profile > vec3<f32>(0.0)) {
+            (*info).instance_index = candidate.emissive_instance;
+            (*info).material_index = U32_MAX;
+            (*info).position = vec4<f32>(light_sample.position, 1.0);
+            (*info).normal = light_sample.normal;
+            (*info).uv = vec2<f32>(0.0);
+
+            candidate.max_distance = distance - RAY_BIAS;
+            candidate.min_distance = distance - 0.1;
+            candidate.p = light_sample.p / count;
+        } else {
+            // Fallback to sample directional
+            *info = empty_hit_info(position, rand_direction);
+
+            candidate.emissive_instance = DONT_SAMPLE_EMISSIVE;
+            candidate.direction = rand_direction;
+            candidate.p = 1.0;
+        }
+    } else if candidate.emissive_instance != DONT_SAMPLE_EMISSIVE {
         // Sample a point on the instance's surface
         // Select a primitive based using the alias table
         let alias_index = min(u32(rand.x * f32(emissive.alias_table.y)), emissive.alias_table.y - 1u);
         let alias_entry = alias_table_buffer[emissive.alias_table.x + alias_index];
-        let primitive_index = select(alias_index, alias_entry.index, rand.y < alias_entry.prob);
+        let bucket_index = select(alias_index, alias_entry.index, rand.y < alias_entry.prob);
+        let bucket = alias_table_buffer[emissive.alias_table.x + bucket_index];
 
         let emissive_instance = instance_buffer[candidate.emissive_instance];
-        let v = primitive_buffer[emissive_instance.mesh.primitive + primitive_index].vertices;
+        let v = primitive_buffer[emissive_instance.mesh.primitive + bucket.primitive].vertices;
         let b = sample_uniform_triangle_barycentric(rand.zw);
         let p = instance_position_local_to_world(emissive_instance, b.x * v[0].position + b.y * v[1].position + (1.0 - b.x - b.y) * v[2].position);
 
@@ -678,13 +1036,15 @@ fn select_light_candidate(
         ray.origin = position + normal * RAY_BIAS;
         ray.direction = normalize(p - position);
 
+        let inverse_model = instance_inverse_model(emissive_instance);
         var r: Ray;
-        r.origin = instance_position_world_to_local(emissive_instance, ray.origin);
-        r.direction = instance_direction_world_to_local(emissive_instance, ray.direction);
+        r.origin = instance_position_world_to_local(inverse_model, ray.origin);
+        r.direction = instance_direction_world_to_local(inverse_model, ray.direction);
         r.inv_direction = 1.0 / r.direction;
 
         candidate.direction = ray.direction;
-        if dot(candidate.direction, normal) > 0.0 && traverse_bottom(&hit, r, emissive_instance.mesh, 0.0) {
+        let facing = in_medium || dot(candidate.direction, normal) > 0.0;
+        if facing && traverse_bottom(&hit, r, emissive_instance.mesh, 0.0, 0u) {
             hit.instance_index = emissive.instance;
             *info = hit_info(ray, hit);
 
@@ -692,7 +1052,8 @@ fn select_light_candidate(
             candidate.min_distance = hit.intersection.distance - 0.1;
             let delta = (*info).position.xyz - position;
 
-            candidate.p = dot(delta, delta) / (abs(dot(ray.direction, (*info).normal) * emissive.surface_area));
+            // Convert the area density of the sampled primitive to solid angle
+            candidate.p = dot(delta, delta) * bucket.density / abs(dot(ray.direction, (*info).normal));
             candidate.p = candidate.p / count;
         } else {
             // Fallback to sample directional        
@@ -726,110 +1087,309 @@ fn calculate_view(
     return V;
 }
 
+fn retreive_layers(material: Material, surface: ptr<function, Surface>) {
+    (*surface).clearcoat = material.clearcoat;
+    (*surface).clearcoat_roughness = perceptualRoughnessToRoughness(material.clearcoat_perceptual_roughness);
+    (*surface).sheen_color = material.sheen_color;
+    (*surface).sheen_roughness = perceptualRoughnessToRoughness(material.sheen_perceptual_roughness);
+    (*surface).anisotropy = material.anisotropy_strength;
+    (*surface).anisotropy_rotation = material.anisotropy_rotation;
+}
+
+// Unlit surfaces emit their base color and don't respond to any light.
+fn retreive_unlit(material: Material, surface: ptr<function, Surface>) {
+    if (material.flags & MATERIAL_UNLIT) == 0u {
+        return;
+    }
+
+    (*surface).emissive = vec4<f32>((*surface).base_color.rgb, 1.0);
+    (*surface).base_color = vec4<f32>(vec3<f32>(0.0), (*surface).base_color.a);
+    (*surface).metallic = 0.0;
+    (*surface).reflectance = 0.0;
+    (*surface).clearcoat = 0.0;
+    (*surface).sheen_color = vec3<f32>(0.0);
+}
+
+// Applies the UV transform of the material, e.g., from `KHR_texture_transform`.
+fn material_uv(material: Material, uv: vec2<f32>) -> vec2<f32> {
+    return (material.uv_transform * vec3<f32>(uv, 1.0)).xy;
+}
+
 #ifdef NO_TEXTURE
-fn retreive_surface(material_index: u32, uv: vec2<f32>) -> Surface {
+fn retreive_surface(material_index: u32, uv: vec2<f32>, uv_1: vec2<f32>, color: vec4<f32>) -> Surface {
     var surface: Surface;
     let material = material_buffer[material_index];
 
-    surface.base_color = material.base_color;
+    surface.base_color = material.base_color * color;
     surface.emissive = material.emissive;
     surface.metallic = material.metallic;
     surface.occlusion = 1.0;
     surface.roughness = perceptualRoughnessToRoughness(material.perceptual_roughness);
     surface.reflectance = material.reflectance;
+    retreive_layers(material, &surface);
+    retreive_unlit(material, &surface);
 
     return surface;
 }
 
 fn retreive_emissive(material_index: u32, uv: vec2<f32>) -> vec4<f32> {
-    var emissive = material_buffer[material_index].emissive;
-    return emissive;
+    let material = material_buffer[material_index];
+    if (material.flags & MATERIAL_UNLIT) != 0u {
+        return vec4<f32>(material.base_color.rgb, 1.0);
+    }
+    return material.emissive;
 }
 #else
-fn retreive_surface(material_index: u32, uv: vec2<f32>) -> Surface {
+fn retreive_surface(material_index: u32, uv: vec2<f32>, uv_1: vec2<f32>, color: vec4<f32>) -> Surface {
     var surface: Surface;
     let material = material_buffer[material_index];
+    let texture_uv = material_uv(material, uv);
+    let texture_uv_1 = material_uv(material, uv_1);
 
-    surface.base_color = material.base_color;
+    surface.base_color = material.base_color * color;
     var id = material.base_color_texture;
     if id != U32_MAX {
-        surface.base_color *= textureSampleLevel(textures[id], samplers[id], uv, 0.0);
+        surface.base_color *= textureSampleLevel(textures[id], samplers[id], texture_uv, 0.0);
     }
 
     surface.emissive = material.emissive;
     id = material.emissive_texture;
     if id != U32_MAX {
-        surface.emissive *= textureSampleLevel(textures[id], samplers[id], uv, 0.0);
+        let emissive_color = textureSampleLevel(textures[id], samplers[id], texture_uv, 0.0).rgb;
+        surface.emissive = vec4<f32>(surface.emissive.rgb * emissive_color, surface.emissive.a);
     }
 
     surface.metallic = material.metallic;
     id = material.metallic_roughness_texture;
     if id != U32_MAX {
-        surface.metallic *= textureSampleLevel(textures[id], samplers[id], uv, 0.0).r;
+        surface.metallic *= textureSampleLevel(textures[id], samplers[id], texture_uv, 0.0).r;
     }
 
     surface.occlusion = 1.0;
     id = material.occlusion_texture;
     if id != U32_MAX {
-        surface.occlusion = textureSampleLevel(textures[id], samplers[id], uv, 0.0).r;
+        surface.occlusion = textureSampleLevel(textures[id], samplers[id], texture_uv_1, 0.0).r;
     }
 
     surface.roughness = perceptualRoughnessToRoughness(material.perceptual_roughness);
     surface.reflectance = material.reflectance;
+    retreive_layers(material, &surface);
+    retreive_unlit(material, &surface);
 
     return surface;
 }
 
 fn retreive_emissive(material_index: u32, uv: vec2<f32>) -> vec4<f32> {
     let material = material_buffer[material_index];
+    let texture_uv = material_uv(material, uv);
+
+    // Unlit surfaces emit their base color, as in `retreive_unlit`
+    if (material.flags & MATERIAL_UNLIT) != 0u {
+        var base_color = material.base_color;
+        let id = material.base_color_texture;
+        if id != U32_MAX {
+            base_color *= textureSampleLevel(textures[id], samplers[id], texture_uv, 0.0);
+        }
+        return vec4<f32>(base_color.rgb, 1.0);
+    }
 
     var emissive = material.emissive;
     let id = material.emissive_texture;
     if id != U32_MAX {
-        emissive *= textureSampleLevel(textures[id], samplers[id], uv, 0.0);
+        let emissive_color = textureSampleLevel(textures[id], samplers[id], texture_uv, 0.0).rgb;
+        emissive = vec4<f32>(emissive.rgb * emissive_color, emissive.a);
     }
 
     return emissive;
 }
 #endif
 
+// Surface of the primary hit, with the vertex color and the second UV set written by the prepass.
+fn retreive_deferred_surface(material_index: u32, uv: vec2<f32>, coords: vec2<i32>) -> Surface {
+    let packed = textureLoad(vertex_color_uv_texture, coords, 0);
+    let color = vec4<f32>(unpack2x16float(packed.x), unpack2x16float(packed.y));
+    let uv_1 = bitcast<vec2<f32>>(packed.zw);
+    return retreive_surface(material_index, uv, uv_1, color);
+}
+
+fn ies_intensity(offset: u32, vertical: u32, horizontal: u32) -> f32 {
+    let index = offset + (horizontal % IES_HORIZONTAL_RESOLUTION) * IES_VERTICAL_RESOLUTION + vertical;
+    return light_profile_buffer.data[index];
+}
+
+// Modulation of the radiance of an area light by its IES profile or cookie,
+// given the direction from the light towards the receiver
+fn area_light_profile(light: AreaLight, direction: vec3<f32>) -> vec3<f32> {
+    let x = normalize(light.axis_x);
+    let y = normalize(light.axis_y);
+    let z = -normalize(cross(light.axis_x, light.axis_y));
+    let local = vec3<f32>(dot(direction, x), dot(direction, y), dot(direction, z));
+
+    var factor = vec3<f32>(1.0);
+
+    if light.profile != U32_MAX {
+        let theta = acos(clamp(local.z, -1.0, 1.0)) / PI;
+        let phi = fract(atan2(local.y, local.x) * INV_TAU + 1.0);
+
+        let v = theta * f32(IES_VERTICAL_RESOLUTION - 1u);
+        let h = phi * f32(IES_HORIZONTAL_RESOLUTION);
+        let v0 = min(u32(v), IES_VERTICAL_RESOLUTION - 1u);
+        let v1 = min(v0 + 1u, IES_VERTICAL_RESOLUTION - 1u);
+        let h0 = u32(h) % IES_HORIZONTAL_RESOLUTION;
+        let h1 = h0 + 1u;
+
+        let c0 = mix(ies_intensity(light.profile, v0, h0), ies_intensity(light.profile, v1, h0), fract(v));
+        let c1 = mix(ies_intensity(light.profile, v0, h1), ies_intensity(light.profile, v1, h1), fract(v));
+        factor *= mix(c0, c1, fract(h));
+    }
+
+#ifndef NO_TEXTURE
+    if light.cookie != U32_MAX {
+        let id = light.cookie;
+        let uv = 0.5 + 0.5 * local.xy / (max(local.z, F32_EPSILON) * light.cookie_scale);
+        let inside = local.z > 0.0 && all(abs(uv - 0.5) < vec2<f32>(0.5));
+        let cookie = textureSampleLevel(textures[id], samplers[id], uv, 0.0).rgb;
+        factor *= select(vec3<f32>(0.0), cookie, inside);
+    }
+#endif
+
+    return factor;
+}
+
+// Tangent frame of the anisotropy direction
+fn anisotropy_basis(N: vec3<f32>, rotation: f32) -> mat3x3<f32> {
+    let basis = normal_basis(N);
+    let T = cos(rotation) * basis[0] + sin(rotation) * basis[1];
+    return mat3x3<f32>(T, cross(N, T), N);
+}
+
+// Roughnesses along the tangent and the bitangent, as in `KHR_materials_anisotropy`
+fn anisotropic_roughness(surface: Surface) -> vec2<f32> {
+    let tangent_roughness = mix(surface.roughness, 1.0, surface.anisotropy * surface.anisotropy);
+    return vec2<f32>(tangent_roughness, surface.roughness);
+}
+
+fn D_GGX_anisotropic(at: f32, ab: f32, ToH: f32, BoH: f32, NoH: f32) -> f32 {
+    let a2 = at * ab;
+    let d = vec3<f32>(ab * ToH, at * BoH, a2 * NoH);
+    let b2 = a2 / dot(d, d);
+    return a2 * b2 * b2 * (1.0 / PI);
+}
+
+fn V_SmithGGXCorrelated_anisotropic(at: f32, ab: f32, ToV: f32, BoV: f32, ToL: f32, BoL: f32, NoV: f32, NoL: f32) -> f32 {
+    let lambdaV = NoL * length(vec3<f32>(at * ToV, ab * BoV, NoV));
+    let lambdaL = NoV * length(vec3<f32>(at * ToL, ab * BoL, NoL));
+    return 0.5 / (lambdaV + lambdaL);
+}
+
+fn specular_anisotropic(F0: vec3<f32>, surface: Surface, H: vec3<f32>, L: vec3<f32>, N: vec3<f32>, V: vec3<f32>, NoV: f32, NoL: f32, NoH: f32, LoH: f32) -> vec3<f32> {
+    let basis = anisotropy_basis(N, surface.anisotropy_rotation);
+    let roughness = anisotropic_roughness(surface);
+    let D = D_GGX_anisotropic(roughness.x, roughness.y, dot(basis[0], H), dot(basis[1], H), NoH);
+    let Vis = V_SmithGGXCorrelated_anisotropic(
+        roughness.x,
+        roughness.y,
+        dot(basis[0], V),
+        dot(basis[1], V),
+        dot(basis[0], L),
+        dot(basis[1], L),
+        NoV,
+        NoL
+    );
+    return D * Vis * fresnel(F0, LoH);
+}
+
+// Charlie sheen distribution with Neubelt visibility, see https://google.github.io/filament/Filament.html#materialsystem/clothmodel
+fn D_Charlie(roughness: f32, NoH: f32) -> f32 {
+    let inv_alpha = 1.0 / roughness;
+    let sin2h = max(1.0 - NoH * NoH, 0.0078125);
+    return (2.0 + inv_alpha) * pow(sin2h, inv_alpha * 0.5) / (2.0 * PI);
+}
+
+fn V_Neubelt(NoV: f32, NoL: f32) -> f32 {
+    return saturate(1.0 / (4.0 * (NoL + NoV - NoL * NoV)));
+}
+
+// Kelemen visibility of the clearcoat layer
+fn V_Kelemen(LoH: f32) -> f32 {
+    return 0.25 / max(LoH * LoH, 0.0001);
+}
+
+// Fitted directional albedo of the sheen lobe, within 0.05 of the integral
+fn sheen_albedo(surface: Surface, NoV: f32) -> vec3<f32> {
+    let r = max(surface.sheen_roughness, 0.05);
+    let albedo = 0.53 * pow(r, -0.27) * exp(-1.3 * pow(r, -0.54) * pow(NoV, 1.25));
+    return surface.sheen_color * min(albedo, 1.0);
+}
+
+fn clearcoat_albedo(surface: Surface, NoV: f32) -> f32 {
+    return surface.clearcoat * EnvBRDFApprox(vec3<f32>(0.04), surface.clearcoat_roughness, NoV).x;
+}
+
+// Fraction of the energy that passes through the sheen and clearcoat layers down to the base
+fn base_layer_scale(surface: Surface, NoV: f32) -> f32 {
+    let sheen = sheen_albedo(surface, NoV);
+    return (1.0 - max(sheen.r, max(sheen.g, sheen.b))) * (1.0 - clearcoat_albedo(surface, NoV));
+}
+
 fn lit(
     radiance: vec3<f32>,
     diffuse_color: vec3<f32>,
-    roughness: f32,
     F0: vec3<f32>,
     L: vec3<f32>,
     N: vec3<f32>,
     V: vec3<f32>,
+    surface: Surface,
 ) -> vec3<f32> {
+    let roughness = surface.roughness;
+
     let H = normalize(L + V);
     let NoL = saturate(dot(N, L));
     let NoH = saturate(dot(N, H));
     let LoH = saturate(dot(L, H));
     let NdotV = max(dot(N, V), 0.0001);
 
-    let R = reflect(-V, N);
-
     let diffuse = diffuse_color * Fd_Burley(roughness, NdotV, NoL, LoH);
-    let specular_intensity = 1.0;
-    let specular_light = specular(F0, roughness, H, NdotV, NoL, NoH, LoH, specular_intensity);
+    var specular_light: vec3<f32>;
+    if surface.anisotropy > 0.0 {
+        specular_light = specular_anisotropic(F0, surface, H, L, N, V, NdotV, NoL, NoH, LoH);
+    } else {
+        let specular_intensity = 1.0;
+        specular_light = specular(F0, roughness, H, NdotV, NoL, NoH, LoH, specular_intensity);
+    }
+    var color = specular_light + diffuse;
+
+    // The sheen layer sits on top of the base, taking away the energy it reflects
+    if any(surface.sheen_color > vec3<f32>(0.0)) {
+        let sheen = sheen_albedo(surface, NdotV);
+        color *= 1.0 - max(sheen.r, max(sheen.g, sheen.b));
+        color += surface.sheen_color * D_Charlie(max(surface.sheen_roughness, 0.05), NoH) * V_Neubelt(NdotV, NoL);
+    }
 
-    return (specular_light + diffuse) * radiance * NoL;
+    // The clearcoat layer is a dielectric interface over everything else
+    if surface.clearcoat > 0.0 {
+        let Fc = F_Schlick(0.04, 1.0, LoH) * surface.clearcoat;
+        let clearcoat = D_GGX(surface.clearcoat_roughness, NoH, H) * V_Kelemen(LoH) * Fc;
+        color = color * (1.0 - Fc) + clearcoat;
+    }
+
+    return color * radiance * NoL;
 }
 
 fn ambient(
     diffuse_color: vec3<f32>,
-    roughness: f32,
-    occlusion: f32,
     F0: vec3<f32>,
     N: vec3<f32>,
     V: vec3<f32>,
+    surface: Surface,
 ) -> vec3<f32> {
     let NdotV = max(dot(N, V), 0.0001);
 
     let diffuse_ambient = EnvBRDFApprox(diffuse_color, 1.0, NdotV);
-    let specular_ambient = EnvBRDFApprox(F0, roughness, NdotV);
-    return occlusion * (diffuse_ambient + specular_ambient) * lights.ambient_color.rgb;
+    let specular_ambient = EnvBRDFApprox(F0, surface.roughness, NdotV);
+    let base = (diffuse_ambient + specular_ambient) * base_layer_scale(surface, NdotV);
+    let layers = sheen_albedo(surface, NdotV) + clearcoat_albedo(surface, NdotV);
+    return surface.occlusion * (base + layers) * lights.ambient_color.rgb;
 }
 
 fn input_radiance(
@@ -838,6 +1398,7 @@ fn input_radiance(
     sample_directional: bool,
     sample_emissive: u32,
     sample_ambient: bool,
+    receiver: u32,
 ) -> vec4<f32> {
     var radiance = vec3<f32>(0.0);
     var ambient = 0.0;
@@ -857,9 +1418,18 @@ fn input_radiance(
         }
     } else {
         // Input radiance is emissive, but bounced radiance is not added here
-        if sample_emissive == info.instance_index {
-            let emissive = retreive_emissive(info.material_index, info.uv);
-            radiance = compute_emissive_radiance(emissive);
+        let groups = receiver_light_groups(receiver);
+        if sample_emissive == info.instance_index && (info.instance_index & AREA_LIGHT_FLAG) != 0u {
+            let light = area_light_buffer.data[info.instance_index & ~AREA_LIGHT_FLAG];
+            if light_linked(groups, light.light_include, light.light_exclude) {
+                radiance = light.radiance * area_light_profile(light, -ray.direction);
+            }
+        } else if sample_emissive == info.instance_index {
+            let instance = instance_buffer[info.instance_index];
+            if light_linked(groups, instance.light_include, instance.light_exclude) {
+                let emissive = retreive_emissive(info.material_index, info.uv);
+                radiance = compute_emissive_radiance(emissive);
+            }
         }
     }
 
@@ -875,15 +1445,13 @@ fn shading(
 ) -> vec3<f32> {
     let base_color = surface.base_color.rgb;
     let reflectance = surface.reflectance;
-    let roughness = surface.roughness;
     let metallic = surface.metallic;
-    let occlusion = surface.occlusion;
 
     let F0 = 0.16 * reflectance * reflectance * (1.0 - metallic) + base_color * metallic;
     let diffuse_color = base_color * (1.0 - metallic);
 
-    let lit_radiance = lit(input_radiance.rgb, diffuse_color, roughness, F0, L, N, V);
-    let ambient_radiance = ambient(diffuse_color, roughness, occlusion, F0, N, V);
+    let lit_radiance = lit(input_radiance.rgb, diffuse_color, F0, L, N, V, surface);
+    let ambient_radiance = ambient(diffuse_color, F0, N, V, surface);
     return mix(lit_radiance, ambient_radiance, 1.0 - input_radiance.a);
 }
 
@@ -904,10 +1472,305 @@ fn env_brdf(
 
     let diffuse_ambient = EnvBRDFApprox(diffuse_color, 1.0, NdotV);
     let specular_ambient = EnvBRDFApprox(F0, roughness, NdotV);
-    return occlusion * (diffuse_ambient + specular_ambient);
+    let base = (diffuse_ambient + specular_ambient) * base_layer_scale(surface, NdotV);
+    let layers = sheen_albedo(surface, NdotV) + clearcoat_albedo(surface, NdotV);
+    return occlusion * (base + layers);
+}
+
+// Directional albedos of the diffuse, specular, transmission, clearcoat and sheen lobes
+fn lobe_albedo(
+    V: vec3<f32>,
+    N: vec3<f32>,
+    surface: Surface,
+) -> array<vec3<f32>, 5> {
+    let base_color = surface.base_color.rgb;
+    let reflectance = surface.reflectance;
+    let metallic = surface.metallic;
+    let opacity = surface.base_color.a;
+
+    let NdotV = max(dot(N, V), 0.0001);
+    let F0 = 0.16 * reflectance * reflectance * (1.0 - metallic) + base_color * metallic;
+    let diffuse_color = base_color * (1.0 - metallic);
+
+    let reflection = opacity * surface.occlusion;
+    let base = reflection * base_layer_scale(surface, NdotV);
+    let diffuse = base * EnvBRDFApprox(diffuse_color, 1.0, NdotV);
+    let specular = base * EnvBRDFApprox(F0, surface.roughness, NdotV);
+    let transmission = vec3<f32>(1.0 - opacity);
+    let clearcoat = vec3<f32>(reflection * clearcoat_albedo(surface, NdotV));
+    let sheen = reflection * sheen_albedo(surface, NdotV);
+    return array<vec3<f32>, 5>(diffuse, specular, transmission, clearcoat, sheen);
+}
+
+// Clearcoat bounces count towards the specular depth, and sheen bounces towards the diffuse depth
+fn lobe_depth_index(lobe: u32) -> u32 {
+    if lobe == LOBE_CLEARCOAT {
+        return LOBE_SPECULAR;
+    }
+    if lobe == LOBE_SHEEN {
+        return LOBE_DIFFUSE;
+    }
+    return lobe;
+}
+
+// Probability density of the Burley normalized diffusion profile over the radius,
+// which is a mixture of two exponentials
+fn burley_profile_pdf(r: f32, d: vec3<f32>) -> vec3<f32> {
+    return 0.25 * exp(-r / d) / d + 0.75 * exp(-r / (3.0 * d)) / (3.0 * d);
+}
+
+// Diffuse radiance of an instance with subsurface scattering.
+// Light is gathered at a point on the same instance around the shading position, sampled by the diffusion profile
+// of a random color channel and found by a probe ray along the normal.
+fn subsurface_radiance(
+    seed: u32,
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    instance_index: u32,
+    surface: Surface,
+    sample_directional: bool,
+    select_light_instance: u32,
+) -> vec3<f32> {
+    let subsurface = instance_buffer[instance_index].subsurface;
+    let d = max(subsurface.rgb * subsurface.a, vec3<f32>(0.0001));
+
+    var rand: array<f32, 8>;
+    for (var i = 0u; i < 8u; i += 1u) {
+        rand[i] = min(random_float(pcg_hash(seed + i)), 0.999999);
+    }
+
+    let channel = min(u32(rand[0] * 3.0), 2u);
+    let scale = select(1.0, 3.0, rand[1] >= 0.25) * d[channel];
+    let r = -scale * log(1.0 - rand[2]);
+    let phi = TAU * rand[3];
+
+    // One sample serves all channels, weighted by their profiles over the averaged density
+    let pdf = burley_profile_pdf(r, d);
+    let weights = pdf * 3.0 / max(pdf.r + pdf.g + pdf.b, F32_EPSILON);
+
+    var ray: Ray;
+    var hit: Hit;
+    var info: HitInfo;
+
+    let height = max(r, RAY_BIAS);
+    ray.origin = position + normal_basis(normal) * vec3<f32>(r * cos(phi), r * sin(phi), height);
+    ray.direction = -normal;
+    ray.inv_direction = 1.0 / ray.direction;
+    hit = traverse_top(ray, 2.0 * height, 0.0, DONT_EXCLUDE);
+
+    var probe_position = position;
+    var probe_normal = normal;
+    var probe_weights = vec3<f32>(1.0);
+    if hit.instance_index == instance_index {
+        info = hit_info(ray, hit);
+        probe_position = info.position.xyz;
+        probe_normal = info.normal;
+        probe_weights = weights;
+    }
+
+    let light_rand = vec4<f32>(rand[4], rand[5], rand[6], rand[7]);
+    let candidate = select_light_candidate(light_rand, probe_position, probe_normal, select_light_instance, &info);
+    var trace_condition = dot(candidate.direction, probe_normal) > 0.0 && candidate.p > 0.0;
+    trace_condition = trace_condition && (sample_directional || candidate.emissive_instance != DONT_SAMPLE_EMISSIVE);
+    if !trace_condition {
+        return vec3<f32>(0.0);
+    }
+
+    ray.origin = probe_position + probe_normal * RAY_BIAS;
+    ray.direction = candidate.direction;
+    ray.inv_direction = 1.0 / ray.direction;
+    hit = traverse_top(ray, candidate.max_distance, candidate.min_distance, candidate.emissive_instance);
+    occlude_hit_info(ray, hit, &info);
+
+    let emissive_instance = select(candidate.emissive_instance, DONT_SAMPLE_EMISSIVE, sample_directional);
+    let radiance = input_radiance(ray, info, sample_directional, emissive_instance, false, instance_index);
+    let irradiance = radiance.rgb * dot(candidate.direction, probe_normal) / candidate.p;
+
+    let diffuse_color = surface.base_color.rgb * (1.0 - surface.metallic);
+    return diffuse_color * probe_weights * irradiance / PI;
+}
+
+// Instances with subsurface scattering reflect only specularly at their surfaces,
+// since their diffuse light is transported beneath
+fn subsurface_specular(surface: Surface) -> Surface {
+    var specular = surface;
+    specular.base_color = vec4<f32>(vec3<f32>(0.0), surface.base_color.a);
+    specular.metallic = 0.0;
+    return specular;
 }
 // -------- SHADING     --------
 
+// -------- RADIANCE CACHE --------
+// Returns the index of the cell and its checksum
+fn radiance_cache_key(position: vec3<f32>, normal: vec3<f32>) -> vec2<u32> {
+    let cell = bitcast<vec3<u32>>(vec3<i32>(floor(position / frame.radiance_cache_cell_size)));
+
+    // Quantize the normal to the dominant axis, so that radiance doesn't leak through thin walls
+    let a = abs(normal);
+    var face = select(0u, 1u, normal.x < 0.0);
+    if a.y > a.x && a.y >= a.z {
+        face = select(2u, 3u, normal.y < 0.0);
+    } else if a.z > a.x && a.z > a.y {
+        face = select(4u, 5u, normal.z < 0.0);
+    }
+
+    let index = pcg_hash(cell.x ^ pcg_hash(cell.y ^ pcg_hash(cell.z ^ pcg_hash(face))));
+    let checksum = pcg_hash(face ^ pcg_hash(cell.z ^ pcg_hash(cell.y ^ pcg_hash(cell.x + 1u))));
+    // Empty cells have zero checksum
+    return vec2<u32>(index % RADIANCE_CACHE_CAPACITY, max(checksum, 1u));
+}
+
+// Returns the cached radiance and the sample count, which is zero on a miss
+fn radiance_cache_lookup(position: vec3<f32>, normal: vec3<f32>) -> vec4<f32> {
+    let key = radiance_cache_key(position, normal);
+    let radiance = radiance_cache_buffer.data[key.x].radiance;
+    return select(vec4<f32>(0.0), radiance, radiance_cache_buffer.data[key.x].checksum == key.y);
+}
+
+// Samples are summed with atomics and only blended into the cell by `radiance_cache_resolve`,
+// so that concurrent updates of a cell are never lost.
+fn radiance_cache_update(position: vec3<f32>, normal: vec3<f32>, radiance: vec3<f32>) {
+    let key = radiance_cache_key(position, normal);
+
+    // The largest colliding checksum claims the cell; a smaller one may still add samples before it is outbid
+    let claim = atomicMax(&radiance_cache_buffer.data[key.x].claim, key.y);
+    if claim > key.y {
+        return;
+    }
+
+    var value = radiance;
+    let value_luminance = luminance(value);
+    if value_luminance > frame.max_indirect_luminance {
+        value = value * frame.max_indirect_luminance / value_luminance;
+    }
+    let quantized = vec3<u32>(value * RADIANCE_CACHE_FIXED_POINT_SCALE + 0.5);
+    atomicAdd(&radiance_cache_buffer.data[key.x].accumulation[0], quantized.r);
+    atomicAdd(&radiance_cache_buffer.data[key.x].accumulation[1], quantized.g);
+    atomicAdd(&radiance_cache_buffer.data[key.x].accumulation[2], quantized.b);
+    atomicAdd(&radiance_cache_buffer.data[key.x].accumulation[3], 1u);
+}
+// -------- RADIANCE CACHE --------
+
+// -------- MEDIA       --------
+fn slab_range(origin: vec3<f32>, direction: vec3<f32>, aabb: Aabb) -> vec2<f32> {
+    let inv_direction = 1.0 / direction;
+    let t1 = (aabb.min - origin) * inv_direction;
+    let t2 = (aabb.max - origin) * inv_direction;
+    let t_min = max(max(min(t1.x, t2.x), min(t1.y, t2.y)), min(t1.z, t2.z));
+    let t_max = min(min(max(t1.x, t2.x), max(t1.y, t2.y)), max(t1.z, t2.z));
+    return vec2<f32>(t_min, t_max);
+}
+
+// Range of the ray inside the fog volume, clipped to `[0, max_distance]`; it is empty if `x >= y`
+fn fog_volume_range(volume: FogVolume, ray: Ray, max_distance: f32) -> vec2<f32> {
+    var aabb: Aabb;
+    var range = vec2<f32>(0.0);
+
+    if volume.shape == FOG_VOLUME_BOX {
+        aabb.min = vec3<f32>(-0.5);
+        aabb.max = vec3<f32>(0.5);
+        let origin = (volume.inverse_model * vec4<f32>(ray.origin, 1.0)).xyz;
+        let direction = (volume.inverse_model * vec4<f32>(ray.direction, 0.0)).xyz;
+        range = slab_range(origin, direction, aabb);
+    } else if instance_node_buffer.count > 0u {
+        // Global fog fills the bounds of the scene, i.e., the root of the instance BVH
+        let root = instance_node_buffer.data[0];
+        aabb.min = root.min;
+        aabb.max = root.max;
+        range = slab_range(ray.origin, ray.direction, aabb);
+    }
+
+    return vec2<f32>(max(range.x, 0.0), min(range.y, max_distance));
+}
+
+fn fog_optical_depth(ray: Ray, max_distance: f32) -> f32 {
+    var optical_depth = 0.0;
+    for (var i = 0u; i < fog_volume_buffer.count; i += 1u) {
+        let volume = fog_volume_buffer.data[i];
+        let range = fog_volume_range(volume, ray, max_distance);
+        optical_depth += volume.density * max(range.y - range.x, 0.0);
+    }
+    return optical_depth;
+}
+
+fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    return (1.0 - g * g) / (2.0 * TAU * denom * sqrt(denom));
+}
+
+// Radiance scattered along `direction` by a point inside fog volume `volume_index`,
+// from one sample of the directional light and one of the emissive lights.
+// Ambient light is in-scattered without occlusion.
+fn medium_radiance(rand: vec4<f32>, position: vec3<f32>, direction: vec3<f32>, volume_index: u32) -> vec3<f32> {
+    let anisotropy = fog_volume_buffer.data[volume_index].anisotropy;
+    let receiver = MEDIUM_RECEIVER_FLAG | volume_index;
+    var radiance = lights.ambient_color.rgb;
+
+    var ray: Ray;
+    var hit: Hit;
+    var info: HitInfo;
+
+    ray.origin = position;
+
+    var candidate = select_light_candidate(rand, position, vec3<f32>(0.0), DONT_SAMPLE_EMISSIVE, &info);
+    ray.direction = candidate.direction;
+    ray.inv_direction = 1.0 / ray.direction;
+
+    hit = traverse_top(ray, candidate.max_distance, candidate.min_distance, DONT_EXCLUDE);
+    occlude_hit_info(ray, hit, &info);
+
+    var in_radiance = input_radiance(ray, info, true, DONT_SAMPLE_EMISSIVE, false, U32_MAX).rgb;
+    in_radiance *= exp(-fog_optical_depth(ray, candidate.max_distance));
+    radiance += henyey_greenstein(dot(ray.direction, direction), anisotropy) * in_radiance;
+
+    candidate = select_light_candidate(rand, position, vec3<f32>(0.0), receiver, &info);
+    if candidate.emissive_instance != DONT_SAMPLE_EMISSIVE && candidate.p > 0.0 {
+        ray.direction = candidate.direction;
+        ray.inv_direction = 1.0 / ray.direction;
+
+        hit = traverse_top(ray, candidate.max_distance, candidate.min_distance, candidate.emissive_instance);
+        occlude_hit_info(ray, hit, &info);
+
+        in_radiance = input_radiance(ray, info, false, candidate.emissive_instance, false, receiver).rgb;
+        in_radiance *= exp(-fog_optical_depth(ray, candidate.max_distance));
+        radiance += henyey_greenstein(dot(ray.direction, direction), anisotropy) * in_radiance / candidate.p;
+    }
+
+    return radiance;
+}
+
+// Single scattering along a ray segment, sampling one free-flight distance inside each fog volume.
+// Returns the radiance scattered towards the ray origin in `rgb`, and the transmittance of the segment in `a`.
+fn medium_scattering(ray: Ray, max_distance: f32, rand: vec4<f32>, seed: u32) -> vec4<f32> {
+    var radiance = vec3<f32>(0.0);
+    var optical_depth = 0.0;
+
+    for (var i = 0u; i < fog_volume_buffer.count; i += 1u) {
+        let volume = fog_volume_buffer.data[i];
+        let range = fog_volume_range(volume, ray, max_distance);
+        if range.y <= range.x {
+            continue;
+        }
+
+        let extent = range.y - range.x;
+        let opacity = 1.0 - exp(-volume.density * extent);
+        optical_depth += volume.density * extent;
+
+        // Free-flight distance, distributed by the transmittance of this volume within the segment
+        let u = min(random_float(seed ^ pcg_hash(i)), 0.999999);
+        let t = range.x - log(1.0 - u * opacity) / volume.density;
+        let position = ray.origin + t * ray.direction;
+
+        // The transmittance of this volume cancels out with the distance pdf, leaving that of the others
+        let other_depth = fog_optical_depth(ray, t) - volume.density * (t - range.x);
+        let in_radiance = medium_radiance(rand, position, ray.direction, i);
+        radiance += volume.albedo * opacity * exp(-other_depth) * in_radiance;
+    }
+
+    return vec4<f32>(radiance, exp(-optical_depth));
+}
+// -------- MEDIA       --------
+
 // -------- RESTIR      --------
 // The lifetime of the reservoir is randomized per sample
 fn reservoir_lifetime(r: Reservoir) -> f32 {
@@ -1002,9 +1865,214 @@ fn compute_jacobian(q: Sample, r: Sample) -> f32 {
     jacobian = clamp(jacobian, 1.0, 50.0);
     return jacobian;
 }
-// -------- RESTIR  --------
 
-fn jittered_deferred_uv(uv: vec2<f32>) -> vec2<f32> {
+// Checks if the sample point can be seen from the visible point
+fn sample_visible(visible_position: vec3<f32>, visible_normal: vec3<f32>, sample_position: vec3<f32>) -> bool {
+    var ray: Ray;
+    ray.origin = visible_position + visible_normal * RAY_BIAS;
+    let offset = sample_position - ray.origin;
+    let distance = length(offset);
+    ray.direction = offset / distance;
+    ray.inv_direction = 1.0 / ray.direction;
+
+    let max_distance = min(distance, DISTANCE_MAX) - RAY_BIAS;
+    if max_distance <= 0.0 {
+        return true;
+    }
+
+    let hit = traverse_top(ray, max_distance, max_distance, DONT_EXCLUDE);
+    return hit.instance_index == U32_MAX;
+}
+
+// Jacobian of reconnecting the sample to another visible point
+fn shift_jacobian(s: Sample, visible_position: vec3<f32>) -> f32 {
+    if s.sample_position.w < 0.5 {
+        return 1.0;
+    }
+
+    let offset_source = s.visible_position.xyz - s.sample_position.xyz;
+    let offset_target = visible_position - s.sample_position.xyz;
+    let distance_source = dot(offset_source, offset_source);
+    let distance_target = dot(offset_target, offset_target);
+    let cos_source = abs(dot(normalize(offset_source), s.sample_normal));
+    let cos_target = abs(dot(normalize(offset_target), s.sample_normal));
+
+    let denominator = cos_source * distance_target;
+    return select(0.0, cos_target * distance_source / denominator, denominator > 0.0);
+}
+
+// Target function of the sample evaluated at another visible point, optionally tracing its visibility
+fn shifted_target(
+    s: Sample,
+    visible_position: vec3<f32>,
+    visible_normal: vec3<f32>,
+    surface: Surface,
+    shade: bool,
+    visibility: bool,
+) -> f32 {
+    let direction = normalize(s.sample_position.xyz - visible_position);
+    if dot(direction, visible_normal) <= 0.0 {
+        return 0.0;
+    }
+    if visibility && !sample_visible(visible_position, visible_normal, s.sample_position.xyz) {
+        return 0.0;
+    }
+
+    if shade {
+        let view_direction = calculate_view(vec4<f32>(visible_position, 1.0), view.projection[3].w == 1.0);
+        return luminance(shading(view_direction, visible_normal, direction, surface, s.radiance));
+    }
+    return luminance(s.radiance.rgb);
+}
+
+fn balance_heuristic(p: f32, q: f32) -> f32 {
+    return select(0.0, p / (p + q), p + q > 0.0);
+}
+
+// Streams a sample into the reservoir, keeping the target function of the selected sample.
+// Returns whether the sample is selected.
+fn stream_reservoir(r: ptr<function, Reservoir>, p_selected: ptr<function, f32>, s: Sample, w_new: f32, p: f32) -> bool {
+    (*r).w_sum += w_new;
+    (*r).w2_sum += w_new * w_new;
+
+    let rand = fract(dot(s.random, vec4<f32>(1.0)));
+    if w_new > 0.0 && rand * (*r).w_sum < w_new {
+        (*r).s = s;
+        *p_selected = p;
+        return true;
+    }
+    return false;
+}
+
+// Converts the unbiased contribution weight into the streaming weights used by biased reuse
+fn normalize_reservoir(r: ptr<function, Reservoir>, w: f32, p_selected: f32) {
+    let w_sum = w * (*r).count * p_selected;
+    let scale = select(0.0, w_sum / (*r).w_sum, (*r).w_sum > 0.0);
+    (*r).w = w;
+    (*r).w_sum = w_sum;
+    (*r).w2_sum *= scale * scale;
+}
+
+// Streams a neighbor reservoir with pairwise MIS against the canonical reservoir of this pixel,
+// shifting samples between their visible points with visibility rays.
+// Each domain evaluates the target function with the surface at its own visible point.
+// Returns the MIS weight the neighbor takes from the canonical sample.
+fn pairwise_reuse(
+    r: ptr<function, Reservoir>,
+    p_selected: ptr<function, f32>,
+    neighbor: Reservoir,
+    canonical: Reservoir,
+    p_canonical: f32,
+    canonical_count: f32,
+    surface: Surface,
+    neighbor_surface: Surface,
+    shade: bool,
+) -> f32 {
+    let visible_position = canonical.s.visible_position.xyz;
+    let visible_normal = canonical.s.visible_normal;
+    let neighbor_position = neighbor.s.visible_position.xyz;
+    let neighbor_normal = neighbor.s.visible_normal;
+
+    let jacobian = shift_jacobian(neighbor.s, visible_position);
+    let p_current = shifted_target(neighbor.s, visible_position, visible_normal, surface, shade, true);
+    let p_neighbor = shifted_target(neighbor.s, neighbor_position, neighbor_normal, neighbor_surface, shade, false);
+    var p_shifted = shifted_target(canonical.s, neighbor_position, neighbor_normal, neighbor_surface, shade, true);
+    p_shifted *= shift_jacobian(canonical.s, neighbor_position);
+
+    let m = balance_heuristic(neighbor.count * p_neighbor, canonical_count * p_current * jacobian);
+    stream_reservoir(r, p_selected, neighbor.s, m * p_current * jacobian * neighbor.w, p_current);
+    (*r).count += neighbor.count;
+
+    return balance_heuristic(neighbor.count * p_shifted, canonical_count * p_canonical);
+}
+
+// Streams a fresh candidate of this frame into the canonical reservoir, with resampled importance sampling
+fn stream_candidate(r: ptr<function, Reservoir>, p_selected: ptr<function, f32>, s: Sample, p_s: f32, pdf: f32) {
+    let w_new = select(0.0, p_s / pdf, pdf > 0.0);
+    stream_reservoir(r, p_selected, s, w_new, p_s);
+    (*r).count += 1.0;
+}
+
+// Unbiased temporal reuse: the fresh candidates of this frame form the canonical reservoir,
+// and the previous reservoir is its only neighbor
+fn temporal_restir_unbiased(
+    r: ptr<function, Reservoir>,
+    canonical: Reservoir,
+    p_canonical: f32,
+    surface: Surface,
+    shade: bool,
+    max_sample_count: u32
+) {
+    var previous = *r;
+    previous.count = min(previous.count, max(f32(max(max_sample_count, 1u)) - canonical.count, 0.0));
+
+    var q: Reservoir;
+    var p_selected = 0.0;
+    q.lifetime = previous.lifetime;
+
+    // `check_previous_reservoir` only keeps reservoirs visible on the same instance, thus of the same material
+    var canonical_mis = 2.0;
+    if previous.count > 0.0 {
+        canonical_mis -= pairwise_reuse(&q, &p_selected, previous, canonical, p_canonical, canonical.count, surface, surface, shade);
+    }
+
+    // The canonical sample is streamed with its unbiased contribution weight
+    let w_canonical = select(0.0, canonical.w_sum / (canonical.count * p_canonical), p_canonical > 0.0);
+    if stream_reservoir(&q, &p_selected, canonical.s, canonical_mis * p_canonical * w_canonical, p_canonical) {
+        q.lifetime = 0.0;
+    }
+    q.count += canonical.count;
+
+    normalize_reservoir(&q, select(0.0, q.w_sum / (2.0 * p_selected), p_selected > 0.0), p_selected);
+
+    q.s.visible_position = canonical.s.visible_position;
+    q.s.visible_normal = canonical.s.visible_normal;
+    q.s.visible_instance = canonical.s.visible_instance;
+    *r = q;
+}
+// -------- RESTIR  --------
+
+// -------- ADAPTIVE SAMPLING --------
+fn normalized_variance(variance: f32) -> f32 {
+    return select(0.0, min(variance / frame.max_variance, 1.0), variance > 0.0 && frame.max_variance > 0.0);
+}
+
+// Count of new samples a pixel takes this frame, by its variance in the previous frame
+fn adaptive_sample_count(pass_index: u32, variance: f32, rand: f32) -> u32 {
+    if frame.adaptive_sampling == 0u || frame.temporal_reuse == 0u {
+        return 1u;
+    }
+
+    let previous = 1u - (frame.number & 1u);
+    let pixel_count = f32(atomicLoad(&adaptive_statistics.data[previous].pixel_count[pass_index]));
+    if pixel_count < 1.0 {
+        return 1u;
+    }
+    let variance_sum = f32(atomicLoad(&adaptive_statistics.data[previous].variance_sum[pass_index])) / ADAPTIVE_VARIANCE_SCALE;
+
+    // Converged pixels keep a uniform share of the budget, so that they still pick up changes in lighting
+    let uniform_share = 1.0 / pixel_count;
+    let variance_share = select(uniform_share, normalized_variance(variance) / variance_sum, variance_sum > 0.0);
+    let share = mix(uniform_share, variance_share, ADAPTIVE_VARIANCE_WEIGHT);
+
+    let budget = select(frame.adaptive_emissive_samples, frame.adaptive_indirect_samples, pass_index == ADAPTIVE_PASS_INDIRECT);
+    return min(u32(budget * share + rand), frame.adaptive_max_samples);
+}
+
+fn adaptive_record(pass_index: u32, variance: f32, rand: f32) {
+    if frame.adaptive_sampling == 0u {
+        return;
+    }
+
+    // Stochastic rounding keeps the fixed point sum unbiased for small variances
+    let current = frame.number & 1u;
+    let value = u32(normalized_variance(variance) * ADAPTIVE_VARIANCE_SCALE + rand);
+    atomicAdd(&adaptive_statistics.data[current].variance_sum[pass_index], value);
+    atomicAdd(&adaptive_statistics.data[current].pixel_count[pass_index], 1u);
+}
+// -------- ADAPTIVE SAMPLING --------
+
+fn jittered_deferred_uv(uv: vec2<f32>) -> vec2<f32> {
     let texel_size = 1.0 / vec2<f32>(textureDimensions(position_texture));
     let ratio = frame.upscale_ratio - 1.0;
     return uv + select(0.25, -0.25, (frame.number & 1u) == 0u) * texel_size * ratio;
@@ -1016,6 +2084,84 @@ fn jittered_deferred_coords(uv: vec2<f32>) -> vec2<i32> {
     return vec2<i32>(deferred_uv * size);
 }
 
+// Traces primary visibility through the thin lens and at the shutter time of each pixel,
+// replacing the pinhole G-buffer rasterized at the current pose by the prepass.
+// Each pixel takes its own point on the aperture and time, so defocus and motion blur show within a single frame.
+// The depth gradient is kept from the prepass.
+@compute @workgroup_size(8, 8, 1)
+fn trace_primary(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let size = textureDimensions(primary_position_texture);
+    let coords = vec2<i32>(invocation_id.xy);
+    let uv = coords_to_uv(coords, size);
+    sample_shutter_time(coords, size);
+
+    // The pinhole ray through the pixel; aim at the rasterized surface to keep the sub-pixel jitter
+    let is_orthographic = view.projection[3].w == 1.0;
+    let ndc = vec2<f32>(2.0 * uv.x - 1.0, 1.0 - 2.0 * uv.y);
+    let near = view.inverse_view_proj * vec4<f32>(ndc, 1.0, 1.0);
+    let far = view.inverse_view_proj * vec4<f32>(ndc, 0.5, 1.0);
+    let eye = select(view.world_position.xyz, near.xyz / near.w, is_orthographic);
+    var direction = normalize(far.xyz / far.w - eye);
+
+    let position_depth = textureLoad(primary_position_texture, coords);
+    if position_depth.w > F32_EPSILON {
+        direction = normalize(position_depth.xyz - eye);
+    }
+
+    var ray: Ray;
+    ray.origin = eye;
+    ray.direction = direction;
+
+    if !is_orthographic && frame.lens_radius > 0.0 {
+        // All rays through the pixel meet the pinhole ray on the focus plane
+        let forward = -view.view[2].xyz;
+        let focus = eye + direction * frame.focus_distance / dot(direction, forward);
+
+        let noise_id = frame.number % NOISE_TEXTURE_COUNT;
+        let noise_size = textureDimensions(noise_texture[noise_id]);
+        let noise_uv = (vec2<f32>(coords) + f32(frame.number) + 0.5) / vec2<f32>(noise_size);
+        var rand = textureSampleLevel(noise_texture[noise_id], noise_sampler, noise_uv, 0.0);
+        rand = fract(rand + f32(frame.number) * GOLDEN_RATIO);
+
+        ray.origin = eye + (view.view * vec4<f32>(sample_aperture(rand.zw), 0.0, 0.0)).xyz;
+        ray.direction = normalize(focus - ray.origin);
+    }
+    ray.inv_direction = 1.0 / ray.direction;
+
+    let hit = traverse_top(ray, F32_MAX, 0.0, DONT_EXCLUDE);
+    if hit.instance_index == U32_MAX {
+        textureStore(primary_position_texture, coords, vec4<f32>(0.0));
+        textureStore(primary_normal_texture, coords, vec4<f32>(0.0));
+        textureStore(primary_instance_material_texture, coords, vec4<f32>(0.0));
+        textureStore(primary_velocity_uv_texture, coords, vec4<f32>(0.0));
+        textureStore(primary_vertex_color_uv_texture, coords, vec4<u32>(0u));
+        return;
+    }
+
+    let info = hit_info(ray, hit);
+    let clip = view.view_proj * info.position;
+    textureStore(primary_position_texture, coords, vec4<f32>(info.position.xyz, clip.z / clip.w));
+    textureStore(primary_normal_texture, coords, vec4<f32>(info.normal, 1.0));
+
+    let instance_material = vec2<f32>(f32(info.instance_index), f32(info.material_index)) + 0.5;
+    textureStore(primary_instance_material_texture, coords, vec4<f32>(instance_material, 0.0, 0.0));
+
+    // Velocity of the visible point over the whole frame as in the prepass, whatever time it was seen at,
+    // so that reprojection follows the moving instance
+    let instance = instance_buffer[info.instance_index];
+    let local_position = vec4<f32>(instance_position_world_to_local(instance_inverse_model(instance), info.position.xyz), 1.0);
+    let current_position = instance.model * local_position;
+    let previous_position = instance.previous_model * local_position;
+    let velocity = clip_to_uv(view.view_proj * current_position) - clip_to_uv(previous_view.view_proj * previous_position);
+    textureStore(primary_velocity_uv_texture, coords, vec4<f32>(velocity, info.uv));
+
+    textureStore(primary_vertex_color_uv_texture, coords, vec4<u32>(
+        pack2x16float(info.color.rg),
+        pack2x16float(info.color.ba),
+        bitcast<vec2<u32>>(info.uv_1)
+    ));
+}
+
 @compute @workgroup_size(8, 8, 1)
 fn full_screen_albedo(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     let render_size = textureDimensions(albedo_texture);
@@ -1036,9 +2182,14 @@ fn full_screen_albedo(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     let instance_material = vec2<u32>(textureLoad(instance_material_texture, coords, 0).xy);
     let velocity_uv = textureLoad(velocity_uv_texture, coords, 0);
 
-    let surface = retreive_surface(instance_material.y, velocity_uv.zw);
+    let surface = retreive_deferred_surface(instance_material.y, velocity_uv.zw, coords);
     let view_direction = calculate_view(position, view.projection[3].w == 1.0);
-    textureStore(albedo_texture, coords, vec4<f32>(env_brdf(view_direction, normal, surface), 1.0));
+    var albedo = env_brdf(view_direction, normal, surface);
+    if (material_buffer[instance_material.y].flags & MATERIAL_UNLIT) != 0u {
+        // Keep the emission of unlit surfaces through demodulation
+        albedo = surface.emissive.rgb;
+    }
+    textureStore(albedo_texture, coords, vec4<f32>(albedo, 1.0));
 }
 
 @compute @workgroup_size(8, 8, 1)
@@ -1051,6 +2202,7 @@ fn direct_lit(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     var s: Sample;
 
     let deferred_coords = jittered_deferred_coords(uv);
+    sample_shutter_time(deferred_coords, deferred_size);
     let position_depth = textureLoad(position_texture, deferred_coords, 0);
     let position = vec4<f32>(position_depth.xyz, 1.0);
     let depth = position_depth.w;
@@ -1086,6 +2238,9 @@ fn direct_lit(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     var hit: Hit;
     var info: HitInfo;
 
+    let surface = retreive_deferred_surface(instance_material.y, velocity_uv.zw, deferred_coords);
+    let view_direction = calculate_view(position, view.projection[3].w == 1.0);
+
     let previous_uv = jittered_deferred_uv(uv) - velocity_uv.xy;
     var r: Reservoir = load_previous_reservoir(previous_uv, render_size);
 
@@ -1104,52 +2259,83 @@ fn direct_lit(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     let sample_directional = true;
 #endif
 
+    // Adaptive sampling only applies to emissive lighting; directional light takes one sample
+    let adaptive_seed = pcg_hash(u32(coords.x + render_size.x * coords.y) ^ pcg_hash(frame.number));
+#ifdef EMISSIVE_LIT
+    let previous_variance = textureLoad(variance_texture, coords).x;
+    var sample_count = adaptive_sample_count(ADAPTIVE_PASS_EMISSIVE, previous_variance, random_float(adaptive_seed));
+#else
+    var sample_count = 1u;
+#endif
+    if r.count < f32(DIRECT_VALIDATION_FRAME_SAMPLE_THRESHOLD) {
+        sample_count = max(sample_count, 1u);
+    }
+
     // Non-validation frame, or sample count too low
     if frame.number % validate_interval != 0u || r.count < f32(DIRECT_VALIDATION_FRAME_SAMPLE_THRESHOLD) {
-        let candidate = select_light_candidate(
-            s.random,
-            s.visible_position.xyz,
-            s.visible_normal,
-            select_light_instance,
-            &info
-        );
+        // With unbiased reuse, all candidates of this frame are merged with the previous reservoir at once
+        var canonical: Reservoir;
+        var p_canonical = 0.0;
+
+        for (var sample_index = 0u; sample_index < sample_count; sample_index += 1u) {
+            if sample_index > 0u {
+                s.random = fract(s.random + GOLDEN_RATIO);
+                s.radiance = vec4<f32>(0.0);
+            }
 
-        // Direct light sampling
-        ray.origin = position.xyz + normal * RAY_BIAS;
-        ray.direction = candidate.direction;
-        ray.inv_direction = 1.0 / ray.direction;
+            let candidate = select_light_candidate(
+                s.random,
+                s.visible_position.xyz,
+                s.visible_normal,
+                select_light_instance,
+                &info
+            );
 
-        var trace_condition = dot(candidate.direction, normal) > 0.0;
-        trace_condition = trace_condition && candidate.p > 0.0;
+            // Direct light sampling
+            ray.origin = position.xyz + normal * RAY_BIAS;
+            ray.direction = candidate.direction;
+            ray.inv_direction = 1.0 / ray.direction;
+
+            var trace_condition = dot(candidate.direction, normal) > 0.0;
+            trace_condition = trace_condition && candidate.p > 0.0;
 #ifdef EMISSIVE_LIT
-        trace_condition = trace_condition && candidate.emissive_instance != DONT_SAMPLE_EMISSIVE;
+            trace_condition = trace_condition && candidate.emissive_instance != DONT_SAMPLE_EMISSIVE;
 #endif
 
-        if trace_condition {
-            hit = traverse_top(ray, candidate.max_distance, candidate.min_distance, candidate.emissive_instance);
-            occlude_hit_info(ray, hit, &info);
+            if trace_condition {
+                hit = traverse_top(ray, candidate.max_distance, candidate.min_distance, candidate.emissive_instance);
+                occlude_hit_info(ray, hit, &info);
 
 #ifdef EMISSIVE_LIT
-            // Don't sample directional light, sample emissive only
-            s.radiance = input_radiance(ray, info, false, candidate.emissive_instance, false);
+                // Don't sample directional light, sample emissive only
+                s.radiance = input_radiance(ray, info, false, candidate.emissive_instance, false, s.visible_instance);
 #else
-            // Sample directional light only, don't sample emissive
-            s.radiance = input_radiance(ray, info, true, DONT_SAMPLE_EMISSIVE, false);
+                // Sample directional light only, don't sample emissive
+                s.radiance = input_radiance(ray, info, true, DONT_SAMPLE_EMISSIVE, false, s.visible_instance);
 #endif
-        }
+            }
 
-        s.sample_position = info.position;
-        s.sample_normal = info.normal;
+            s.sample_position = info.position;
+            s.sample_normal = info.normal;
 
-        // let sample_radiance = shading(
-        //     view_direction,
-        //     s.visible_normal,
-        //     normalize(s.sample_position.xyz - s.visible_position.xyz),
-        //     surface,
-        //     s.radiance
-        // );
-        let w_new = select(0.0, luminance(s.radiance.rgb) / candidate.p, candidate.p > 0.0);
-        temporal_restir(&r, s, w_new, frame.max_temporal_reuse_count);
+            // let sample_radiance = shading(
+            //     view_direction,
+            //     s.visible_normal,
+            //     normalize(s.sample_position.xyz - s.visible_position.xyz),
+            //     surface,
+            //     s.radiance
+            // );
+            if frame.unbiased_restir > 0u {
+                stream_candidate(&canonical, &p_canonical, s, luminance(s.radiance.rgb), candidate.p);
+            } else {
+                let w_new = select(0.0, luminance(s.radiance.rgb) / candidate.p, candidate.p > 0.0);
+                temporal_restir(&r, s, w_new, frame.max_temporal_reuse_count);
+            }
+        }
+
+        if frame.unbiased_restir > 0u && canonical.count > 0.0 {
+            temporal_restir_unbiased(&r, canonical, p_canonical, surface, false, frame.max_temporal_reuse_count);
+        }
     }
 
     // Validation frame
@@ -1180,9 +2366,9 @@ fn direct_lit(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
             occlude_hit_info(ray, hit, &info);
 
 #ifdef EMISSIVE_LIT
-            validate_radiance = input_radiance(ray, info, false, candidate.emissive_instance, false);
+            validate_radiance = input_radiance(ray, info, false, candidate.emissive_instance, false, s.visible_instance);
 #else
-            validate_radiance = input_radiance(ray, info, true, DONT_SAMPLE_EMISSIVE, false);
+            validate_radiance = input_radiance(ray, info, true, DONT_SAMPLE_EMISSIVE, false, s.visible_instance);
 #endif
         }
 
@@ -1223,15 +2409,31 @@ fn direct_lit(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
 
     var variance = r.w2_sum / r.count - pow(r.w_sum / r.count, 2.0);
     variance = select(variance / r.count, variance, r.count < 1.0);
-    variance = min(variance, MAX_VARIANCE);
+    variance = min(variance, frame.max_variance);
     textureStore(variance_texture, coords, vec4<f32>(variance));
+#ifdef EMISSIVE_LIT
+    adaptive_record(ADAPTIVE_PASS_EMISSIVE, variance, random_float(adaptive_seed ^ 1u));
+#endif
 
     if frame.temporal_reuse > 0u {
         store_reservoir(coords.x + render_size.x * coords.y, r);
     }
 
-    let surface = retreive_surface(instance_material.y, velocity_uv.zw);
-    let view_direction = calculate_view(position, view.projection[3].w == 1.0);
+    var shade_surface = surface;
+    var subsurface = vec3<f32>(0.0);
+    if instance_buffer[instance_material.x].subsurface.a > 0.0 {
+        shade_surface = subsurface_specular(surface);
+        let seed = pcg_hash(u32(coords.x + render_size.x * coords.y) ^ pcg_hash(frame.number));
+        subsurface = subsurface_radiance(
+            seed,
+            position.xyz,
+            normal,
+            instance_material.x,
+            surface,
+            sample_directional,
+            select_light_instance
+        );
+    }
 
     // if frame.enable_spatial_reuse == 0u {
 #ifdef RENDER_EMISSIVE
@@ -1239,10 +2441,10 @@ fn direct_lit(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
         view_direction,
         r.s.visible_normal,
         normalize(r.s.sample_position.xyz - r.s.visible_position.xyz),
-        surface,
+        shade_surface,
         r.s.radiance
     );
-    out_radiance *= r.w;
+    out_radiance = out_radiance * r.w + subsurface;
     let out_color = out_radiance + compute_emissive_radiance(surface.emissive);
     textureStore(render_texture, coords, vec4<f32>(out_color, 1.0));
 #else
@@ -1250,10 +2452,10 @@ fn direct_lit(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
         view_direction,
         r.s.visible_normal,
         normalize(r.s.sample_position.xyz - r.s.visible_position.xyz),
-        surface,
+        shade_surface,
         r.s.radiance
     );
-    out_radiance *= r.w;
+    out_radiance = out_radiance * r.w + subsurface;
     let out_color = out_radiance;
     textureStore(render_texture, coords, vec4<f32>(out_color, 1.0));
 #endif
@@ -1266,8 +2468,12 @@ fn indirect_lit_ambient(@builtin(global_invocation_id) invocation_id: vec3<u32>)
     let render_size = textureDimensions(render_texture);
 
     let coords = vec2<i32>(invocation_id.xy);
-    let uv = coords_to_uv(coords, render_size);
+    if any(coords >= render_size) {
+        return;
+    }
+    let uv = stratified_uv(coords, render_size, frame.indirect_scale, frame.number);
     let deferred_coords = jittered_deferred_coords(uv);
+    sample_shutter_time(deferred_coords, textureDimensions(position_texture));
 
     let position_depth = textureLoad(position_texture, deferred_coords, 0);
     let position = vec4<f32>(position_depth.xyz, 1.0);
@@ -1290,6 +2496,18 @@ fn indirect_lit_ambient(@builtin(global_invocation_id) invocation_id: vec3<u32>)
     let instance_material = vec2<u32>(textureLoad(instance_material_texture, deferred_coords, 0).xy);
     let velocity_uv = textureLoad(velocity_uv_texture, deferred_coords, 0);
 
+    if frame.radiance_cache_debug > 0u {
+        // Show the cached radiance in place of indirect lighting
+        store_reservoir(coords.x + render_size.x * coords.y, r);
+        store_spatial_reservoir(coords.x + render_size.x * coords.y, r);
+        store_previous_spatial_reservoir(coords.x + render_size.x * coords.y, r);
+
+        let cached = radiance_cache_lookup(position.xyz, normal);
+        textureStore(variance_texture, coords, vec4<f32>(0.0));
+        textureStore(render_texture, coords, vec4<f32>(cached.rgb, 1.0));
+        return;
+    }
+
     let noise_id = frame.number % NOISE_TEXTURE_COUNT;
     let noise_size = textureDimensions(noise_texture[noise_id]);
     let noise_uv = (vec2<f32>(coords) + f32(frame.number) + 0.5) / vec2<f32>(noise_size);
@@ -1306,47 +2524,281 @@ fn indirect_lit_ambient(@builtin(global_invocation_id) invocation_id: vec3<u32>)
     var pdf: f32;
     var surface: Surface;
 
+    // ReSTIR: Temporal
+    let previous_uv = jittered_deferred_uv(uv) - velocity_uv.xy;
+    r = load_previous_reservoir(previous_uv, render_size);
+
+    if !check_previous_reservoir(&r, s) && all(abs(previous_uv - 0.5) <= vec2<f32>(0.5)) {
+        let previous_coords = vec2<i32>(previous_uv * vec2<f32>(render_size));
+        store_previous_spatial_reservoir(previous_coords.x + render_size.x * previous_coords.y, r);
+    }
+
+    let primary_surface = retreive_deferred_surface(instance_material.y, velocity_uv.zw, deferred_coords);
+    let view_direction = calculate_view(position, view.projection[3].w == 1.0);
+
+    // Converged pixels may keep their reservoir without taking a new sample, and noisy ones take several
+    let adaptive_seed = pcg_hash(u32(coords.x + render_size.x * coords.y) ^ pcg_hash(frame.number));
+    let previous_variance = textureLoad(variance_texture, coords).x;
+    var sample_count = adaptive_sample_count(ADAPTIVE_PASS_INDIRECT, previous_variance, random_float(adaptive_seed));
+    if r.count < 1.0 {
+        sample_count = max(sample_count, 1u);
+    }
+
+    // With unbiased reuse, all candidates of this frame are merged with the previous reservoir at once
+    var canonical: Reservoir;
+    var p_canonical = 0.0;
+
+    let visible_sample = s;
+    for (var sample_index = 0u; sample_index < sample_count; sample_index += 1u) {
+        s = visible_sample;
+        s.random = fract(s.random + f32(sample_index) * GOLDEN_RATIO);
+
 #ifdef MULTIPLE_BOUNCES
-    var bounce_sample = s;
-    var color_transport = vec3<f32>(1.0);
+        var bounce_sample = s;
+        var color_transport = vec3<f32>(1.0);
+
+        // The first bounce off the primary surface is always diffuse
+        var bounce_lobe = LOBE_DIFFUSE;
+        var bounce_view_direction = vec3<f32>(0.0);
+        var bounce_roughness = 1.0;
+        var bounce_surface: Surface;
+        var lobe_depth = vec3<u32>(0u);
+        let seed = pcg_hash(adaptive_seed ^ pcg_hash(sample_index));
+        var first_in_scatter = vec3<f32>(0.0);
+        var first_transmittance = 1.0;
+
+        for (var n = 0u; n < frame.indirect_bounces; n += 1u) {
+            if frame.russian_roulette == 0u && all(color_transport <= vec3<f32>(0.01)) {
+                break;
+            }
 
-    for (var n = 0u; n < frame.indirect_bounces && any(color_transport > vec3<f32>(0.01)); n += 1u) {
-        var rand_sample = sample_cosine_hemisphere(bounce_sample.random.xy);
-        ray.origin = bounce_sample.visible_position.xyz + bounce_sample.visible_normal * RAY_BIAS;
-        ray.direction = normal_basis(bounce_sample.visible_normal) * rand_sample.xyz;
+            var rand_sample = sample_cosine_hemisphere(bounce_sample.random.xy);
+            ray.origin = bounce_sample.visible_position.xyz + bounce_sample.visible_normal * RAY_BIAS;
+            ray.direction = normal_basis(bounce_sample.visible_normal) * rand_sample.xyz;
+            if bounce_lobe == LOBE_SPECULAR || bounce_lobe == LOBE_CLEARCOAT {
+                var basis = normal_basis(bounce_sample.visible_normal);
+                var roughness = vec2<f32>(bounce_surface.clearcoat_roughness);
+                if bounce_lobe == LOBE_SPECULAR {
+                    basis = anisotropy_basis(bounce_sample.visible_normal, bounce_surface.anisotropy_rotation);
+                    roughness = anisotropic_roughness(bounce_surface);
+                }
+                ray.direction = sample_ggx_reflection(
+                    bounce_sample.random.xy,
+                    bounce_view_direction,
+                    basis,
+                    roughness
+                );
+                rand_sample.w = 1.0;
+                if dot(ray.direction, bounce_sample.visible_normal) <= 0.0 {
+                    break;
+                }
+            } else if bounce_lobe == LOBE_TRANSMISSION {
+                // Thin surface transmission: pass through without refraction
+                ray.origin = bounce_sample.visible_position.xyz - bounce_sample.visible_normal * RAY_BIAS;
+                ray.direction = -bounce_view_direction;
+                rand_sample.w = 1.0;
+            }
+            ray.inv_direction = 1.0 / ray.direction;
+            lobe_depth[lobe_depth_index(bounce_lobe)] += 1u;
+
+            hit = traverse_top(ray, F32_MAX, 0.0, DONT_EXCLUDE);
+            info = hit_info(ray, hit);
+
+            // Scattering and attenuation by participating media along the bounce
+            if fog_volume_buffer.count > 0u {
+                let segment = select(DISTANCE_MAX, hit.intersection.distance, hit.instance_index != U32_MAX);
+                let medium = medium_scattering(ray, segment, bounce_sample.random, pcg_hash(seed + n));
+                s.radiance += vec4<f32>(color_transport * medium.rgb, 0.0);
+                color_transport *= medium.a;
+
+                if n == 0u {
+                    first_in_scatter = medium.rgb;
+                    first_transmittance = medium.a;
+                }
+            }
+
+            if n == 0u {
+                s.sample_position = info.position;
+                s.sample_normal = info.normal;
+                pdf = rand_sample.w;
+            }
+
+            bounce_sample.sample_position = info.position;
+            bounce_sample.sample_normal = info.normal;
+
+            // Terminate the path where the radiance cache has converged
+            if n > 0u && frame.radiance_cache > 0u && hit.instance_index != U32_MAX {
+                let cached = radiance_cache_lookup(info.position.xyz, info.normal);
+                if cached.a >= RADIANCE_CACHE_MIN_SAMPLES {
+                    // Same pdf convention as the radiance gathered at this hit
+                    let cached_radiance = select(cached.rgb / rand_sample.w, vec3<f32>(0.0), rand_sample.w < 0.01);
+                    s.radiance += vec4<f32>(color_transport * cached_radiance, 0.0);
+                    break;
+                }
+            }
+
+            // N bounce: from sample position
+            if hit.instance_index != U32_MAX {
+                var out_radiance = vec3<f32>(0.0);
+
+                surface = retreive_surface(info.material_index, info.uv, info.uv_1, info.color);
+                bounce_roughness = surface.roughness;
+                surface.roughness = 1.0;
+
+                // Unlit surfaces are not sampled as lights, so their emission is only gathered where paths hit them
+                if (material_buffer[info.material_index].flags & MATERIAL_UNLIT) != 0u {
+                    var emitted_radiance = compute_emissive_radiance(surface.emissive);
+                    if n > 0u {
+                        emitted_radiance = select(emitted_radiance / rand_sample.w, vec3<f32>(0.0), rand_sample.w < 0.01);
+                    }
+                    let emitted_luminance = luminance(emitted_radiance);
+                    if emitted_luminance > frame.max_indirect_luminance {
+                        emitted_radiance = emitted_radiance * frame.max_indirect_luminance / emitted_luminance;
+                    }
+                    s.radiance += vec4<f32>(color_transport * emitted_radiance, 1.0);
+                }
+
+                let receiver = info.instance_index;
+                let candidate = select_light_candidate(
+                    bounce_sample.random,
+                    bounce_sample.sample_position.xyz,
+                    bounce_sample.sample_normal,
+                    receiver,
+                    &info
+                );
+                let sample_directional = (candidate.emissive_instance == DONT_SAMPLE_EMISSIVE);
+                let view_direction = normalize(bounce_sample.visible_position.xyz - bounce_sample.sample_position.xyz);
+
+                if dot(candidate.direction, bounce_sample.sample_normal) > 0.0 && candidate.p > 0.0 {
+                    ray.origin = bounce_sample.sample_position.xyz + bounce_sample.sample_normal * RAY_BIAS;
+                    ray.direction = candidate.direction;
+                    ray.inv_direction = 1.0 / ray.direction;
+
+                    hit = traverse_top(ray, candidate.max_distance, candidate.min_distance, candidate.emissive_instance);
+                    // info = hit_info(ray, hit);
+                    occlude_hit_info(ray, hit, &info);
+
+                    var in_radiance = input_radiance(ray, info, sample_directional, candidate.emissive_instance, false, receiver);
+                    in_radiance = vec4<f32>(in_radiance.xyz, in_radiance.a);
+
+                    out_radiance = shading(
+                        view_direction,
+                        bounce_sample.sample_normal,
+                        ray.direction,
+                        surface,
+                        in_radiance
+                    );
+                    out_radiance = out_radiance / candidate.p;
+                    if n > 0u {
+                        out_radiance = select(out_radiance / rand_sample.w, vec3<f32>(0.0), rand_sample.w < 0.01);
+                    }
+
+                    // Do radiance clamping
+                    let out_luminance = luminance(out_radiance);
+                    if out_luminance > frame.max_indirect_luminance {
+                        out_radiance = out_radiance * frame.max_indirect_luminance / out_luminance;
+                    }
+
+                    s.radiance += vec4<f32>(color_transport * out_radiance, 1.0);
+                }
+            
+                // Lobe albedos approximate the reflection of the surface regardless of the input direction,
+                // which may be a good choice for color transport.
+                // Select the lobe of the next bounce, skipping those that have reached their depth caps.
+                surface.roughness = bounce_roughness;
+                bounce_surface = surface;
+                var albedo = lobe_albedo(view_direction, bounce_sample.sample_normal, surface);
+                let max_depth = vec3<u32>(frame.max_diffuse_bounces, frame.max_specular_bounces, frame.max_transmission_bounces);
+                var weights: array<f32, 5>;
+                var total_weight = 0.0;
+                for (var lobe = 0u; lobe < LOBE_COUNT; lobe += 1u) {
+                    let depth_index = lobe_depth_index(lobe);
+                    weights[lobe] = select(0.0, luminance(albedo[lobe]), lobe_depth[depth_index] < max_depth[depth_index]);
+                    total_weight += weights[lobe];
+                }
+                if total_weight <= 0.0 {
+                    break;
+                }
+
+                // Pick the last lobe with weight whose interval starts below the random number
+                let lobe_rand = min(random_float(seed ^ pcg_hash(2u * n)), 0.999999) * total_weight;
+                var lobe_start = 0.0;
+                for (var lobe = 0u; lobe < LOBE_COUNT; lobe += 1u) {
+                    if weights[lobe] > 0.0 && lobe_rand >= lobe_start {
+                        bounce_lobe = lobe;
+                    }
+                    lobe_start += weights[lobe];
+                }
+                color_transport *= albedo[bounce_lobe] * total_weight / weights[bounce_lobe];
+
+                // Russian roulette by the path throughput
+                if frame.russian_roulette > 0u && n + 1u >= frame.min_bounces {
+                    let throughput = max(color_transport.r, max(color_transport.g, color_transport.b));
+                    let survival = min(throughput, RUSSIAN_ROULETTE_MAX_SURVIVAL);
+                    if random_float(seed ^ pcg_hash(2u * n + 1u)) >= survival {
+                        break;
+                    }
+                    color_transport /= survival;
+                }
+
+                bounce_view_direction = view_direction;
+                bounce_sample.random = fract(bounce_sample.random + f32(frame.number) * GOLDEN_RATIO);
+                bounce_sample.visible_position = bounce_sample.sample_position;
+                bounce_sample.visible_normal = bounce_sample.sample_normal;
+            } else {
+                // Only ambient radiance
+                var out_radiance = input_radiance(ray, info, false, DONT_SAMPLE_EMISSIVE, true, U32_MAX).rgb;
+                s.radiance += vec4<f32>(color_transport * out_radiance, 0.0);
+                break;
+            }
+        }
+
+        // The radiance leaving the first secondary hit feeds the cache.
+        // The in-scattering and attenuation between the visible point and that hit depend on the view, so they are removed.
+        if frame.radiance_cache > 0u && s.sample_position.w > 0.5 && first_transmittance > 0.01 {
+            let surface_radiance = (s.radiance.rgb - first_in_scatter) / first_transmittance;
+            radiance_cache_update(s.sample_position.xyz, s.sample_normal, max(surface_radiance, vec3<f32>(0.0)));
+        }
+#else
+        var rand_sample = sample_cosine_hemisphere(s.random.xy);
+        ray.origin = s.visible_position.xyz + s.visible_normal * RAY_BIAS;
+        ray.direction = normal_basis(s.visible_normal) * rand_sample.xyz;
         ray.inv_direction = 1.0 / ray.direction;
 
         hit = traverse_top(ray, F32_MAX, 0.0, DONT_EXCLUDE);
         info = hit_info(ray, hit);
 
-        if n == 0u {
-            s.sample_position = info.position;
-            s.sample_normal = info.normal;
-            pdf = rand_sample.w;
+        s.sample_position = info.position;
+        s.sample_normal = info.normal;
+        pdf = rand_sample.w;
+
+        // Scattering and attenuation by participating media along the bounce
+        var medium = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        if fog_volume_buffer.count > 0u {
+            let segment = select(DISTANCE_MAX, hit.intersection.distance, hit.instance_index != U32_MAX);
+            let seed = pcg_hash(adaptive_seed ^ pcg_hash(sample_index));
+            medium = medium_scattering(ray, segment, s.random, seed);
+            s.radiance += vec4<f32>(medium.rgb, 0.0);
         }
 
-        bounce_sample.sample_position = info.position;
-        bounce_sample.sample_normal = info.normal;
-
-        // N bounce: from sample position
         if hit.instance_index != U32_MAX {
             var out_radiance = vec3<f32>(0.0);
 
-            surface = retreive_surface(info.material_index, info.uv);
+            surface = retreive_surface(info.material_index, info.uv, info.uv_1, info.color);
             surface.roughness = 1.0;
 
+            let receiver = info.instance_index;
             let candidate = select_light_candidate(
-                bounce_sample.random,
-                bounce_sample.sample_position.xyz,
-                bounce_sample.sample_normal,
-                info.instance_index,
+                s.random,
+                s.sample_position.xyz,
+                s.sample_normal,
+                receiver,
                 &info
             );
             let sample_directional = (candidate.emissive_instance == DONT_SAMPLE_EMISSIVE);
-            let bounce_view_direction = normalize(bounce_sample.visible_position.xyz - bounce_sample.sample_position.xyz);
 
-            if dot(candidate.direction, bounce_sample.sample_normal) > 0.0 && candidate.p > 0.0 {
-                ray.origin = bounce_sample.sample_position.xyz + bounce_sample.sample_normal * RAY_BIAS;
+            if dot(candidate.direction, s.sample_normal) > 0.0 && candidate.p > 0.0 {
+                ray.origin = s.sample_position.xyz + s.sample_normal * RAY_BIAS;
                 ray.direction = candidate.direction;
                 ray.inv_direction = 1.0 / ray.direction;
 
@@ -1354,121 +2806,46 @@ fn indirect_lit_ambient(@builtin(global_invocation_id) invocation_id: vec3<u32>)
                 // info = hit_info(ray, hit);
                 occlude_hit_info(ray, hit, &info);
 
-                var in_radiance = input_radiance(ray, info, sample_directional, candidate.emissive_instance, false);
+                var in_radiance = input_radiance(ray, info, sample_directional, candidate.emissive_instance, false, receiver);
                 in_radiance = vec4<f32>(in_radiance.xyz, in_radiance.a);
 
                 out_radiance = shading(
-                    bounce_view_direction,
-                    bounce_sample.sample_normal,
+                    normalize(s.visible_position.xyz - s.sample_position.xyz),
+                    s.sample_normal,
                     ray.direction,
                     surface,
                     in_radiance
                 );
                 out_radiance = out_radiance / candidate.p;
-                if n > 0u {
-                    out_radiance = select(out_radiance / rand_sample.w, vec3<f32>(0.0), rand_sample.w < 0.01);
-                }
-
-                // Do radiance clamping
-                let out_luminance = luminance(out_radiance);
-                if out_luminance > frame.max_indirect_luminance {
-                    out_radiance = out_radiance * frame.max_indirect_luminance / out_luminance;
-                }
-
-                s.radiance += vec4<f32>(color_transport * out_radiance, 1.0);
+                s.radiance += vec4<f32>(medium.a * out_radiance, 1.0);
             }
-            
-            // Env BRDF approximates the reflection of the surface regardless of the input direction,
-            // which may be a good choice for color transport.
-            color_transport *= env_brdf(bounce_view_direction, bounce_sample.sample_normal, surface);
-
-            bounce_sample.random = fract(bounce_sample.random + f32(frame.number) * GOLDEN_RATIO);
-            bounce_sample.visible_position = bounce_sample.sample_position;
-            bounce_sample.visible_normal = bounce_sample.sample_normal;
         } else {
             // Only ambient radiance
-            var out_radiance = input_radiance(ray, info, false, DONT_SAMPLE_EMISSIVE, true).rgb;
-            s.radiance += vec4<f32>(color_transport * out_radiance, 0.0);
-            break;
+            var out_radiance = input_radiance(ray, info, false, DONT_SAMPLE_EMISSIVE, true, U32_MAX).rgb;
+            s.radiance += vec4<f32>(medium.a * out_radiance, 0.0);
         }
-    }
-#else
-    var rand_sample = sample_cosine_hemisphere(s.random.xy);
-    ray.origin = s.visible_position.xyz + s.visible_normal * RAY_BIAS;
-    ray.direction = normal_basis(s.visible_normal) * rand_sample.xyz;
-    ray.inv_direction = 1.0 / ray.direction;
-
-    hit = traverse_top(ray, F32_MAX, 0.0, DONT_EXCLUDE);
-    info = hit_info(ray, hit);
-
-    s.sample_position = info.position;
-    s.sample_normal = info.normal;
-    pdf = rand_sample.w;
-
-    if hit.instance_index != U32_MAX {
-        var out_radiance = vec3<f32>(0.0);
-
-        surface = retreive_surface(info.material_index, info.uv);
-        surface.roughness = 1.0;
+#endif
 
-        let candidate = select_light_candidate(
-            s.random,
-            s.sample_position.xyz,
-            s.sample_normal,
-            info.instance_index,
-            &info
+        let sample_radiance = shading(
+            view_direction,
+            s.visible_normal,
+            normalize(s.sample_position.xyz - s.visible_position.xyz),
+            primary_surface,
+            s.radiance
         );
-        let sample_directional = (candidate.emissive_instance == DONT_SAMPLE_EMISSIVE);
-
-        if dot(candidate.direction, s.sample_normal) > 0.0 && candidate.p > 0.0 {
-            ray.origin = s.sample_position.xyz + s.sample_normal * RAY_BIAS;
-            ray.direction = candidate.direction;
-            ray.inv_direction = 1.0 / ray.direction;
-
-            hit = traverse_top(ray, candidate.max_distance, candidate.min_distance, candidate.emissive_instance);
-            // info = hit_info(ray, hit);
-            occlude_hit_info(ray, hit, &info);
-
-            var in_radiance = input_radiance(ray, info, sample_directional, candidate.emissive_instance, false);
-            in_radiance = vec4<f32>(in_radiance.xyz, in_radiance.a);
-
-            out_radiance = shading(
-                normalize(s.visible_position.xyz - s.sample_position.xyz),
-                s.sample_normal,
-                ray.direction,
-                surface,
-                in_radiance
-            );
-            out_radiance = out_radiance / candidate.p;
-            s.radiance += vec4<f32>(out_radiance, 1.0);
+        if frame.unbiased_restir > 0u {
+            stream_candidate(&canonical, &p_canonical, s, luminance(sample_radiance), pdf);
+        } else {
+            let w_new = select(0.0, luminance(sample_radiance) / pdf, pdf > 0.0);
+            temporal_restir(&r, s, w_new, frame.max_temporal_reuse_count);
         }
-    } else {
-        // Only ambient radiance
-        var out_radiance = input_radiance(ray, info, false, DONT_SAMPLE_EMISSIVE, true).rgb;
-        s.radiance += vec4<f32>(out_radiance, 0.0);
     }
-#endif
 
-    // ReSTIR: Temporal
-    let previous_uv = jittered_deferred_uv(uv) - velocity_uv.xy;
-    r = load_previous_reservoir(previous_uv, render_size);
-
-    if !check_previous_reservoir(&r, s) && all(abs(previous_uv - 0.5) <= vec2<f32>(0.5)) {
-        let previous_coords = vec2<i32>(previous_uv * vec2<f32>(render_size));
-        store_previous_spatial_reservoir(previous_coords.x + render_size.x * previous_coords.y, r);
+    if frame.unbiased_restir > 0u && canonical.count > 0.0 {
+        temporal_restir_unbiased(&r, canonical, p_canonical, primary_surface, true, frame.max_temporal_reuse_count);
     }
 
-    surface = retreive_surface(instance_material.y, velocity_uv.zw);
-    let view_direction = calculate_view(position, view.projection[3].w == 1.0);
-    let sample_radiance = shading(
-        view_direction,
-        s.visible_normal,
-        normalize(s.sample_position.xyz - s.visible_position.xyz),
-        surface,
-        s.radiance
-    );
-    let w_new = select(0.0, luminance(sample_radiance) / pdf, pdf > 0.0);
-    temporal_restir(&r, s, w_new, frame.max_temporal_reuse_count);
+    surface = primary_surface;
 
     let out_radiance = shading(
         view_direction,
@@ -1487,8 +2864,9 @@ fn indirect_lit_ambient(@builtin(global_invocation_id) invocation_id: vec3<u32>)
 
     var variance = r.w2_sum / r.count - pow(r.w_sum / r.count, 2.0);
     variance = select(variance / r.count, variance, r.count < 1.0);
-    variance = min(variance, MAX_VARIANCE);
+    variance = min(variance, frame.max_variance);
     textureStore(variance_texture, coords, vec4<f32>(variance));
+    adaptive_record(ADAPTIVE_PASS_INDIRECT, variance, random_float(adaptive_seed ^ 1u));
 
     if frame.temporal_reuse > 0u {
         store_reservoir(coords.x + render_size.x * coords.y, r);
@@ -1497,6 +2875,87 @@ fn indirect_lit_ambient(@builtin(global_invocation_id) invocation_id: vec3<u32>)
     textureStore(render_texture, coords, vec4<f32>(out_radiance * r.w, 1.0));
 }
 
+@compute @workgroup_size(8, 8, 1)
+fn fog_lit(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let render_size = textureDimensions(render_texture);
+    let coords = vec2<i32>(invocation_id.xy);
+    let uv = coords_to_uv(coords, render_size);
+
+    let deferred_coords = jittered_deferred_coords(uv);
+    sample_shutter_time(deferred_coords, textureDimensions(position_texture));
+    let position_depth = textureLoad(position_texture, deferred_coords, 0);
+    let depth = position_depth.w;
+
+    // Primary ray from the near plane, through the pixel towards the visible surface or the background
+    let ndc = vec2<f32>(2.0 * uv.x - 1.0, 1.0 - 2.0 * uv.y);
+    let near = view.inverse_view_proj * vec4<f32>(ndc, 1.0, 1.0);
+    let far = view.inverse_view_proj * vec4<f32>(ndc, 0.5, 1.0);
+
+    var ray: Ray;
+    ray.origin = near.xyz / near.w;
+    ray.direction = normalize(far.xyz / far.w - ray.origin);
+    var distance = DISTANCE_MAX;
+    if depth > F32_EPSILON {
+        let delta = position_depth.xyz - ray.origin;
+        distance = length(delta);
+        ray.direction = delta / distance;
+    }
+    ray.inv_direction = 1.0 / ray.direction;
+
+    let noise_id = frame.number % NOISE_TEXTURE_COUNT;
+    let noise_size = textureDimensions(noise_texture[noise_id]);
+    let noise_uv = (vec2<f32>(coords) + f32(frame.number) + 0.5) / vec2<f32>(noise_size);
+    var rand = textureSampleLevel(noise_texture[noise_id], noise_sampler, noise_uv, 0.0);
+    rand = fract(rand + f32(frame.number) * GOLDEN_RATIO);
+    let seed = pcg_hash(u32(coords.x + render_size.x * coords.y) ^ pcg_hash(frame.number));
+
+    var medium = medium_scattering(ray, distance, rand, seed);
+
+    // Do radiance clamping
+    let medium_luminance = luminance(medium.rgb);
+    if medium_luminance > frame.max_indirect_luminance {
+        medium = vec4<f32>(medium.rgb * frame.max_indirect_luminance / medium_luminance, medium.a);
+    }
+
+    // A single sample has no spread to measure; its second moment bounds the variance instead
+    let clamped_luminance = luminance(medium.rgb);
+    let variance = min(clamped_luminance * clamped_luminance, frame.max_variance);
+    textureStore(variance_texture, coords, vec4<f32>(variance));
+    textureStore(render_texture, coords, medium);
+}
+
+// Blends the samples summed this frame into each cell of the radiance cache, then clears the sums.
+@compute @workgroup_size(64, 1, 1)
+fn radiance_cache_resolve(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let index = invocation_id.x;
+    if index >= RADIANCE_CACHE_CAPACITY {
+        return;
+    }
+
+    let sample_count = atomicExchange(&radiance_cache_buffer.data[index].accumulation[3], 0u);
+    let claim = atomicExchange(&radiance_cache_buffer.data[index].claim, 0u);
+    let sum = vec3<u32>(
+        atomicExchange(&radiance_cache_buffer.data[index].accumulation[0], 0u),
+        atomicExchange(&radiance_cache_buffer.data[index].accumulation[1], 0u),
+        atomicExchange(&radiance_cache_buffer.data[index].accumulation[2], 0u)
+    );
+    if sample_count == 0u {
+        return;
+    }
+
+    var radiance = radiance_cache_buffer.data[index].radiance;
+    if radiance_cache_buffer.data[index].checksum != claim {
+        // Evict the colliding cell
+        radiance_cache_buffer.data[index].checksum = claim;
+        radiance = vec4<f32>(0.0);
+    }
+
+    let mean = vec3<f32>(sum) / (RADIANCE_CACHE_FIXED_POINT_SCALE * f32(sample_count));
+    let count = min(radiance.a + f32(sample_count), RADIANCE_CACHE_MAX_SAMPLES);
+    let weight = clamp(f32(sample_count) / count, frame.radiance_cache_update_rate, 1.0);
+    radiance_cache_buffer.data[index].radiance = vec4<f32>(mix(radiance.rgb, mean, weight), count);
+}
+
 var<workgroup> shared_reservoir: array<array<Reservoir, 8u>, 8u>;
 var<workgroup> shared_depth: array<array<f32, 8u>, 8u>;
 
@@ -1510,8 +2969,13 @@ fn spatial_reuse(
     let render_size = textureDimensions(render_texture);
 
     let coords = vec2<i32>(invocation_id.xy);
+#ifdef EMISSIVE_LIT
     let uv = coords_to_uv(coords, render_size);
+#else
+    let uv = stratified_uv(coords, render_size, frame.indirect_scale, frame.number);
+#endif
     let deferred_coords = jittered_deferred_coords(uv);
+    sample_shutter_time(deferred_coords, deferred_size);
 
     let position_depth = textureLoad(position_texture, deferred_coords, 0);
     let position = vec4<f32>(position_depth.xyz, 1.0);
@@ -1532,7 +2996,7 @@ fn spatial_reuse(
     let instance_material = vec2<u32>(textureLoad(instance_material_texture, deferred_coords, 0).xy);
     let velocity_uv = textureLoad(velocity_uv_texture, deferred_coords, 0);
 
-    let surface = retreive_surface(instance_material.y, velocity_uv.zw);
+    let surface = retreive_deferred_surface(instance_material.y, velocity_uv.zw, deferred_coords);
 
     let use_spatial_variance = r.count <= f32(SPATIAL_VARIANCE_SAMPLE_THRESHOLD);
 
@@ -1541,11 +3005,49 @@ fn spatial_reuse(
 
     var q = r;
     let s = q.s;
+    let canonical = q;
 
-    if r.lifetime <= reservoir_lifetime(r) {
+    let reuse_previous = r.lifetime <= reservoir_lifetime(r);
+    if reuse_previous {
         r = load_previous_spatial_reservoir(previous_uv, render_size);
     }
 
+#ifdef EMISSIVE_LIT
+    let shade = false;
+    let reuse_count = frame.emissive_spatial_reuse_count;
+    let reuse_range = frame.emissive_spatial_reuse_range * f32(render_size.y);
+#else
+    let shade = true;
+    let reuse_count = frame.indirect_spatial_reuse_count;
+    // The range is relative to the render height, which is that of the low resolution indirect render here
+    let reuse_range = frame.indirect_spatial_reuse_range * f32(render_size.y);
+#endif
+
+    // Unbiased reuse: the previous spatial reservoir and the neighbors are paired with this pixel,
+    // counting rejected ones as having zero target function
+    var unbiased: Reservoir;
+    var p_selected = 0.0;
+    let neighbor_count = f32(reuse_count + 1u);
+    let canonical_count = canonical.count / neighbor_count;
+    let p_canonical = shifted_target(canonical.s, s.visible_position.xyz, s.visible_normal, surface, shade, false);
+    var canonical_mis = neighbor_count + 1.0;
+    if frame.unbiased_restir > 0u {
+        unbiased.lifetime = r.lifetime;
+        if reuse_previous && r.count > 0.0 {
+            // The previous reservoir was visible on the previous frame's surface at the reprojected pixel
+            let previous_coords = vec2<i32>(previous_uv * vec2<f32>(textureDimensions(previous_position_texture)));
+            let previous_material = u32(textureLoad(previous_instance_material_texture, previous_coords, 0).y);
+            let previous_surface_uv = textureLoad(previous_velocity_uv_texture, previous_coords, 0).zw;
+            // Vertex colors and the second UV set are not kept across frames, so those of this frame are read there
+            let previous_surface = retreive_deferred_surface(previous_material, previous_surface_uv, previous_coords);
+
+            var previous = r;
+            previous.s.visible_position = textureLoad(previous_position_texture, previous_coords, 0);
+            previous.s.visible_normal = normalize(textureLoad(previous_normal_texture, previous_coords, 0).xyz);
+            canonical_mis -= pairwise_reuse(&unbiased, &p_selected, previous, canonical, p_canonical, canonical_count, surface, previous_surface, shade);
+        }
+    }
+
     let view_direction = calculate_view(position, view.projection[3].w == 1.0);
 #ifdef EMISSIVE_LIT
     merge_reservoir(&r, q, luminance(q.s.radiance.rgb));
@@ -1563,16 +3065,21 @@ fn spatial_reuse(
     r.s.visible_position = s.visible_position;
     r.s.visible_normal = s.visible_normal;
 
-    for (var i = 1u; i <= SPATIAL_REUSE_COUNT; i += 1u) {
+    for (var i = 1u; i <= reuse_count; i += 1u) {
         // Fibonacci spiral: http://extremelearning.com.au/how-to-evenly-distribute-points-on-a-sphere-more-effectively-than-the-canonical-fibonacci-lattice/
         let polar_offset = vec2<f32>(
             TAU * fract(f32(i) * GOLDEN_RATIO + dot(s.random, vec4<f32>(1.0)) + random_float(frame.number)),
-            sqrt(f32(i) / f32(SPATIAL_REUSE_COUNT)) * SPATIAL_REUSE_RANGE
+            sqrt(f32(i) / f32(reuse_count)) * reuse_range
         );
         let offset = polar_offset.y * vec2<f32>(cos(polar_offset.x), sin(polar_offset.x));
 
         let sample_coords = vec2<i32>(offset + vec2<f32>(coords));
+#ifdef EMISSIVE_LIT
         let sample_uv = coords_to_uv(sample_coords, render_size);
+#else
+        // The neighbor traced from the pixel of its block picked this frame
+        let sample_uv = stratified_uv(sample_coords, render_size, frame.indirect_scale, frame.number);
+#endif
         let sample_deferred_coords = jittered_deferred_coords(sample_uv);
         if any(sample_uv < vec2<f32>(0.0)) || any(sample_uv > vec2<f32>(1.0)) {
             continue;
@@ -1600,13 +3107,21 @@ fn spatial_reuse(
             continue;
         }
 
+        if frame.unbiased_restir > 0u {
+            let sample_material = u32(textureLoad(instance_material_texture, sample_deferred_coords, 0).y);
+            let sample_texture_uv = textureLoad(velocity_uv_texture, sample_deferred_coords, 0).zw;
+            let sample_surface = retreive_deferred_surface(sample_material, sample_texture_uv, sample_deferred_coords);
+            canonical_mis -= pairwise_reuse(&unbiased, &p_selected, q, canonical, p_canonical, canonical_count, surface, sample_surface, shade);
+            continue;
+        }
+
         let sample_direction = normalize(q.s.sample_position.xyz - s.visible_position.xyz);
         if dot(sample_direction, s.visible_normal) < 0.0 {
             continue;
         }
 
         // Perform screen-space ray-marching the depth to reject samples
-        let tap_interval = max(1.0, polar_offset.y / f32(SPATIAL_REUSE_TAPS + 1u));
+        let tap_interval = max(1.0, polar_offset.y / f32(frame.spatial_reuse_taps + 1u));
         let tap_count = u32(polar_offset.y / tap_interval);
         var occluded = false;
         for (var j = 1u; j <= tap_count; j += 1u) {
@@ -1642,6 +3157,19 @@ fn spatial_reuse(
 #endif
     }
 
+    if frame.unbiased_restir > 0u {
+        let w_canonical = canonical_mis * p_canonical * canonical.w;
+        stream_reservoir(&unbiased, &p_selected, canonical.s, w_canonical, p_canonical);
+        unbiased.count += canonical.count;
+
+        let w = select(0.0, unbiased.w_sum / ((neighbor_count + 1.0) * p_selected), p_selected > 0.0);
+        normalize_reservoir(&unbiased, w, p_selected);
+
+        unbiased.s.visible_position = s.visible_position;
+        unbiased.s.visible_normal = s.visible_normal;
+        r = unbiased;
+    }
+
     // Clamp...
     let m = f32(frame.max_spatial_reuse_count);
     if r.count > m {
@@ -1671,14 +3199,37 @@ fn spatial_reuse(
     if use_spatial_variance {
         var variance = r.w2_sum / r.count - pow(r.w_sum / r.count, 2.0);
         variance = select(variance / r.count, variance, r.count < 1.0);
-        variance = min(variance, MAX_VARIANCE);
+        variance = min(variance, frame.max_variance);
         textureStore(variance_texture, coords, vec4<f32>(variance));
     }
 
+    var final_radiance = r.w * out_radiance;
+#ifdef EMISSIVE_LIT
+    if instance_buffer[instance_material.x].subsurface.a > 0.0 {
+        final_radiance = r.w * shading(
+            view_direction,
+            s.visible_normal,
+            normalize(r.s.sample_position.xyz - s.visible_position.xyz),
+            subsurface_specular(surface),
+            r.s.radiance
+        );
+        let seed = pcg_hash(u32(coords.x + render_size.x * coords.y) ^ pcg_hash(frame.number));
+        final_radiance += subsurface_radiance(
+            seed,
+            position.xyz,
+            s.visible_normal,
+            instance_material.x,
+            surface,
+            false,
+            instance_material.x
+        );
+    }
+#endif
+
 #ifdef RENDER_EMISSIVE
-    let out_color = r.w * out_radiance + compute_emissive_radiance(surface.emissive);
+    let out_color = final_radiance + compute_emissive_radiance(surface.emissive);
 #else
-    let out_color = r.w * out_radiance;
+    let out_color = final_radiance;
 #endif
     textureStore(render_texture, coords, vec4<f32>(out_color, 1.0));
 }
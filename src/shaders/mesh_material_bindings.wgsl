@@ -20,3 +20,9 @@ var<storage> material_buffer: Materials;
 var<storage> emissive_node_buffer: Nodes;
 @group(2) @binding(8)
 var<storage> emissive_buffer: Emissives;
+@group(2) @binding(9)
+var<storage> area_light_buffer: AreaLights;
+@group(2) @binding(10)
+var<storage> light_profile_buffer: LightProfiles;
+@group(2) @binding(11)
+var<storage> fog_volume_buffer: FogVolumes;
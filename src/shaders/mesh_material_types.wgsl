@@ -5,6 +5,8 @@ struct Vertex {
     u: f32,
     normal: vec3<f32>,
     v: f32,
+    color: u32,     // pack4x8unorm
+    uv_1: u32,      // pack2x16float
 };
 
 struct PrimitiveVertex {
@@ -29,7 +31,12 @@ struct Instance {
     node_index: u32,
     model: mat4x4<f32>,
     inverse_transpose_model: mat4x4<f32>,
+    previous_model: mat4x4<f32>,
     mesh: MeshIndex,
+    light_groups: u32,
+    light_include: u32,
+    light_exclude: u32,
+    subsurface: vec4<f32>,
 };
 
 struct Node {
@@ -53,11 +60,22 @@ struct Material {
 
     normal_map_texture: u32,
     occlusion_texture: u32,
+
+    clearcoat: f32,
+    clearcoat_perceptual_roughness: f32,
+    anisotropy_strength: f32,
+    anisotropy_rotation: f32,
+    sheen_color: vec3<f32>,
+    sheen_perceptual_roughness: f32,
+    flags: u32,
+    uv_transform: mat3x3<f32>,
 };
 
 struct AliasEntry {
     prob: f32,
     index: u32,
+    primitive: u32,
+    density: f32,
 }
 
 struct Emissive {
@@ -67,9 +85,36 @@ struct Emissive {
     instance: u32,
     alias_table: vec2<u32>,
     surface_area: f32,
+    light_include: u32,
+    light_exclude: u32,
     node_index: u32,
 };
 
+struct AreaLight {
+    radiance: vec3<f32>,
+    kind: u32,
+    position: vec3<f32>,
+    range: f32,
+    axis_x: vec3<f32>,
+    area: f32,
+    axis_y: vec3<f32>,
+    two_sided: u32,
+    profile: u32,
+    cookie: u32,
+    cookie_scale: f32,
+    light_include: u32,
+    light_exclude: u32,
+};
+
+struct FogVolume {
+    inverse_model: mat4x4<f32>,
+    albedo: vec3<f32>,
+    density: f32,
+    anisotropy: f32,
+    shape: u32,
+    light_groups: u32,
+};
+
 type Vertices = array<Vertex>;
 type Primitives = array<Primitive>;
 type Instances = array<Instance>;
@@ -77,6 +122,21 @@ type Materials = array<Material>;
 type AliasTable = array<AliasEntry>;
 type Emissives = array<Emissive>;
 
+struct AreaLights {
+    count: u32,
+    data: array<AreaLight>,
+};
+
+struct LightProfiles {
+    count: u32,
+    data: array<f32>,
+};
+
+struct FogVolumes {
+    count: u32,
+    data: array<FogVolume>,
+};
+
 struct Nodes {
     count: u32,
     data: array<Node>,
@@ -11,12 +11,41 @@ struct Frame {
     temporal_reuse: u32,
     emissive_spatial_reuse: u32,
     indirect_spatial_reuse: u32,
+    unbiased_restir: u32,
+    emissive_spatial_reuse_count: u32,
+    indirect_spatial_reuse_count: u32,
+    emissive_spatial_reuse_range: f32,
+    indirect_spatial_reuse_range: f32,
+    spatial_reuse_taps: u32,
     max_temporal_reuse_count: u32,
     max_spatial_reuse_count: u32,
     max_reservoir_lifetime: f32,
     solar_angle: f32,
     max_indirect_luminance: f32,
+    max_variance: f32,
+    position_miss_threshold: f32,
     upscale_ratio: f32,
+    radiance_cache: u32,
+    radiance_cache_cell_size: f32,
+    radiance_cache_update_rate: f32,
+    radiance_cache_debug: u32,
+    russian_roulette: u32,
+    min_bounces: u32,
+    max_diffuse_bounces: u32,
+    max_specular_bounces: u32,
+    max_transmission_bounces: u32,
+    lens_blade_count: u32,
+    lens_radius: f32,
+    focus_distance: f32,
+    shutter: f32,
+    backface_culling: u32,
+    indirect_scale: u32,
+    adaptive_sampling: u32,
+    adaptive_emissive_samples: f32,
+    adaptive_indirect_samples: f32,
+    adaptive_max_samples: u32,
+    tonemapping: u32,
+    exposure: f32,
 };
 
 struct PreviousView {
@@ -1,5 +1,5 @@
-#import bevy_core_pipeline::tonemapping
 #import bevy_hikari::utils
+#import bevy_hikari::tonemapping_operators
 
 @group(0) @binding(0)
 var input_texture: texture_2d<f32>;
@@ -25,12 +25,6 @@ struct FragmentOutput {
     @location(0) color: vec4<f32>,
 }
 
-fn inverse_reintard_luminance(color: vec3<f32>) -> vec3<f32> {
-    let l_old = clamp(tonemapping_luminance(color), 0.0005, 0.995);
-    let l_new = l_old / (1.0 - l_old);
-    return tonemapping_change_luminance(color, l_new);
-}
-
 @fragment
 fn fragment(in: VertexOutput) -> FragmentOutput {
     var out: FragmentOutput;
@@ -41,8 +35,20 @@ fn fragment(in: VertexOutput) -> FragmentOutput {
     out.color = textureSample(input_texture, linear_sampler, uv);
     out.color = select(out.color, albedo, any_is_nan_vec4(out.color));
 
+    // Hand linear radiance to the tone mapping of the camera
 #ifdef HDR
-    out.color = vec4<f32>(inverse_reintard_luminance(out.color.rgb), out.color.a);
+#ifdef TONEMAPPING_REINHARD
+    out.color = vec4<f32>(inverse_tonemap_reinhard(out.color.rgb), out.color.a);
+#endif
+#ifdef TONEMAPPING_ACES_FITTED
+    out.color = vec4<f32>(inverse_tonemap_aces_fitted(out.color.rgb), out.color.a);
+#endif
+#ifdef TONEMAPPING_AGX
+    out.color = vec4<f32>(inverse_tonemap_agx(out.color.rgb), out.color.a);
+#endif
+#ifdef TONEMAPPING_HUE_PRESERVING
+    out.color = vec4<f32>(inverse_tonemap_hue_preserving(out.color.rgb), out.color.a);
+#endif
 #endif
 
     return out;
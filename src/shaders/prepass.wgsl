@@ -16,7 +16,15 @@ let PI: f32 = 3.1415926;
 struct Vertex {
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
+#ifdef VERTEX_UVS
     @location(2) uv: vec2<f32>,
+#endif
+#ifdef VERTEX_UVS_1
+    @location(3) uv_1: vec2<f32>,
+#endif
+#ifdef VERTEX_COLORS
+    @location(4) color: vec4<f32>,
+#endif
 };
 
 struct VertexOutput {
@@ -25,6 +33,8 @@ struct VertexOutput {
     @location(1) previous_world_position: vec4<f32>,
     @location(2) world_normal: vec3<f32>,
     @location(3) uv: vec2<f32>,
+    @location(4) uv_1: vec2<f32>,
+    @location(5) color: vec4<f32>,
 };
 
 fn frame_jitter() -> vec2<f32> {
@@ -66,7 +76,20 @@ fn vertex(vertex: Vertex) -> VertexOutput {
 
     out.world_normal = mesh_normal_local_to_world(vertex.normal);
     out.clip_position = view.view_proj * out.world_position;
+
+    // Missing optional attributes default to zero UVs and white color
+    out.uv = vec2<f32>(0.0);
+#ifdef VERTEX_UVS
     out.uv = vertex.uv;
+#endif
+    out.uv_1 = out.uv;
+#ifdef VERTEX_UVS_1
+    out.uv_1 = vertex.uv_1;
+#endif
+    out.color = vec4<f32>(1.0);
+#ifdef VERTEX_COLORS
+    out.color = vertex.color;
+#endif
 
     out.clip_position += vec4<f32>(jitter.x, -jitter.y, 0.0, 0.0) * out.clip_position.w;
 
@@ -79,6 +102,7 @@ struct FragmentOutput {
     @location(2) depth_gradient: vec2<f32>,
     @location(3) instance_material: vec2<f32>,
     @location(4) velocity_uv: vec4<f32>,
+    @location(5) vertex_color_uv: vec4<u32>,
 };
 
 @fragment
@@ -95,6 +119,11 @@ fn fragment(in: VertexOutput) -> FragmentOutput {
 
     let velocity = clip_to_uv(view.view_proj * in.world_position) - clip_to_uv(previous_view.view_proj * in.previous_world_position);
     out.velocity_uv = vec4<f32>(velocity, in.uv);
+    out.vertex_color_uv = vec4<u32>(
+        pack2x16float(in.color.rg),
+        pack2x16float(in.color.ba),
+        bitcast<vec2<u32>>(in.uv_1)
+    );
 
     return out;
 }
@@ -84,6 +84,9 @@ fn smaa_tu4x(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     let output_size = textureDimensions(output_texture);
     let deferred_size = textureDimensions(position_texture);
     let coords = vec2<i32>(invocation_id.xy);
+    if any(coords >= input_size) {
+        return;
+    }
     let uv = coords_to_uv(coords, input_size);
 
     // In this implementation, a thread computes 4 output pixels in a quad.
@@ -138,16 +141,22 @@ fn smaa_tu4x(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     let current_instance = textureSampleLevel(instance_material_texture, nearest_sampler, previous_output_uv, 0.0).x;
     var instance_miss = false;
 
-    let current_depth = textureSampleLevel(position_texture, nearest_sampler, previous_output_uv, 0.0).w;
+    let current_position_depth = textureSampleLevel(position_texture, nearest_sampler, previous_output_uv, 0.0);
+    let current_depth = current_position_depth.w;
     var depth_miss = current_depth == 0.0;
 
+    // Under defocus, the surface seen through a pixel wanders within the circle of confusion
+    let view_distance = max(distance(current_position_depth.xyz, view.world_position.xyz), 0.0001);
+    let circle_of_confusion = 2.0 * frame.lens_radius * abs(view_distance - frame.focus_distance) / frame.focus_distance;
+    let depth_ratio_threshold = 0.95 * (1.0 - min(circle_of_confusion / view_distance, 0.5));
+
     for (var i = 0u; i < 5u; i += 1u) {
         let previous_depths = textureGather(3, previous_position_texture, linear_sampler, previous_reprojected_uv + uv_biases[i]);
         let depth_ratio = select(vec4<f32>(current_depth) / previous_depths, vec4<f32>(1.0), previous_depths == vec4<f32>(0.0));
-        depth_miss = depth_miss || any(depth_ratio < vec4<f32>(0.95));
+        depth_miss = depth_miss || any(depth_ratio < vec4<f32>(depth_ratio_threshold));
 
         let previous_instance = textureSampleLevel(instance_material_texture, nearest_sampler, previous_reprojected_uv + uv_biases[i], 0.0).x;
-        instance_miss = instance_miss || (any(depth_ratio < vec4<f32>(0.95)) && abs(previous_instance - current_instance) > 1.0);
+        instance_miss = instance_miss || (any(depth_ratio < vec4<f32>(depth_ratio_threshold)) && abs(previous_instance - current_instance) > 1.0);
     }
 
     let previous_velocity = textureSampleLevel(previous_velocity_uv_texture, nearest_sampler, previous_reprojected_uv, 0.0).xy;
@@ -198,6 +207,12 @@ fn smaa_tu4x(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     textureStore(output_texture, previous_output_coords, vec4<f32>(previous_color, 1.0));
 }
 
+// Neighbours of the border quads are clamped into the output, which is exactly twice the render size
+fn load_output(coords: vec2<i32>) -> vec4<f32> {
+    let size = textureDimensions(output_texture);
+    return textureLoad(output_texture, clamp(coords, vec2<i32>(0), size - vec2<i32>(1)));
+}
+
 fn differential_blend_factor(
     t: vec4<f32>,
     b: vec4<f32>,
@@ -240,6 +255,9 @@ fn differential_blend(
 fn smaa_tu4x_extrapolate(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     let input_size = textureDimensions(render_texture);
     let coords = vec2<i32>(invocation_id.xy);
+    if any(coords >= input_size) {
+        return;
+    }
     let uv = coords_to_uv(coords, input_size);
 
     let frame_index = frame.number % 2u;
@@ -253,12 +271,12 @@ fn smaa_tu4x_extrapolate(@builtin(global_invocation_id) invocation_id: vec3<u32>
     //  2 |  |s |  |  |
     //    +--+--+--+--+
 
-    let t_color = textureLoad(output_texture, 2 * coords);
-    let b_color = textureLoad(output_texture, 2 * coords + vec2<i32>(1, 1));
-    let n_color = textureLoad(output_texture, 2 * coords + vec2<i32>(1, -1));
-    let e_color = textureLoad(output_texture, 2 * coords + vec2<i32>(2, 0));
-    let s_color = textureLoad(output_texture, 2 * coords + vec2<i32>(0, 2));
-    let w_color = textureLoad(output_texture, 2 * coords + vec2<i32>(-1, 1));
+    let t_color = load_output(2 * coords);
+    let b_color = load_output(2 * coords + vec2<i32>(1, 1));
+    let n_color = load_output(2 * coords + vec2<i32>(1, -1));
+    let e_color = load_output(2 * coords + vec2<i32>(2, 0));
+    let s_color = load_output(2 * coords + vec2<i32>(0, 2));
+    let w_color = load_output(2 * coords + vec2<i32>(-1, 1));
 
     let factor = differential_blend_factor(t_color, b_color, n_color, e_color, s_color, w_color);
     let x_color = differential_blend(t_color, s_color, w_color, b_color, factor);
@@ -106,6 +106,11 @@ fn taa_jasmine(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     var depth_miss = current_position_depth.w == 0.0;
     var position_miss = current_position_depth.w == 0.0;
 
+    // Under defocus, the surface seen through a pixel wanders within the circle of confusion
+    let view_distance = distance(current_position_depth.xyz, view.world_position.xyz);
+    let circle_of_confusion = 2.0 * frame.lens_radius * abs(view_distance - frame.focus_distance) / frame.focus_distance;
+    let position_miss_threshold = frame.position_miss_threshold * view_distance + circle_of_confusion;
+
     for (var i = 0u; i < 5u; i += 1u) {
         let previous_depths = textureGather(3, previous_position_texture, linear_sampler, previous_uv + uv_biases[i]);
         let depth_ratio = select(vec4<f32>(current_position_depth.w) / previous_depths, vec4<f32>(1.0), previous_depths == vec4<f32>(0.0));
@@ -113,7 +118,7 @@ fn taa_jasmine(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
         depth_miss = depth_miss || any(depth_ratio < vec4<f32>(0.95));
 
         let previous_position = textureSampleLevel(previous_position_texture, nearest_sampler, previous_uv + uv_biases[i], 0.0).xyz;
-        position_miss = position_miss || distance(current_position_depth.xyz, previous_position) > 0.5;
+        position_miss = position_miss || distance(current_position_depth.xyz, previous_position) > position_miss_threshold;
     }
 
     if !has_content {
@@ -1,7 +1,7 @@
-#import bevy_core_pipeline::tonemapping
 #import bevy_hikari::mesh_view_bindings
 #import bevy_hikari::deferred_bindings
 #import bevy_hikari::utils
+#import bevy_hikari::tonemapping_operators
 
 @group(2) @binding(0)
 var nearest_sampler: sampler;
@@ -14,19 +14,153 @@ var direct_render_texture: texture_2d<f32>;
 var emissive_render_texture: texture_2d<f32>;
 @group(3) @binding(2)
 var indirect_render_texture: texture_2d<f32>;
+@group(3) @binding(3)
+var fog_render_texture: texture_2d<f32>;
 
 @group(4) @binding(0)
 var output_texture: texture_storage_2d<rgba16float, read_write>;
 
-@compute @workgroup_size(8, 8, 1)
-fn tone_mapping(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
-    let coords = vec2<i32>(invocation_id.xy);
+struct AutoExposure {
+    enabled: u32,
+    min_ev: f32,
+    max_ev: f32,
+    speed: f32,
+    delta_time: f32,
+};
+
+struct AutoExposureState {
+    histogram: array<atomic<u32>, 256>,
+    ev: f32,
+    initialized: u32,
+};
 
+@group(5) @binding(0)
+var<uniform> auto_exposure: AutoExposure;
+@group(5) @binding(1)
+var<storage, read_write> auto_exposure_state: AutoExposureState;
+@group(5) @binding(2)
+var metering_mask_texture: texture_2d<f32>;
+
+let HISTOGRAM_BIN_COUNT: u32 = 256u;
+// Metering mask weights are accumulated in fixed point
+let HISTOGRAM_WEIGHT_SCALE: f32 = 16.0;
+// The adapted average luminance is mapped to middle grey
+let AUTO_EXPOSURE_KEY: f32 = 0.18;
+
+var<workgroup> shared_histogram: array<atomic<u32>, 256>;
+var<workgroup> shared_weight: array<f32, 256>;
+var<workgroup> shared_weighted_ev: array<f32, 256>;
+
+// Sum of the renders, attenuated by the media in front, with the light they scatter towards the camera
+fn combined_render(coords: vec2<i32>) -> vec4<f32> {
     var color = textureLoad(direct_render_texture, coords, 0);
     color += textureLoad(emissive_render_texture, coords, 0);
     color += textureLoad(indirect_render_texture, coords, 0);
 
-    color = vec4<f32>(reinhard_luminance(max(color.rgb, vec3<f32>(0.0039))), color.a);
-    color = select(frame.clear_color, color, color.a > 0.0);
+    let fog = textureLoad(fog_render_texture, coords, 0);
+    return vec4<f32>(color.rgb * fog.a + fog.rgb, color.a);
+}
+
+// The first bin holds everything darker than the minimum EV, the rest split the range evenly
+fn histogram_bin(value: f32) -> u32 {
+    let range = max(auto_exposure.max_ev - auto_exposure.min_ev, 0.0001);
+    let t = (log2(max(value, 1e-10)) - auto_exposure.min_ev) / range;
+    if t < 0.0 {
+        return 0u;
+    }
+    return 1u + min(u32(t * f32(HISTOGRAM_BIN_COUNT - 1u)), HISTOGRAM_BIN_COUNT - 2u);
+}
+
+fn histogram_bin_ev(bin: u32) -> f32 {
+    if bin == 0u {
+        return auto_exposure.min_ev;
+    }
+    let t = (f32(bin - 1u) + 0.5) / f32(HISTOGRAM_BIN_COUNT - 1u);
+    return mix(auto_exposure.min_ev, auto_exposure.max_ev, t);
+}
+
+fn exposure_scale() -> f32 {
+    var scale = frame.exposure;
+    if auto_exposure.enabled != 0u && auto_exposure_state.initialized != 0u {
+        scale *= AUTO_EXPOSURE_KEY * exp2(-auto_exposure_state.ev);
+    }
+    return scale;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn luminance_histogram(
+    @builtin(global_invocation_id) invocation_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    for (var bin = local_index; bin < HISTOGRAM_BIN_COUNT; bin += 64u) {
+        atomicStore(&shared_histogram[bin], 0u);
+    }
+    workgroupBarrier();
+
+    let size = textureDimensions(direct_render_texture);
+    let coords = vec2<i32>(invocation_id.xy);
+    if all(coords < size) {
+        // Only surfaces are metered; the clear color is not scene luminance
+        let color = combined_render(coords);
+        if color.a > 0.0 {
+            let uv = coords_to_uv(coords, size);
+            let weight = textureSampleLevel(metering_mask_texture, linear_sampler, uv, 0.0).r;
+            let bin = histogram_bin(luminance(color.rgb));
+            atomicAdd(&shared_histogram[bin], u32(max(weight, 0.0) * HISTOGRAM_WEIGHT_SCALE + 0.5));
+        }
+    }
+    workgroupBarrier();
+
+    for (var bin = local_index; bin < HISTOGRAM_BIN_COUNT; bin += 64u) {
+        let count = atomicLoad(&shared_histogram[bin]);
+        if count > 0u {
+            atomicAdd(&auto_exposure_state.histogram[bin], count);
+        }
+    }
+}
+
+@compute @workgroup_size(256, 1, 1)
+fn auto_exposure(@builtin(local_invocation_index) local_index: u32) {
+    // Read the histogram of this frame, and leave it empty for the next
+    let weight = f32(atomicExchange(&auto_exposure_state.histogram[local_index], 0u));
+    shared_weight[local_index] = weight;
+    shared_weighted_ev[local_index] = weight * histogram_bin_ev(local_index);
+    workgroupBarrier();
+
+    for (var stride = HISTOGRAM_BIN_COUNT / 2u; stride > 0u; stride = stride / 2u) {
+        if local_index < stride {
+            shared_weight[local_index] += shared_weight[local_index + stride];
+            shared_weighted_ev[local_index] += shared_weighted_ev[local_index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if local_index != 0u || shared_weight[0] <= 0.0 {
+        return;
+    }
+
+    let metered_ev = clamp(shared_weighted_ev[0] / shared_weight[0], auto_exposure.min_ev, auto_exposure.max_ev);
+    var ev = metered_ev;
+    if auto_exposure_state.initialized != 0u {
+        let rate = 1.0 - exp(-auto_exposure.speed * auto_exposure.delta_time);
+        ev = mix(auto_exposure_state.ev, metered_ev, rate);
+    }
+    auto_exposure_state.ev = ev;
+    auto_exposure_state.initialized = 1u;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn tone_mapping(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let coords = vec2<i32>(invocation_id.xy);
+    let exposure = exposure_scale();
+
+    var color = combined_render(coords);
+    let fog = textureLoad(fog_render_texture, coords, 0);
+    let scattered = tonemap(fog.rgb * exposure, frame.tonemapping);
+    let background = vec4<f32>(frame.clear_color.rgb * fog.a + scattered, frame.clear_color.a);
+
+    let exposed = max(color.rgb * exposure, vec3<f32>(0.0));
+    color = vec4<f32>(tonemap(exposed, frame.tonemapping), color.a);
+    color = select(background, color, color.a > 0.0);
     textureStore(output_texture, coords, color);
 }
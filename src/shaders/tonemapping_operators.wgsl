@@ -0,0 +1,139 @@
+#define_import_path bevy_hikari::tonemapping_operators
+
+// Largest display value the inverse operators are evaluated at, which keeps them finite
+let TONEMAPPING_MAX_INVERSE: f32 = 0.995;
+
+fn tonemapping_rec709_luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+fn tonemap_reinhard(color: vec3<f32>) -> vec3<f32> {
+    let l_old = tonemapping_rec709_luminance(color);
+    let l_new = l_old / (1.0 + l_old);
+    return color * l_new / max(l_old, 0.0001);
+}
+
+fn inverse_tonemap_reinhard(color: vec3<f32>) -> vec3<f32> {
+    let l_old = clamp(tonemapping_rec709_luminance(color), 0.0005, TONEMAPPING_MAX_INVERSE);
+    let l_new = l_old / (1.0 - l_old);
+    return color * l_new / l_old;
+}
+
+// https://knarkowicz.wordpress.com/2016/01/06/aces-filmic-tone-mapping-curve/
+let ACES_A: f32 = 2.51;
+let ACES_B: f32 = 0.03;
+let ACES_C: f32 = 2.43;
+let ACES_D: f32 = 0.59;
+let ACES_E: f32 = 0.14;
+let ACES_INPUT_SCALE: f32 = 0.6;
+
+fn tonemap_aces_fitted(color: vec3<f32>) -> vec3<f32> {
+    let x = color * ACES_INPUT_SCALE;
+    return saturate((x * (ACES_A * x + ACES_B)) / (x * (ACES_C * x + ACES_D) + ACES_E));
+}
+
+fn inverse_tonemap_aces_fitted(color: vec3<f32>) -> vec3<f32> {
+    // Positive root of (a - c y) x^2 + (b - d y) x - e y = 0
+    let y = clamp(color, vec3<f32>(0.0), vec3<f32>(TONEMAPPING_MAX_INVERSE));
+    let a = ACES_A - ACES_C * y;
+    let b = ACES_B - ACES_D * y;
+    let x = (sqrt(b * b + 4.0 * a * ACES_E * y) - b) / (2.0 * a);
+    return x / ACES_INPUT_SCALE;
+}
+
+// https://iolite-engine.com/blog_posts/minimal_agx_implementation
+let AGX_MIN_EV: f32 = -12.47393;
+let AGX_MAX_EV: f32 = 4.026069;
+let AGX_INVERSE_ITERATIONS: u32 = 12u;
+
+fn agx_inset() -> mat3x3<f32> {
+    return mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+}
+
+fn agx_outset() -> mat3x3<f32> {
+    return mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+}
+
+// Polynomial fit of the AgX sigmoid, monotonic on [0, 1]
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+fn tonemap_agx(color: vec3<f32>) -> vec3<f32> {
+    var x = agx_inset() * max(color, vec3<f32>(1e-10));
+    x = clamp(log2(x), vec3<f32>(AGX_MIN_EV), vec3<f32>(AGX_MAX_EV));
+    x = (x - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+    x = agx_contrast(x);
+    x = agx_outset() * x;
+    return pow(saturate(x), vec3<f32>(2.2));
+}
+
+fn inverse_tonemap_agx(color: vec3<f32>) -> vec3<f32> {
+    let y = agx_inset() * pow(saturate(color), vec3<f32>(1.0 / 2.2));
+
+    // The sigmoid has no closed form inverse, so bisect it
+    var lower = vec3<f32>(0.0);
+    var upper = vec3<f32>(1.0);
+    for (var i = 0u; i < AGX_INVERSE_ITERATIONS; i += 1u) {
+        let middle = 0.5 * (lower + upper);
+        let below = agx_contrast(middle) < y;
+        lower = select(lower, middle, below);
+        upper = select(middle, upper, below);
+    }
+
+    let x = 0.5 * (lower + upper) * (AGX_MAX_EV - AGX_MIN_EV) + AGX_MIN_EV;
+    return max(agx_outset() * exp2(x), vec3<f32>(0.0));
+}
+
+// Hue is kept by compressing the largest channel. The path to white only depends on the compressed value,
+// so that the largest channel is unchanged by it and the operator can be inverted.
+let HUE_PRESERVING_WHITE_START: f32 = 0.6;
+let HUE_PRESERVING_MAX_WHITE: f32 = 0.9;
+
+fn hue_preserving_white(value: f32) -> f32 {
+    return HUE_PRESERVING_MAX_WHITE * smoothstep(HUE_PRESERVING_WHITE_START, 1.0, value);
+}
+
+fn tonemap_hue_preserving(color: vec3<f32>) -> vec3<f32> {
+    let x = max(color, vec3<f32>(0.0));
+    let peak = max(max(x.r, x.g), x.b);
+    let compressed = peak / (1.0 + peak);
+    let hue = x * compressed / max(peak, 0.0001);
+    return mix(hue, vec3<f32>(compressed), hue_preserving_white(compressed));
+}
+
+fn inverse_tonemap_hue_preserving(color: vec3<f32>) -> vec3<f32> {
+    let y = max(color, vec3<f32>(0.0));
+    let compressed = clamp(max(max(y.r, y.g), y.b), 0.0005, TONEMAPPING_MAX_INVERSE);
+    let white = hue_preserving_white(compressed);
+    let hue = (y - white * compressed) / (1.0 - white);
+    let peak = compressed / (1.0 - compressed);
+    return max(hue, vec3<f32>(0.0)) * peak / compressed;
+}
+
+// Modes are indexed as in `HikariTonemapping`
+fn tonemap(color: vec3<f32>, mode: u32) -> vec3<f32> {
+    if mode == 1u {
+        return tonemap_reinhard(color);
+    }
+    if mode == 2u {
+        return tonemap_aces_fitted(color);
+    }
+    if mode == 3u {
+        return tonemap_agx(color);
+    }
+    if mode == 4u {
+        return tonemap_hue_preserving(color);
+    }
+    return color;
+}
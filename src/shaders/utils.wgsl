@@ -27,6 +27,14 @@ fn random_float(value: u32) -> f32 {
     return f32(hash(value)) / 4294967295.0;
 }
 
+// Picks one pixel of each `scale` x `scale` block, so that a low resolution pass covers the whole block in a few frames.
+fn stratified_uv(coords: vec2<i32>, size: vec2<i32>, scale: u32, seed: u32) -> vec2<f32> {
+    let index = (seed + u32(coords.x + coords.y)) % (scale * scale);
+    let stratum = vec2<f32>(f32(index % scale), f32(index / scale));
+    let offset = (stratum + 0.5) / f32(scale) - 0.5;
+    return (vec2<f32>(coords) + 0.5 + offset) / vec2<f32>(size);
+}
+
 fn clip_to_uv(clip: vec4<f32>) -> vec2<f32> {
     var uv = clip.xy / clip.w;
     uv = (uv + 1.0) * 0.5;
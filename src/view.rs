@@ -1,4 +1,4 @@
-use crate::{transform::GlobalTransformQueue, HikariSettings};
+use crate::{transform::GlobalTransformQueue, HikariSettings, HikariThinLens};
 use bevy::{
     ecs::query::QueryItem,
     prelude::*,
@@ -16,6 +16,7 @@ impl Plugin for ViewPlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<FrameCounter>()
             .add_plugin(ExtractComponentPlugin::<FrameCounter>::default())
+            .add_plugin(ExtractComponentPlugin::<HikariThinLens>::default())
             .add_plugin(ExtractComponentPlugin::<FrameUniform>::default())
             .add_plugin(UniformComponentPlugin::<FrameUniform>::default())
             .add_system_to_stage(CoreStage::PostUpdate, frame_counter_system);
@@ -114,12 +115,50 @@ pub struct FrameUniform {
     pub temporal_reuse: u32,
     pub emissive_spatial_reuse: u32,
     pub indirect_spatial_reuse: u32,
+    pub unbiased_restir: u32,
+    pub emissive_spatial_reuse_count: u32,
+    pub indirect_spatial_reuse_count: u32,
+    pub emissive_spatial_reuse_range: f32,
+    pub indirect_spatial_reuse_range: f32,
+    pub spatial_reuse_taps: u32,
     pub max_temporal_reuse_count: u32,
     pub max_spatial_reuse_count: u32,
     pub max_reservoir_lifetime: f32,
     pub solar_angle: f32,
     pub max_indirect_luminance: f32,
+    pub max_variance: f32,
+    /// Fraction of the view distance of a surface.
+    pub position_miss_threshold: f32,
     pub upscale_ratio: f32,
+    pub radiance_cache: u32,
+    pub radiance_cache_cell_size: f32,
+    pub radiance_cache_update_rate: f32,
+    pub radiance_cache_debug: u32,
+    pub russian_roulette: u32,
+    pub min_bounces: u32,
+    pub max_diffuse_bounces: u32,
+    pub max_specular_bounces: u32,
+    pub max_transmission_bounces: u32,
+    /// Count of aperture blades; less than 3 means a circular aperture.
+    pub lens_blade_count: u32,
+    /// Radius of the aperture; zero for a pinhole camera.
+    pub lens_radius: f32,
+    pub focus_distance: f32,
+    /// Fraction of the frame interval before the current frame that traced paths sample their time from.
+    /// Zero if motion blur is disabled.
+    pub shutter: f32,
+    pub backface_culling: u32,
+    /// Edge length of the pixel block covered by one indirect sample.
+    pub indirect_scale: u32,
+    pub adaptive_sampling: u32,
+    /// Count of samples the emissive pass takes over all pixels this frame.
+    pub adaptive_emissive_samples: f32,
+    /// Count of samples the indirect pass takes over all pixels this frame.
+    pub adaptive_indirect_samples: f32,
+    pub adaptive_max_samples: u32,
+    pub tonemapping: u32,
+    /// Linear scale of the render before tone mapping.
+    pub exposure: f32,
 }
 
 const KERNEL: Mat3 = Mat3 {
@@ -138,11 +177,24 @@ const HALTON: [Vec4; 8] = [
     Vec4::new(0.437500, 0.814815, 0.937500, 0.259259),
 ];
 
+impl ExtractComponent for HikariThinLens {
+    type Query = &'static Self;
+    type Filter = With<HikariSettings>;
+
+    fn extract_component(item: QueryItem<Self::Query>) -> Self {
+        *item
+    }
+}
+
 impl ExtractComponent for FrameUniform {
-    type Query = (&'static HikariSettings, &'static FrameCounter);
+    type Query = (
+        &'static HikariSettings,
+        &'static FrameCounter,
+        Option<&'static HikariThinLens>,
+    );
     type Filter = ();
 
-    fn extract_component((settings, counter): QueryItem<Self::Query>) -> Self {
+    fn extract_component((settings, counter, lens): QueryItem<Self::Query>) -> Self {
         let HikariSettings {
             direct_validate_interval,
             emissive_validate_interval,
@@ -152,24 +204,81 @@ impl ExtractComponent for FrameUniform {
             solar_angle,
             indirect_bounces,
             max_indirect_luminance,
+            max_variance,
+            position_miss_threshold,
             clear_color,
             temporal_reuse,
             emissive_spatial_reuse,
             indirect_spatial_reuse,
+            spatial_reuse,
+            unbiased_restir,
+            radiance_cache,
+            path_depth,
+            motion_blur,
+            backface_culling,
+            indirect_resolution,
+            adaptive_sampling,
+            tonemapping,
+            exposure,
             ..
         } = settings.clone();
 
         let number = counter.0 as u32;
         let direct_validate_interval = direct_validate_interval as u32;
         let emissive_validate_interval = emissive_validate_interval as u32;
-        let indirect_bounces = indirect_bounces as u32;
+        let indirect_bounces = indirect_bounces.min(u32::MAX as usize) as u32;
         let clear_color = clear_color.into();
         let max_temporal_reuse_count = max_temporal_reuse_count as u32;
         let max_spatial_reuse_count = max_spatial_reuse_count as u32;
         let temporal_reuse = temporal_reuse.into();
         let emissive_spatial_reuse = emissive_spatial_reuse.into();
         let indirect_spatial_reuse = indirect_spatial_reuse.into();
+        let unbiased_restir = unbiased_restir.into();
+        let backface_culling = backface_culling.into();
+        let indirect_scale = indirect_resolution.scale();
+        let adaptive_max_samples = adaptive_sampling.max_samples.min(u32::MAX as usize) as u32;
+
+        // Split the ray budget evenly, then convert rays into samples by the cost of a sample in each pass
+        let (adaptive_emissive_samples, adaptive_indirect_samples) = {
+            let rays = adaptive_sampling.rays_per_frame as f32;
+            match indirect_bounces {
+                0 => (rays, 0.0),
+                bounces => (0.5 * rays, 0.25 * rays / bounces as f32),
+            }
+        };
+        let adaptive_sampling = adaptive_sampling.enabled.into();
+        let tonemapping = tonemapping as u32;
+        let exposure = exposure.exp2();
+        let emissive_spatial_reuse_count = spatial_reuse.emissive_count as u32;
+        let indirect_spatial_reuse_count = spatial_reuse.indirect_count as u32;
+        let emissive_spatial_reuse_range = spatial_reuse.emissive_range;
+        let indirect_spatial_reuse_range = spatial_reuse.indirect_range;
+        let spatial_reuse_taps = spatial_reuse.taps as u32;
         let upscale_ratio = settings.upscale.ratio();
+        let radiance_cache_cell_size = radiance_cache.cell_size.max(0.001);
+        let radiance_cache_update_rate = radiance_cache.update_rate.clamp(0.0, 1.0);
+        let radiance_cache_debug = (radiance_cache.enabled && radiance_cache.debug).into();
+        let radiance_cache = radiance_cache.enabled.into();
+        let russian_roulette = path_depth.russian_roulette.into();
+        let min_bounces = path_depth.min_bounces.min(u32::MAX as usize) as u32;
+        let max_diffuse_bounces = path_depth.diffuse_bounces.min(u32::MAX as usize) as u32;
+        let max_specular_bounces = path_depth.specular_bounces.min(u32::MAX as usize) as u32;
+        let max_transmission_bounces =
+            path_depth.transmission_bounces.min(u32::MAX as usize) as u32;
+
+        let (lens_blade_count, lens_radius, focus_distance) = match lens {
+            Some(lens) if lens.enabled && lens.aperture > 0.0 => (
+                lens.blade_count,
+                0.5 * lens.aperture,
+                lens.focus_distance.max(0.001),
+            ),
+            _ => (0, 0.0, 1.0),
+        };
+
+        let shutter = match motion_blur.enabled {
+            true => motion_blur.shutter.clamp(0.0, 1.0),
+            false => 0.0,
+        };
 
         Self {
             kernel: KERNEL,
@@ -182,12 +291,41 @@ impl ExtractComponent for FrameUniform {
             temporal_reuse,
             emissive_spatial_reuse,
             indirect_spatial_reuse,
+            unbiased_restir,
+            emissive_spatial_reuse_count,
+            indirect_spatial_reuse_count,
+            emissive_spatial_reuse_range,
+            indirect_spatial_reuse_range,
+            spatial_reuse_taps,
             max_temporal_reuse_count,
             max_spatial_reuse_count,
             max_reservoir_lifetime,
             solar_angle,
             max_indirect_luminance,
+            max_variance,
+            position_miss_threshold,
             upscale_ratio,
+            radiance_cache,
+            radiance_cache_cell_size,
+            radiance_cache_update_rate,
+            radiance_cache_debug,
+            russian_roulette,
+            min_bounces,
+            max_diffuse_bounces,
+            max_specular_bounces,
+            max_transmission_bounces,
+            lens_blade_count,
+            lens_radius,
+            focus_distance,
+            shutter,
+            backface_culling,
+            indirect_scale,
+            adaptive_sampling,
+            adaptive_emissive_samples,
+            adaptive_indirect_samples,
+            adaptive_max_samples,
+            tonemapping,
+            exposure,
         }
     }
 }